@@ -3,7 +3,6 @@
 use anyhow::{anyhow, Result};
 use chrono::Utc;
 use rad_common::*;
-use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, CHACHA20_POLY1305};
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::TcpStream;
 use tokio::time::{sleep, timeout, Duration};
@@ -50,12 +49,9 @@ async fn main() -> Result<()> {
     // Maneuver the craft into the inner radiation belt
     let now = Utc::now();
     let request = ControlRequest::Maneuver {
-        burns: vec![Burn {
-            start: now.timestamp() as _,
-            length: 255,
-            thrust: 1.0,
-            vector: (-1.0, 0.0, 0.0),
-        }],
+        burns: vec![
+            Burn::new(now.timestamp() as _, 255, 1.0, (-1.0, 0.0, 0.0)).map_err(|e| anyhow!(e))?
+        ],
     };
     let _response = timeout(timeout_duration, send(&mut control, request)).await??;
 
@@ -104,17 +100,7 @@ async fn main() -> Result<()> {
 
 async fn authenticate(socket: &mut TcpStream) -> Result<()> {
     let timeout_duration = Duration::from_secs(5);
-    let auth_key = UnboundKey::new(&CHACHA20_POLY1305, &RAD_AUTH_KEY)
-        .map_err(|_| anyhow!("create auth key"))?;
-    let auth_key = LessSafeKey::new(auth_key);
-    let nonce = Nonce::assume_unique_for_key([0u8; 12]);
-    let mut token = TEST_TOKEN.as_bytes().to_vec();
-    auth_key.seal_in_place_append_tag(nonce, Aad::empty(), &mut token)?;
-    let nonce = Nonce::assume_unique_for_key([0u8; 12]);
-    let request = ControlRequest::Authenticate {
-        token,
-        nonce: nonce.as_ref().to_vec(),
-    };
+    let request = auth::authenticate(RAD_AUTH_KEY, TEST_TOKEN).map_err(|e| anyhow!(e))?;
     match timeout(timeout_duration, send(socket, request)).await?? {
         ControlResponse::Authenticate {
             authenticated,
@@ -125,6 +111,14 @@ async fn authenticate(socket: &mut TcpStream) -> Result<()> {
         }
         _ => panic!("expected authentication response"),
     }
+    let request = ControlRequest::Hello {
+        version: message::PROTOCOL_VERSION,
+        key_version: 0,
+    };
+    match timeout(timeout_duration, send(socket, request)).await?? {
+        ControlResponse::Hello { accepted, .. } => assert!(accepted),
+        _ => panic!("expected hello response"),
+    }
     Ok(())
 }
 
@@ -1,20 +1,33 @@
 //! Rad client.
 
 use anyhow::{anyhow, Context, Result};
-use chrono::{DateTime, Utc};
-use rad_common::{ControlRequest, ControlResponse, Event, ModuleStatus};
-use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, CHACHA20_POLY1305};
+use chrono::{DateTime, TimeZone, Utc};
+use rad_common::auth;
+use rad_common::frame::{read_framed, MAX_FRAME_SIZE};
+use rad_common::message::{self, PROTOCOL_VERSION};
+use rad_common::{
+    compute_radiation, Burn, ControlRequest, ControlResponse, Eclipse, Event, FieldRepairs,
+    ModuleStatus, SensorSample, Severity, MAX_MESSAGE_SIZE, MAX_MODULE_SIZE, SIGNATURE_SIZE,
+};
+use rand::Rng;
+use serde::Serialize;
 use std::collections::VecDeque;
+use std::fs::OpenOptions;
+use std::io::Write;
 use std::net::SocketAddr;
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
 use structopt::StructOpt;
+use termion::event::Key;
 use termion::event::Key::Char;
 use termion::input::TermRead;
 use termion::raw::IntoRawMode;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tokio::net::tcp::OwnedWriteHalf;
 use tokio::net::TcpStream;
-use tokio::time::{sleep, Duration};
+use tokio::sync::mpsc::{channel, unbounded_channel, Receiver, UnboundedReceiver, UnboundedSender};
+use tokio::time::{sleep, Duration, Instant};
 use tui::backend::{Backend, TermionBackend};
 use tui::layout::{Constraint, Direction, Layout};
 use tui::style::{Color, Modifier, Style};
@@ -25,327 +38,33 @@ use tui::widgets::{Axis, Block, Borders, Chart, Dataset, GraphType, Paragraph};
 use tui::{Frame, Terminal};
 
 static QUIT: AtomicBool = AtomicBool::new(false);
+static NEXT_REQUEST_ID: AtomicU64 = AtomicU64::new(1);
 
 const RAD_AUTH_KEY: &[u8] = include_bytes!("../../data/rad_auth_key");
 const MAX_RADIATION_POINTS: usize = 10;
-const RAD_PTS_LOW: &[(f64, f64)] = &[
-    (-12.0, -4.0),
-    (-12.0, -3.0),
-    (-12.0, -2.0),
-    (-12.0, -1.0),
-    (-12.0, 0.0),
-    (-12.0, 1.0),
-    (-12.0, 2.0),
-    (-12.0, 3.0),
-    (-12.0, 4.0),
-    (-11.0, -6.0),
-    (-11.0, -5.0),
-    (-11.0, -4.0),
-    (-11.0, -3.0),
-    (-11.0, 3.0),
-    (-11.0, 4.0),
-    (-11.0, 5.0),
-    (-11.0, 6.0),
-    (-10.0, -7.0),
-    (-10.0, -6.0),
-    (-10.0, 6.0),
-    (-10.0, 7.0),
-    (-9.0, -9.0),
-    (-9.0, -8.0),
-    (-9.0, -7.0),
-    (-9.0, -2.0),
-    (-9.0, -1.0),
-    (-9.0, 0.0),
-    (-9.0, 1.0),
-    (-9.0, 2.0),
-    (-9.0, 7.0),
-    (-9.0, 8.0),
-    (-9.0, 9.0),
-    (-8.0, -9.0),
-    (-8.0, -4.0),
-    (-8.0, -3.0),
-    (-8.0, -2.0),
-    (-8.0, -1.0),
-    (-8.0, 1.0),
-    (-8.0, 2.0),
-    (-8.0, 3.0),
-    (-8.0, 4.0),
-    (-8.0, 9.0),
-    (-7.0, -10.0),
-    (-7.0, -9.0),
-    (-7.0, -6.0),
-    (-7.0, -5.0),
-    (-7.0, -4.0),
-    (-7.0, 4.0),
-    (-7.0, 5.0),
-    (-7.0, 6.0),
-    (-7.0, 9.0),
-    (-7.0, 10.0),
-    (-6.0, -11.0),
-    (-6.0, -10.0),
-    (-6.0, -7.0),
-    (-6.0, -6.0),
-    (-6.0, 6.0),
-    (-6.0, 7.0),
-    (-6.0, 10.0),
-    (-6.0, 11.0),
-    (-5.0, -11.0),
-    (-5.0, -7.0),
-    (-5.0, 7.0),
-    (-5.0, 11.0),
-    (-4.0, -12.0),
-    (-4.0, -11.0),
-    (-4.0, -8.0),
-    (-4.0, -7.0),
-    (-4.0, 7.0),
-    (-4.0, 8.0),
-    (-4.0, 11.0),
-    (-4.0, 12.0),
-    (-3.0, -12.0),
-    (-3.0, -11.0),
-    (-3.0, -8.0),
-    (-3.0, 8.0),
-    (-3.0, 11.0),
-    (-3.0, 12.0),
-    (-2.0, -12.0),
-    (-2.0, -9.0),
-    (-2.0, -8.0),
-    (-2.0, 8.0),
-    (-2.0, 9.0),
-    (-2.0, 12.0),
-    (-1.0, -12.0),
-    (-1.0, -9.0),
-    (-1.0, -8.0),
-    (-1.0, 8.0),
-    (-1.0, 9.0),
-    (-1.0, 12.0),
-    (0.0, -12.0),
-    (0.0, -9.0),
-    (0.0, 9.0),
-    (0.0, 12.0),
-    (1.0, -12.0),
-    (1.0, -9.0),
-    (1.0, -8.0),
-    (1.0, 8.0),
-    (1.0, 9.0),
-    (1.0, 12.0),
-    (2.0, -12.0),
-    (2.0, -9.0),
-    (2.0, -8.0),
-    (2.0, 8.0),
-    (2.0, 9.0),
-    (2.0, 12.0),
-    (3.0, -12.0),
-    (3.0, -11.0),
-    (3.0, -8.0),
-    (3.0, 8.0),
-    (3.0, 11.0),
-    (3.0, 12.0),
-    (4.0, -12.0),
-    (4.0, -11.0),
-    (4.0, -8.0),
-    (4.0, -7.0),
-    (4.0, 7.0),
-    (4.0, 8.0),
-    (4.0, 11.0),
-    (4.0, 12.0),
-    (5.0, -11.0),
-    (5.0, -7.0),
-    (5.0, 7.0),
-    (5.0, 11.0),
-    (6.0, -11.0),
-    (6.0, -10.0),
-    (6.0, -7.0),
-    (6.0, -6.0),
-    (6.0, 6.0),
-    (6.0, 7.0),
-    (6.0, 10.0),
-    (6.0, 11.0),
-    (7.0, -10.0),
-    (7.0, -9.0),
-    (7.0, -6.0),
-    (7.0, -5.0),
-    (7.0, -4.0),
-    (7.0, 4.0),
-    (7.0, 5.0),
-    (7.0, 6.0),
-    (7.0, 9.0),
-    (7.0, 10.0),
-    (8.0, -9.0),
-    (8.0, -4.0),
-    (8.0, -3.0),
-    (8.0, -2.0),
-    (8.0, -1.0),
-    (8.0, 1.0),
-    (8.0, 2.0),
-    (8.0, 3.0),
-    (8.0, 4.0),
-    (8.0, 9.0),
-    (9.0, -9.0),
-    (9.0, -8.0),
-    (9.0, -7.0),
-    (9.0, -2.0),
-    (9.0, -1.0),
-    (9.0, 0.0),
-    (9.0, 1.0),
-    (9.0, 2.0),
-    (9.0, 7.0),
-    (9.0, 8.0),
-    (9.0, 9.0),
-    (10.0, -7.0),
-    (10.0, -6.0),
-    (10.0, 6.0),
-    (10.0, 7.0),
-    (11.0, -6.0),
-    (11.0, -5.0),
-    (11.0, -4.0),
-    (11.0, -3.0),
-    (11.0, 3.0),
-    (11.0, 4.0),
-    (11.0, 5.0),
-    (11.0, 6.0),
-    (12.0, -4.0),
-    (12.0, -3.0),
-    (12.0, -2.0),
-    (12.0, -1.0),
-    (12.0, 0.0),
-    (12.0, 1.0),
-    (12.0, 2.0),
-    (12.0, 3.0),
-    (12.0, 4.0),
-];
-const RAD_PTS_MED: &[(f64, f64)] = &[
-    (-11.0, -2.0),
-    (-11.0, -1.0),
-    (-11.0, 0.0),
-    (-11.0, 1.0),
-    (-11.0, 2.0),
-    (-10.0, -5.0),
-    (-10.0, -4.0),
-    (-10.0, -1.0),
-    (-10.0, 0.0),
-    (-10.0, 1.0),
-    (-10.0, 4.0),
-    (-10.0, 5.0),
-    (-9.0, -6.0),
-    (-9.0, -4.0),
-    (-9.0, -3.0),
-    (-9.0, 3.0),
-    (-9.0, 4.0),
-    (-9.0, 6.0),
-    (-8.0, -8.0),
-    (-8.0, -7.0),
-    (-8.0, -6.0),
-    (-8.0, -5.0),
-    (-8.0, 5.0),
-    (-8.0, 6.0),
-    (-8.0, 7.0),
-    (-8.0, 8.0),
-    (-7.0, -8.0),
-    (-7.0, -7.0),
-    (-7.0, 7.0),
-    (-7.0, 8.0),
-    (-6.0, -9.0),
-    (-6.0, -8.0),
-    (-6.0, 8.0),
-    (-6.0, 9.0),
-    (-5.0, -10.0),
-    (-5.0, -8.0),
-    (-5.0, 8.0),
-    (-5.0, 10.0),
-    (-4.0, -10.0),
-    (-4.0, -9.0),
-    (-4.0, 9.0),
-    (-4.0, 10.0),
-    (-3.0, -9.0),
-    (-3.0, 9.0),
-    (-2.0, -11.0),
-    (-2.0, 11.0),
-    (-1.0, -11.0),
-    (-1.0, -10.0),
-    (-1.0, 10.0),
-    (-1.0, 11.0),
-    (0.0, -11.0),
-    (0.0, -10.0),
-    (0.0, 10.0),
-    (0.0, 11.0),
-    (1.0, -11.0),
-    (1.0, -10.0),
-    (1.0, 10.0),
-    (1.0, 11.0),
-    (2.0, -11.0),
-    (2.0, 11.0),
-    (3.0, -9.0),
-    (3.0, 9.0),
-    (4.0, -10.0),
-    (4.0, -9.0),
-    (4.0, 9.0),
-    (4.0, 10.0),
-    (5.0, -10.0),
-    (5.0, -8.0),
-    (5.0, 8.0),
-    (5.0, 10.0),
-    (6.0, -9.0),
-    (6.0, -8.0),
-    (6.0, 8.0),
-    (6.0, 9.0),
-    (7.0, -8.0),
-    (7.0, -7.0),
-    (7.0, 7.0),
-    (7.0, 8.0),
-    (8.0, -8.0),
-    (8.0, -7.0),
-    (8.0, -6.0),
-    (8.0, -5.0),
-    (8.0, 5.0),
-    (8.0, 6.0),
-    (8.0, 7.0),
-    (8.0, 8.0),
-    (9.0, -6.0),
-    (9.0, -4.0),
-    (9.0, -3.0),
-    (9.0, 3.0),
-    (9.0, 4.0),
-    (9.0, 6.0),
-    (10.0, -5.0),
-    (10.0, -4.0),
-    (10.0, -1.0),
-    (10.0, 0.0),
-    (10.0, 1.0),
-    (10.0, 4.0),
-    (10.0, 5.0),
-    (11.0, -2.0),
-    (11.0, -1.0),
-    (11.0, 0.0),
-    (11.0, 1.0),
-    (11.0, 2.0),
-];
-const RAD_PTS_HIGH: &[(f64, f64)] = &[
-    (-10.0, -3.0),
-    (-10.0, -2.0),
-    (-10.0, 2.0),
-    (-10.0, 3.0),
-    (-9.0, -5.0),
-    (-9.0, 5.0),
-    (-5.0, -9.0),
-    (-5.0, 9.0),
-    (-3.0, -10.0),
-    (-3.0, 10.0),
-    (-2.0, -10.0),
-    (-2.0, 10.0),
-    (2.0, -10.0),
-    (2.0, 10.0),
-    (3.0, -10.0),
-    (3.0, 10.0),
-    (5.0, -9.0),
-    (5.0, 9.0),
-    (9.0, -5.0),
-    (9.0, 5.0),
-    (10.0, -3.0),
-    (10.0, -2.0),
-    (10.0, 2.0),
-    (10.0, 3.0),
-];
+const MAX_EVENTS: usize = 1024;
+const MAX_MODULES: usize = 256;
+const MAX_REPAIRED_FIELDS: usize = 4;
+/// Firmware events shown in the telemetry pane
+const MAX_RECENT_EVENTS: usize = 4;
+/// Log entries kept for scrollback
+const MAX_LOG_ENTRIES: usize = 100;
+/// Log entries scrolled per page key
+const LOG_PAGE: usize = 5;
+/// Shortest telemetry push interval a client may request (sec)
+const MIN_POLL_INTERVAL: u64 = 2;
+/// Longest telemetry push interval the satellite accepts (sec)
+const MAX_POLL_INTERVAL: u64 = 3600;
+/// Shortest interval between screen redraws (ms)
+const MIN_REDRAW_INTERVAL: u64 = 50;
+/// Delay before the first reconnection attempt
+const RECONNECT_INITIAL: Duration = Duration::from_secs(1);
+/// Longest delay between reconnection attempts
+const RECONNECT_MAX: Duration = Duration::from_secs(60);
+/// How long a connection must stay up before the reconnection delay starts over
+const RECONNECT_STABLE: Duration = Duration::from_secs(30);
+/// Earth equatorial radius (km), matching the simulation's geodetic model
+const EARTH_RADIUS: f64 = 6378.1363;
 
 /// Rad client.
 #[derive(Clone, StructOpt)]
@@ -360,6 +79,10 @@ struct Config {
 enum Command {
     /// Observe a satellite
     Observe(Observe),
+    /// Log satellite telemetry without a terminal interface
+    Log(Log),
+    /// Upload a firmware module
+    Update(Update),
 }
 
 /// Observe a satellite
@@ -372,52 +95,453 @@ struct Observe {
     /// Team token
     #[structopt(short, long)]
     team_token: String,
+    /// Log decoded protocol responses to a file, or to stderr if "-"
+    #[structopt(long)]
+    debug_protocol: Option<String>,
+    /// Seconds between telemetry pushes requested from the satellite
+    #[structopt(long, default_value = "10", parse(try_from_str = parse_poll_interval))]
+    poll_interval: u64,
+    /// Milliseconds between screen redraws
+    #[structopt(long, default_value = "1000", parse(try_from_str = parse_redraw_interval))]
+    redraw_interval: u64,
+}
+
+impl Observe {
+    /// Request telemetry pushes at the configured interval.
+    fn subscribe_request(&self) -> ControlRequest {
+        ControlRequest::Subscribe {
+            interval_secs: self.poll_interval,
+        }
+    }
+
+    /// Time between screen redraws.
+    fn redraw_delay(&self) -> Duration {
+        Duration::from_millis(self.redraw_interval)
+    }
+}
+
+/// Parse a telemetry poll interval, refusing values that would flood the proxy.
+fn parse_poll_interval(value: &str) -> Result<u64> {
+    let interval = value.parse()?;
+    if !(MIN_POLL_INTERVAL..=MAX_POLL_INTERVAL).contains(&interval) {
+        return Err(anyhow!(
+            "poll interval must be between {} and {} seconds",
+            MIN_POLL_INTERVAL,
+            MAX_POLL_INTERVAL
+        ));
+    }
+    Ok(interval)
+}
+
+/// Parse a redraw interval.
+fn parse_redraw_interval(value: &str) -> Result<u64> {
+    let interval = value.parse()?;
+    if interval < MIN_REDRAW_INTERVAL {
+        return Err(anyhow!(
+            "redraw interval must be at least {}ms",
+            MIN_REDRAW_INTERVAL
+        ));
+    }
+    Ok(interval)
+}
+
+/// Log satellite telemetry without a terminal interface
+#[derive(Clone, StructOpt)]
+#[structopt(rename_all = "snake_case")]
+struct Log {
+    /// Server address
+    #[structopt(short, long)]
+    ground_control_gateway: SocketAddr,
+    /// Team token
+    #[structopt(short, long)]
+    team_token: String,
+    /// Log decoded protocol responses to a file, or to stderr if "-"
+    #[structopt(long)]
+    debug_protocol: Option<String>,
+    /// Print telemetry as JSON lines
+    #[structopt(long)]
+    json: bool,
+}
+
+/// Upload a firmware module
+#[derive(Clone, StructOpt)]
+#[structopt(rename_all = "snake_case")]
+struct Update {
+    /// Server address
+    #[structopt(short, long)]
+    ground_control_gateway: SocketAddr,
+    /// Team token
+    #[structopt(short, long)]
+    team_token: String,
+    /// Log decoded protocol responses to a file, or to stderr if "-"
+    #[structopt(long)]
+    debug_protocol: Option<String>,
+    /// Module slot to replace
+    #[structopt(long)]
+    id: u8,
+    /// Module code path
+    #[structopt(long)]
+    module_path: PathBuf,
+    /// Module signature path
+    #[structopt(long)]
+    signature_path: PathBuf,
+    /// The module code is encoded
+    #[structopt(long)]
+    encoded: bool,
+}
+
+impl Update {
+    /// Read the module and signature into an update request.
+    fn request(&self) -> Result<ControlRequest> {
+        let read = |path: &Path, max: usize| -> Result<Vec<u8>> {
+            let data = std::fs::read(path).with_context(|| format!("read {}", path.display()))?;
+            if data.len() > max {
+                return Err(anyhow!(
+                    "{} is {} bytes, over the {} byte limit",
+                    path.display(),
+                    data.len(),
+                    max
+                ));
+            }
+            Ok(data)
+        };
+        let module = read(&self.module_path, MAX_MODULE_SIZE)?;
+        let signature = read(&self.signature_path, SIGNATURE_SIZE)?;
+        if signature.len() != SIGNATURE_SIZE {
+            return Err(anyhow!(
+                "{} is {} bytes, expected a {} byte signature",
+                self.signature_path.display(),
+                signature.len(),
+                SIGNATURE_SIZE
+            ));
+        }
+        Ok(ControlRequest::UpdateModule {
+            id: self.id,
+            module,
+            signature,
+            encoded: self.encoded,
+        })
+    }
+}
+
+/// Telemetry poll printed by the log command.
+#[derive(Debug, Serialize)]
+struct TelemetryRecord {
+    timestamp: u64,
+    position: (f64, f64, f64),
+    velocity: (f64, f64, f64),
+    fuel: f64,
+    radiation: f64,
+}
+
+impl std::fmt::Display for TelemetryRecord {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} position=({}, {}, {}) velocity=({}, {}, {}) fuel={} radiation={}",
+            self.timestamp,
+            self.position.0,
+            self.position.1,
+            self.position.2,
+            self.velocity.0,
+            self.velocity.1,
+            self.velocity.2,
+            self.fuel,
+            self.radiation
+        )
+    }
+}
+
+/// Radiation belt plot points, binned into display bands.
+struct RadiationBelt {
+    low: Vec<(f64, f64)>,
+    med: Vec<(f64, f64)>,
+    high: Vec<(f64, f64)>,
+}
+
+impl RadiationBelt {
+    /// Sample the radiation model across the equatorial plane of the plot grid (Mm).
+    fn sample() -> Self {
+        let mut belt = Self {
+            low: vec![],
+            med: vec![],
+            high: vec![],
+        };
+        for x in -30..30 {
+            for y in -30..30 {
+                let p = (x as f64, y as f64);
+                let altitude = (p.0 * 1000.0).hypot(p.1 * 1000.0) - EARTH_RADIUS;
+                let level = compute_radiation(0.0, altitude);
+                if level > 350.0 {
+                    belt.high.push(p);
+                } else if level > 100.0 {
+                    belt.med.push(p);
+                } else if level > 10.0 {
+                    belt.low.push(p);
+                }
+            }
+        }
+        belt
+    }
+}
+
+/// Sink for decoded protocol responses.
+#[derive(Clone)]
+struct ProtocolLog(Arc<Mutex<Box<dyn Write + Send>>>);
+
+impl ProtocolLog {
+    /// Open a protocol log at a path, or on stderr if the path is "-".
+    fn open(path: &str) -> Result<Self> {
+        let sink: Box<dyn Write + Send> = if path == "-" {
+            Box::new(std::io::stderr())
+        } else {
+            Box::new(
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .with_context(|| format!("open protocol log {}", path))?,
+            )
+        };
+        Ok(Self(Arc::new(Mutex::new(sink))))
+    }
+
+    /// Record a decoded response.
+    fn record(&self, response: &ControlResponse) -> Result<()> {
+        let mut sink = self.0.lock().map_err(|_| anyhow!("protocol log lock"))?;
+        writeln!(sink, "{} {:?}", Utc::now().to_rfc3339(), response)?;
+        sink.flush()?;
+        Ok(())
+    }
 }
 
 /// State.
+/// Orbit shape from the most recent Keplerian elements response.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct OrbitElements {
+    /// Semi-major axis (km)
+    sma: f64,
+    /// Eccentricity
+    ecc: f64,
+    /// Inclination (deg)
+    inc: f64,
+    /// Right ascension of the ascending node (deg)
+    raan: f64,
+    /// Argument of periapsis (deg)
+    aop: f64,
+    /// True anomaly (deg)
+    ta: f64,
+}
+
+/// What typed input is for.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum InputMode {
+    Maneuver,
+    Filter,
+}
+
 struct State {
     log: VecDeque<(DateTime<Utc>, String)>,
+    /// Entries the log view is scrolled up from the newest, or 0 to follow new entries
+    log_scroll_back: usize,
+    /// Only show log entries containing this text
+    log_filter: Option<String>,
+    input_mode: InputMode,
     position: (f64, f64, f64),
     velocity: (f64, f64, f64),
     fuel: f64,
     repairs: u64,
     restarts: u64,
-    radiation: VecDeque<f64>,
+    /// Corruptions the firmware could not repair
+    repairs_failed: u64,
+    radiation: VecDeque<(u64, f64)>,
+    eclipse: Eclipse,
     events: Vec<Event>,
     modules: Vec<ModuleStatus>,
+    field_repairs: Vec<FieldRepairs>,
+    time: u64,
+    input: Option<String>,
+    selected_module: usize,
+    belt: RadiationBelt,
+    elements: Option<OrbitElements>,
 }
 
 impl State {
     fn new() -> Self {
         Self {
             log: VecDeque::new(),
+            log_scroll_back: 0,
+            log_filter: None,
+            input_mode: InputMode::Maneuver,
             position: (0.0, 0.0, 0.0),
             velocity: (0.0, 0.0, 0.0),
             fuel: 0.0,
             repairs: 0,
             restarts: 0,
+            repairs_failed: 0,
             radiation: VecDeque::new(),
+            eclipse: Eclipse::Sunlit,
             events: vec![],
             modules: vec![],
+            field_repairs: vec![],
+            time: 0,
+            input: None,
+            selected_module: 0,
+            belt: RadiationBelt::sample(),
+            elements: None,
+        }
+    }
+
+    /// Apply a telemetry response.
+    fn record_telemetry(&mut self, response: ControlResponse) {
+        match response {
+            ControlResponse::Telemetry {
+                success: true,
+                t,
+                position,
+                velocity,
+                fuel,
+                radiation,
+                eclipse,
+                repairs,
+                restarts,
+                repairs_failed,
+                events,
+                modules,
+                field_repairs,
+            } => {
+                self.time = t;
+                self.position = position;
+                self.velocity = velocity;
+                self.fuel = fuel;
+                self.record_radiation(t, radiation);
+                self.eclipse = eclipse;
+                self.repairs = repairs;
+                self.restarts = restarts;
+                self.repairs_failed = repairs_failed;
+                self.events = events;
+                self.modules = modules;
+                self.field_repairs = field_repairs;
+            }
+            _ => self.log_message("telemetry request failed".to_owned()),
+        }
+    }
+
+    /// Apply a Keplerian elements response.
+    fn record_elements(&mut self, response: ControlResponse) {
+        match response {
+            ControlResponse::KeplerianElements {
+                success: true,
+                sma,
+                ecc,
+                inc,
+                raan,
+                aop,
+                ta,
+                ..
+            } => {
+                self.elements = Some(OrbitElements {
+                    sma,
+                    ecc,
+                    inc,
+                    raan,
+                    aop,
+                    ta,
+                })
+            }
+            _ => self.log_message("keplerian elements request failed".to_owned()),
         }
     }
 
     fn log_message(&mut self, message: String) {
+        // Keep a scrolled-back view on the same entries as new ones arrive
+        if self.log_scroll_back > 0 && self.log_visible(&message) {
+            self.log_scroll_back += 1;
+        }
         self.log.push_back((Utc::now(), message));
-        if self.log.len() > 100 {
+        if self.log.len() > MAX_LOG_ENTRIES {
             self.log.pop_front();
         }
     }
+
+    /// Check whether a log message passes the filter.
+    fn log_visible(&self, message: &str) -> bool {
+        match self.log_filter {
+            Some(ref filter) => message.contains(filter.as_str()),
+            None => true,
+        }
+    }
+
+    /// Return the log entries passing the filter, oldest first.
+    fn visible_log(&self) -> Vec<&(DateTime<Utc>, String)> {
+        self.log
+            .iter()
+            .filter(|(_, m)| self.log_visible(m))
+            .collect()
+    }
+
+    /// Scroll the log view up by `lines` entries, stopping at the oldest.
+    fn scroll_log_up(&mut self, lines: usize) {
+        let entries = self.visible_log().len();
+        self.log_scroll_back = (self.log_scroll_back + lines).min(entries);
+    }
+
+    /// Scroll the log view down by `lines` entries, following new entries at the bottom.
+    fn scroll_log_down(&mut self, lines: usize) {
+        self.log_scroll_back = self.log_scroll_back.saturating_sub(lines);
+    }
+
+    /// Return the timestamp of the most recent radiation sample.
+    fn last_sample(&self) -> Option<u64> {
+        self.radiation.back().map(|(t, _)| *t)
+    }
+
+    /// Record a radiation sample, keeping samples ordered and unique by timestamp.
+    fn record_radiation(&mut self, timestamp: u64, radiation: f64) {
+        let index = self
+            .radiation
+            .iter()
+            .position(|(t, _)| *t >= timestamp)
+            .unwrap_or_else(|| self.radiation.len());
+        if self.radiation.get(index).map(|(t, _)| *t) == Some(timestamp) {
+            return;
+        }
+        self.radiation.insert(index, (timestamp, radiation));
+        if self.radiation.len() > MAX_RADIATION_POINTS {
+            self.radiation.pop_front();
+        }
+    }
+
+    /// Backfill radiation samples missed while disconnected.
+    fn backfill_radiation(&mut self, samples: &[SensorSample]) {
+        for sample in samples {
+            self.record_radiation(sample.timestamp, sample.radiation);
+        }
+    }
 }
 
+/// Protocol mismatch between the client and server builds.
+#[derive(Debug)]
+struct ProtocolMismatch(String);
+
+impl std::fmt::Display for ProtocolMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "protocol mismatch: {}", self.0)
+    }
+}
+
+impl std::error::Error for ProtocolMismatch {}
+
 /// Main.
 #[tokio::main]
 async fn main() {
     let conf = Config::from_args();
     let result = match conf.command {
-        Command::Observe(ref command) => observe_satellite(command),
+        Command::Observe(ref command) => observe_satellite(command).await,
+        Command::Log(ref command) => log_telemetry(command).await,
+        Command::Update(ref command) => update_module(command).await,
     };
-    if let Err(e) = result.await {
+    if let Err(e) = result {
         eprintln!("{}", e);
     }
     std::process::exit(0);
@@ -435,40 +559,102 @@ async fn observe_satellite(command: &Observe) -> Result<()> {
         .map_err(|_| anyhow!("state lock"))?
         .log_message("initializing observation system".to_string());
 
+    let protocol_log = match command.debug_protocol {
+        Some(ref path) => Some(ProtocolLog::open(path)?),
+        None => None,
+    };
+
+    let (tx_commands, rx_commands) = unbounded_channel();
     tokio::spawn({
         let command = command.clone();
-        poll_satellite(command, state.clone())
+        poll_satellite(command, state.clone(), rx_commands, protocol_log)
     });
 
-    tokio::spawn(poll_stdin());
+    tokio::spawn(poll_stdin(state.clone(), tx_commands));
 
     terminal.clear()?;
     while !QUIT.load(Ordering::Relaxed) {
         if let Ok(state) = state.lock() {
             terminal.draw(|f| draw_ui(f, &state))?;
         }
-        sleep(Duration::from_secs(1)).await;
+        sleep(command.redraw_delay()).await;
     }
 
     terminal.clear()?;
     Ok(())
 }
 
+/// Reconnection delays that double up to a cap, jittered so clients do not retry in lockstep.
+#[derive(Default)]
+struct Backoff {
+    attempts: u32,
+}
+
+impl Backoff {
+    /// Compute the next delay, somewhere between half and all of the current exponential step.
+    fn next_delay<R: Rng>(&mut self, rng: &mut R) -> Duration {
+        let step = RECONNECT_INITIAL
+            .checked_mul(1 << self.attempts.min(16))
+            .map_or(RECONNECT_MAX, |x| x.min(RECONNECT_MAX));
+        self.attempts = self.attempts.saturating_add(1);
+        step / 2 + step.mul_f64(rng.gen_range(0.0..0.5))
+    }
+
+    /// Start over from the initial delay.
+    fn reset(&mut self) {
+        self.attempts = 0;
+    }
+}
+
 /// Poll the satellite status.
-async fn poll_satellite(command: Observe, state: Arc<Mutex<State>>) -> Result<()> {
+async fn poll_satellite(
+    command: Observe,
+    state: Arc<Mutex<State>>,
+    mut rx_commands: UnboundedReceiver<ControlRequest>,
+    protocol_log: Option<ProtocolLog>,
+) -> Result<()> {
+    let mut backoff = Backoff::default();
     loop {
-        if let Err(e) = connect_satellite(&command, state.clone()).await {
+        let connected = Instant::now();
+        if let Err(e) = connect_satellite(
+            &command,
+            state.clone(),
+            &mut rx_commands,
+            protocol_log.as_ref(),
+        )
+        .await
+        {
+            if e.is::<ProtocolMismatch>() {
+                state
+                    .lock()
+                    .map_err(|_| anyhow!("state lock"))?
+                    .log_message(format!("{}; refusing to proceed", e).to_uppercase());
+                return Err(e);
+            }
+            if connected.elapsed() >= RECONNECT_STABLE {
+                backoff.reset();
+            }
+            let delay = backoff.next_delay(&mut rand::thread_rng());
             state
                 .lock()
                 .map_err(|_| anyhow!("state lock"))?
-                .log_message(format!("ground channel error: {}", e));
-            sleep(Duration::from_secs(1)).await;
+                .log_message(format!(
+                    "ground channel error: {}; reconnecting in {}ms",
+                    e,
+                    delay.as_millis()
+                ));
+            sleep(delay).await;
         }
     }
 }
 
 /// Run a satellite ground control connection.
-async fn connect_satellite(command: &Observe, state: Arc<Mutex<State>>) -> Result<()> {
+async fn connect_satellite(
+    command: &Observe,
+    state: Arc<Mutex<State>>,
+    rx_commands: &mut UnboundedReceiver<ControlRequest>,
+    protocol_log: Option<&ProtocolLog>,
+) -> Result<()> {
     state
         .lock()
         .map_err(|_| anyhow!("state lock"))?
@@ -477,100 +663,424 @@ async fn connect_satellite(command: &Observe, state: Arc<Mutex<State>>) -> Resul
             command.ground_control_gateway,
         ));
 
-    let mut socket = TcpStream::connect(command.ground_control_gateway)
-        .await
-        .context("connect error")?;
-    let auth_key = UnboundKey::new(&CHACHA20_POLY1305, &RAD_AUTH_KEY)
-        .map_err(|_| anyhow!("create auth key"))?;
-    let auth_key = LessSafeKey::new(auth_key);
-    let nonce = Nonce::assume_unique_for_key([0u8; 12]);
-    let mut token = command.team_token.as_bytes().to_vec();
-    auth_key.seal_in_place_append_tag(nonce, Aad::empty(), &mut token)?;
-    let nonce = Nonce::assume_unique_for_key([0u8; 12]);
-    let request = ControlRequest::Authenticate {
-        token,
-        nonce: nonce.as_ref().to_vec(),
-    };
-    send_request(&mut socket, &request).await?;
+    let mut socket = open_channel(
+        command.ground_control_gateway,
+        &command.team_token,
+        protocol_log,
+    )
+    .await?;
+    let rtt = ping(&mut socket, protocol_log).await?;
+    state
+        .lock()
+        .map_err(|_| anyhow!("state lock"))?
+        .log_message(format!("round trip time: {}ms", rtt.as_millis()));
 
-    loop {
-        let response = send_request(&mut socket, &ControlRequest::PositionVelocity).await?;
+    // Backfill telemetry missed since the last connection
+    let since = state
+        .lock()
+        .map_err(|_| anyhow!("state lock"))?
+        .last_sample();
+    if let Some(since) = since {
+        let response = send_request(
+            &mut socket,
+            ControlRequest::SensorHistory { since },
+            protocol_log,
+        )
+        .await?;
         match response {
-            ControlResponse::PositionVelocity { success, p, v, .. } => {
+            ControlResponse::SensorHistory { success, samples } => {
                 let mut state = state.lock().map_err(|_| anyhow!("state lock"))?;
                 if success {
-                    state.position = p;
-                    state.velocity = v;
+                    state.backfill_radiation(&samples);
                 } else {
-                    state.log_message("position and velocity request failed".to_owned());
+                    state.log_message("sensor history request failed".to_owned());
                 }
             }
-            _ => return Err(anyhow!("expected position and velocity response")),
+            _ => return Err(anyhow!("expected sensor history response")),
         }
+    }
 
-        let response = send_request(&mut socket, &ControlRequest::Firmware).await?;
-        match response {
-            ControlResponse::Firmware {
-                success,
-                repairs,
-                restarts,
-                events,
-                modules,
-            } => {
-                let mut state = state.lock().map_err(|_| anyhow!("state lock"))?;
-                if success {
-                    state.repairs = repairs;
-                    state.restarts = restarts;
-                    state.events = events;
-                    state.modules = modules;
-                } else {
-                    state.log_message("status request failed".to_owned());
+    let subscribe = command.subscribe_request();
+    match send_request(&mut socket, subscribe, protocol_log).await? {
+        ControlResponse::Subscribe { success: true } => {}
+        _ => return Err(anyhow!("telemetry subscription failed")),
+    }
+
+    // Read on a separate task so a pushed frame is never torn by an operator command
+    let (mut reader, mut writer) = socket.into_split();
+    let (tx_responses, mut rx_responses) = channel(1);
+    let reader = tokio::spawn(async move {
+        let mut buffer = vec![];
+        loop {
+            let response = read_framed(&mut reader, &mut buffer, MAX_FRAME_SIZE)
+                .await
+                .context("read response")
+                .and_then(|()| message::decode(&buffer).context("decode response"));
+            let failed = response.is_err();
+            if tx_responses.send(response).await.is_err() || failed {
+                return;
+            }
+        }
+    });
+    let result = stream_telemetry(
+        &state,
+        &mut writer,
+        &mut rx_responses,
+        rx_commands,
+        protocol_log,
+    )
+    .await;
+    reader.abort();
+    result
+}
+
+/// Apply pushed telemetry and send operator commands as they arrive.
+async fn stream_telemetry(
+    state: &Mutex<State>,
+    writer: &mut OwnedWriteHalf,
+    rx_responses: &mut Receiver<Result<ControlResponse>>,
+    rx_commands: &mut UnboundedReceiver<ControlRequest>,
+    protocol_log: Option<&ProtocolLog>,
+) -> Result<()> {
+    loop {
+        tokio::select! {
+            response = rx_responses.recv() => {
+                let response = response.ok_or_else(|| anyhow!("read response"))??;
+                if let Some(protocol_log) = protocol_log {
+                    protocol_log.record(&response)?;
                 }
+                match response.untag().1 {
+                    response @ ControlResponse::Telemetry { .. } => {
+                        state.lock().map_err(|_| anyhow!("state lock"))?.record_telemetry(response);
+                        // Follow each push with the orbit shape for the elements pane
+                        let request_id = NEXT_REQUEST_ID.fetch_add(1, Ordering::Relaxed);
+                        let request = ControlRequest::KeplerianElements.tag(Some(request_id));
+                        write_request(writer, &request).await?;
+                    }
+                    response @ ControlResponse::KeplerianElements { .. } => {
+                        state.lock().map_err(|_| anyhow!("state lock"))?.record_elements(response)
+                    }
+                    response => state
+                        .lock()
+                        .map_err(|_| anyhow!("state lock"))?
+                        .log_message(command_result(&response)),
+                }
+            }
+            Some(request) = rx_commands.recv() => {
+                let request_id = NEXT_REQUEST_ID.fetch_add(1, Ordering::Relaxed);
+                write_request(writer, &request.tag(Some(request_id))).await?;
+            }
+        }
+    }
+}
+
+/// Measure the round trip time to the satellite executive.
+async fn ping(socket: &mut TcpStream, protocol_log: Option<&ProtocolLog>) -> Result<Duration> {
+    let nonce = rand::random();
+    let start = Instant::now();
+    let response = send_request(socket, ControlRequest::Ping { nonce }, protocol_log).await?;
+    match response {
+        ControlResponse::Pong { nonce: pong, .. } if pong == nonce => Ok(start.elapsed()),
+        ControlResponse::Pong { .. } => Err(anyhow!("ping nonce mismatch")),
+        _ => Err(anyhow!("expected pong response")),
+    }
+}
+
+/// Describe the response to an operator command.
+fn command_result(response: &ControlResponse) -> String {
+    match response {
+        ControlResponse::Maneuver {
+            success,
+            last_burn_fuel: Some(fuel),
+        } => format!(
+            "maneuver: success={} (last burn used {:.3} kg)",
+            success, fuel
+        ),
+        ControlResponse::Maneuver { success, .. } => format!("maneuver: success={}", success),
+        ControlResponse::EnableModule {
+            success,
+            error: Some(error),
+        } => format!("enable module: success={} ({})", success, error),
+        ControlResponse::EnableModule { success, .. } => {
+            format!("enable module: success={}", success)
+        }
+        response => format!("unexpected response {}", response),
+    }
+}
+
+/// Parse a burn entered as "start_offset length thrust x y z", relative to a satellite time.
+fn parse_burn(input: &str, now: u64) -> Result<Burn> {
+    let fields: Vec<_> = input.split_whitespace().collect();
+    if fields.len() != 6 {
+        return Err(anyhow!("expected start offset, length, thrust, and vector"));
+    }
+    let offset: u64 = fields[0].parse().context("start offset")?;
+    let length: u8 = fields[1].parse().context("length")?;
+    let thrust: f64 = fields[2].parse().context("thrust")?;
+    let mut vector = [0.0; 3];
+    for (x, field) in vector.iter_mut().zip(&fields[3..]) {
+        *x = field.parse().context("vector")?;
+    }
+    Burn::new(
+        now + offset,
+        length,
+        thrust,
+        (vector[0], vector[1], vector[2]),
+    )
+    .map_err(|e| anyhow!(e))
+}
+
+/// Open an authenticated ground control channel, checking the server protocol.
+async fn open_channel(
+    gateway: SocketAddr,
+    team_token: &str,
+    protocol_log: Option<&ProtocolLog>,
+) -> Result<TcpStream> {
+    let mut socket = TcpStream::connect(gateway).await.context("connect error")?;
+    let request = auth::authenticate(RAD_AUTH_KEY, team_token).map_err(|e| anyhow!(e))?;
+
+    // The hello goes first so the proxy knows which auth key version sealed the token
+    let hello = ControlRequest::Hello {
+        version: PROTOCOL_VERSION,
+        key_version: 0,
+    };
+    let responses = send_requests(&mut socket, vec![hello, request], protocol_log).await?;
+    match &responses[0] {
+        ControlResponse::Hello { accepted: true, .. } => {}
+        ControlResponse::Hello { version, .. } => {
+            return Err(anyhow!(
+                "server protocol version {} does not match client version {}",
+                version,
+                PROTOCOL_VERSION
+            ))
+        }
+        response => return Err(anyhow!("expected hello response, received {}", response)),
+    }
+
+    // Make sure the server speaks the same protocol before trusting its telemetry
+    let noop = send_request(&mut socket, ControlRequest::NoOp, protocol_log).await?;
+    let firmware = send_request(&mut socket, ControlRequest::Firmware, protocol_log).await?;
+    check_handshake(&noop, &firmware)?;
+    Ok(socket)
+}
+
+/// Log satellite telemetry to stdout.
+async fn log_telemetry(command: &Log) -> Result<()> {
+    let protocol_log = match command.debug_protocol {
+        Some(ref path) => Some(ProtocolLog::open(path)?),
+        None => None,
+    };
+
+    loop {
+        if let Err(e) = log_connection(command, protocol_log.as_ref()).await {
+            if e.is::<ProtocolMismatch>() {
+                return Err(e);
+            }
+            eprintln!("ground channel error: {}", e);
+            sleep(Duration::from_secs(1)).await;
+        }
+    }
+}
+
+/// Upload a module and print the satellite's verdict.
+async fn update_module(command: &Update) -> Result<()> {
+    let request = command.request()?;
+    let protocol_log = match command.debug_protocol {
+        Some(ref path) => Some(ProtocolLog::open(path)?),
+        None => None,
+    };
+
+    let mut socket = open_channel(
+        command.ground_control_gateway,
+        &command.team_token,
+        protocol_log.as_ref(),
+    )
+    .await?;
+    match send_request(&mut socket, request, protocol_log.as_ref()).await? {
+        ControlResponse::UpdateModule {
+            success,
+            checksum,
+            verified,
+            enabled,
+            error,
+        } => {
+            println!(
+                "module {}: success={} checksum={:016x} verified={} enabled={}",
+                command.id, success, checksum, verified, enabled
+            );
+            if let Some(error) = error {
+                println!("module {}: {}", command.id, error);
             }
-            _ => return Err(anyhow!("expected status response")),
+            Ok(())
         }
+        response => Err(anyhow!(
+            "expected update module response, received {}",
+            response
+        )),
+    }
+}
+
+/// Run a ground control connection, printing each telemetry poll.
+async fn log_connection(command: &Log, protocol_log: Option<&ProtocolLog>) -> Result<()> {
+    let mut socket = open_channel(
+        command.ground_control_gateway,
+        &command.team_token,
+        protocol_log,
+    )
+    .await?;
 
-        let response = send_request(&mut socket, &ControlRequest::Sensors).await?;
+    loop {
+        let response = send_request(&mut socket, ControlRequest::Telemetry, protocol_log).await?;
         match response {
-            ControlResponse::Sensors {
-                success,
+            ControlResponse::Telemetry {
+                success: true,
+                t,
+                position,
+                velocity,
                 fuel,
                 radiation,
+                ..
             } => {
-                let mut state = state.lock().map_err(|_| anyhow!("state lock"))?;
-                if success {
-                    state.fuel = fuel;
-                    state.radiation.push_back(radiation);
-                    if state.radiation.len() > MAX_RADIATION_POINTS {
-                        state.radiation.pop_front();
-                    }
+                let record = TelemetryRecord {
+                    timestamp: t,
+                    position,
+                    velocity,
+                    fuel,
+                    radiation,
+                };
+                if command.json {
+                    println!("{}", serde_json::to_string(&record)?);
                 } else {
-                    state.log_message("radiation level request failed".to_owned());
+                    println!("{}", record);
                 }
             }
-            _ => return Err(anyhow!("expected status response")),
+            ControlResponse::Telemetry { success: false, .. } => {
+                eprintln!("telemetry request failed");
+            }
+            _ => return Err(anyhow!("expected telemetry response")),
         }
 
         sleep(Duration::from_secs(10)).await;
     }
 }
 
+/// Check that handshake responses are structurally what this build expects.
+fn check_handshake(
+    noop: &ControlResponse,
+    firmware: &ControlResponse,
+) -> std::result::Result<(), ProtocolMismatch> {
+    if *noop != ControlResponse::NoOp {
+        return Err(ProtocolMismatch(format!(
+            "expected NoOp response, received {}",
+            noop
+        )));
+    }
+    match firmware {
+        ControlResponse::Firmware {
+            success: false,
+            events,
+            modules,
+            ..
+        } => {
+            if !events.is_empty() || !modules.is_empty() {
+                return Err(ProtocolMismatch(
+                    "failed firmware response carries data".to_string(),
+                ));
+            }
+        }
+        ControlResponse::Firmware {
+            success: true,
+            events,
+            modules,
+            ..
+        } => {
+            if events.len() > MAX_EVENTS || modules.len() > MAX_MODULES {
+                return Err(ProtocolMismatch(format!(
+                    "implausible firmware response with {} events and {} modules",
+                    events.len(),
+                    modules.len()
+                )));
+            }
+            if let Some(e) = events.iter().find(|e| e.message.len() != MAX_MESSAGE_SIZE) {
+                return Err(ProtocolMismatch(format!(
+                    "event message size {} != {}",
+                    e.message.len(),
+                    MAX_MESSAGE_SIZE
+                )));
+            }
+        }
+        response => {
+            return Err(ProtocolMismatch(format!(
+                "expected Firmware response, received {}",
+                response
+            )))
+        }
+    }
+    Ok(())
+}
+
 /// Send a control request.
-async fn send_request(socket: &mut TcpStream, request: &ControlRequest) -> Result<ControlResponse> {
-    let buffer = bincode::serialize(&request).context("encode request")?;
-    socket
+async fn send_request(
+    socket: &mut TcpStream,
+    request: ControlRequest,
+    protocol_log: Option<&ProtocolLog>,
+) -> Result<ControlResponse> {
+    send_requests(socket, vec![request], protocol_log)
+        .await?
+        .pop()
+        .ok_or_else(|| anyhow!("missing response"))
+}
+
+/// Pipeline control requests, returning their responses in request order.
+async fn send_requests(
+    socket: &mut TcpStream,
+    requests: Vec<ControlRequest>,
+    protocol_log: Option<&ProtocolLog>,
+) -> Result<Vec<ControlResponse>> {
+    let mut request_ids = Vec::with_capacity(requests.len());
+    for request in requests {
+        let request_id = NEXT_REQUEST_ID.fetch_add(1, Ordering::Relaxed);
+        write_request(socket, &request.tag(Some(request_id))).await?;
+        request_ids.push(request_id);
+    }
+
+    let mut responses: Vec<Option<ControlResponse>> = request_ids.iter().map(|_| None).collect();
+    let mut buffer = vec![];
+    for _ in 0..request_ids.len() {
+        read_framed(socket, &mut buffer, MAX_FRAME_SIZE)
+            .await
+            .context("read response")?;
+        let response: ControlResponse = message::decode(&buffer).context("decode response")?;
+        if let Some(protocol_log) = protocol_log {
+            protocol_log.record(&response)?;
+        }
+        let (id, response) = response.untag();
+        match id.and_then(|id| request_ids.iter().position(|x| *x == id)) {
+            Some(i) if responses[i].is_none() => responses[i] = Some(response),
+            _ => {
+                return Err(anyhow!(
+                    "response carries unexpected ID {:?}, expected one of {:?}",
+                    id,
+                    request_ids
+                ))
+            }
+        }
+    }
+    Ok(responses.into_iter().flatten().collect())
+}
+
+/// Write a control request.
+async fn write_request<W>(writer: &mut W, request: &ControlRequest) -> Result<()>
+where
+    W: AsyncWrite + Unpin,
+{
+    let buffer = message::encode(request).context("encode request")?;
+    writer
         .write_u32(buffer.len() as _)
         .await
         .context("write request length")?;
-    socket.write_all(&buffer).await.context("write request")?;
-    let size = socket.read_u32().await.context("read response length")?;
-    let mut buffer = vec![0u8; size as _];
-    socket
-        .read_exact(&mut buffer)
-        .await
-        .context("read response")?;
-    let response: ControlResponse = bincode::deserialize(&buffer).context("decode response")?;
-    Ok(response)
+    writer.write_all(&buffer).await.context("write request")?;
+    Ok(())
 }
 
 /// Draw the UI.
@@ -591,7 +1101,14 @@ where
     let info_panes = Layout::default()
         .direction(Direction::Vertical)
         .margin(0)
-        .constraints([Constraint::Percentage(60), Constraint::Percentage(40)].as_ref())
+        .constraints(
+            [
+                Constraint::Percentage(45),
+                Constraint::Length(8),
+                Constraint::Min(0),
+            ]
+            .as_ref(),
+        )
         .split(top_panes[1]);
 
     let plot_block = Block::default().title("PLOT").borders(Borders::ALL);
@@ -603,15 +1120,15 @@ where
         .paint(|c| {
             // Draw radiation belt
             c.draw(&Points {
-                coords: RAD_PTS_LOW,
+                coords: &state.belt.low,
                 color: Color::Gray,
             });
             c.draw(&Points {
-                coords: RAD_PTS_MED,
+                coords: &state.belt.med,
                 color: Color::LightYellow,
             });
             c.draw(&Points {
-                coords: RAD_PTS_HIGH,
+                coords: &state.belt.high,
                 color: Color::LightRed,
             });
 
@@ -675,37 +1192,122 @@ where
             Style::default().add_modifier(Modifier::BOLD),
         )),
         Spans::from(Span::raw(format!("  {}", state.repairs))),
+        Spans::from(Span::styled(
+            "Unrepairable Corruptions",
+            Style::default().add_modifier(Modifier::BOLD),
+        )),
+        Spans::from(Span::raw(format!("  {}", state.repairs_failed))),
         Spans::from(Span::styled(
             "Radiation Level",
             Style::default().add_modifier(Modifier::BOLD),
         )),
         Spans::from(Span::raw(format!(
             "  {}",
-            if let Some(r) = state.radiation.back() {
+            if let Some((_, r)) = state.radiation.back() {
                 *r
             } else {
                 0.0
             }
         ))),
+        Spans::from(Span::styled(
+            "Eclipse",
+            Style::default().add_modifier(Modifier::BOLD),
+        )),
+        Spans::from(Span::raw(format!("  {}", state.eclipse))),
         Spans::from(Span::styled(
             "Modules",
             Style::default().add_modifier(Modifier::BOLD),
         )),
     ];
     for (i, m) in state.modules.iter().enumerate() {
-        info_text.push(Spans::from(Span::raw(format!(
-            "  {:02}: en={} vf={} chk={:016x}",
-            i, m.enabled, m.verified, m.checksum
-        ))));
+        let updated = if m.updated_ts == 0 {
+            "never".to_string()
+        } else {
+            format_timestamp(m.updated_ts)
+        };
+        let style = if i == state.selected_module {
+            Style::default().add_modifier(Modifier::REVERSED)
+        } else {
+            Style::default()
+        };
+        info_text.push(Spans::from(Span::styled(
+            format!(
+                "  {:02}: en={} vf={} chk={:016x} len={} upd={}",
+                i, m.enabled, m.verified, m.checksum, m.code_len, updated
+            ),
+            style,
+        )));
+    }
+    if !state.field_repairs.is_empty() {
+        info_text.push(Spans::from(Span::styled(
+            "Repairs by Field",
+            Style::default().add_modifier(Modifier::BOLD),
+        )));
+        for f in most_repaired(&state.field_repairs) {
+            info_text.push(Spans::from(Span::raw(format!(
+                "  {}: {} (last {})",
+                f.field,
+                f.repairs,
+                format_timestamp(f.last_repaired)
+            ))));
+        }
+    }
+    let recent = recent_events(&state.events);
+    if !recent.is_empty() {
+        info_text.push(Spans::from(Span::styled(
+            "Recent Events",
+            Style::default().add_modifier(Modifier::BOLD),
+        )));
+        for e in recent {
+            let end = e
+                .message
+                .iter()
+                .position(|&x| x == 0)
+                .unwrap_or(e.message.len());
+            info_text.push(Spans::from(Span::styled(
+                format!(
+                    "  {} {:<5} {}",
+                    format_timestamp(e.timestamp),
+                    e.severity,
+                    String::from_utf8_lossy(&e.message[..end])
+                ),
+                severity_style(e.severity),
+            )));
+        }
     }
     let info = Paragraph::new(info_text).block(info_block);
 
+    let orbit_block = Block::default().title("ORBIT").borders(Borders::ALL);
+    let orbit_text: Vec<_> = match state.elements {
+        Some(x) => vec![
+            ("Semi-major Axis", format!("{:.3} km", x.sma)),
+            ("Eccentricity", format!("{:.6}", x.ecc)),
+            ("Inclination", format!("{:.4}°", x.inc)),
+            ("RAAN", format!("{:.4}°", x.raan)),
+            ("Arg. of Periapsis", format!("{:.4}°", x.aop)),
+            ("True Anomaly", format!("{:.4}°", x.ta)),
+        ],
+        None => vec![],
+    }
+    .into_iter()
+    .map(|(label, value)| {
+        Spans::from(vec![
+            Span::styled(
+                format!("{:<18}", label),
+                Style::default().add_modifier(Modifier::BOLD),
+            ),
+            Span::raw(value),
+        ])
+    })
+    .collect();
+    let orbit = Paragraph::new(orbit_text).block(orbit_block);
+
     let rad_block = Block::default().title("RADIATION").borders(Borders::ALL);
     let rad_levels: Vec<_> = state
         .radiation
         .iter()
         .enumerate()
-        .map(|(x, y)| (x as f64, *y))
+        .map(|(x, (_, y))| (x as f64, *y))
         .collect();
     let rad_data = vec![Dataset::default()
         .name("radiation")
@@ -733,9 +1335,25 @@ where
                 .style(Style::default().add_modifier(Modifier::DIM)),
         );
 
-    let log_block = Block::default().title("LOG").borders(Borders::ALL);
-    let log_text: Vec<_> = state
-        .log
+    let log_title = match (state.input_mode, &state.input) {
+        (InputMode::Maneuver, Some(input)) => {
+            format!("MANEUVER (start_offset length thrust x y z): {}_", input)
+        }
+        (InputMode::Filter, Some(input)) => format!("FILTER: {}_", input),
+        (_, None) => {
+            let mut title = "LOG".to_string();
+            if let Some(ref filter) = state.log_filter {
+                title += &format!(" [filter: {}]", filter);
+            }
+            if state.log_scroll_back > 0 {
+                title += &format!(" [-{}]", state.log_scroll_back);
+            }
+            title
+        }
+    };
+    let log_block = Block::default().title(log_title).borders(Borders::ALL);
+    let visible_log = state.visible_log();
+    let log_text: Vec<_> = visible_log
         .iter()
         .map(|(t, m)| {
             Spans::from(vec![
@@ -747,27 +1365,624 @@ where
             ])
         })
         .collect();
-    let mut log_scroll = (0u16, 0u16);
-    let log_height = vertical_panes[1].height - 2;
-    let num_log_entries = state.log.len() as u16;
-    if log_height < num_log_entries {
-        log_scroll = (num_log_entries - log_height, 0);
-    }
-    let log = Paragraph::new(log_text).block(log_block).scroll(log_scroll);
+    let log_height = vertical_panes[1].height.saturating_sub(2) as usize;
+    let log_top = log_scroll_offset(visible_log.len(), log_height, state.log_scroll_back);
+    let log = Paragraph::new(log_text)
+        .block(log_block)
+        .scroll((log_top as u16, 0));
 
     f.render_widget(plot, top_panes[0]);
     f.render_widget(info, info_panes[0]);
-    f.render_widget(rad_graph, info_panes[1]);
+    f.render_widget(orbit, info_panes[1]);
+    f.render_widget(rad_graph, info_panes[2]);
     f.render_widget(log, vertical_panes[1]);
 }
 
+/// Return the first log entry shown in a view of `height` lines scrolled `back` entries up from
+/// the newest of `entries`.
+fn log_scroll_offset(entries: usize, height: usize, back: usize) -> usize {
+    entries.saturating_sub(height).saturating_sub(back)
+}
+
+/// Return the fields taking the most repairs, most damaged first.
+fn most_repaired(field_repairs: &[FieldRepairs]) -> Vec<&FieldRepairs> {
+    let mut fields: Vec<_> = field_repairs.iter().collect();
+    fields.sort_by(|a, b| {
+        b.repairs
+            .cmp(&a.repairs)
+            .then(b.last_repaired.cmp(&a.last_repaired))
+    });
+    fields.truncate(MAX_REPAIRED_FIELDS);
+    fields
+}
+
+/// Return the most recent logged firmware events, newest first.
+///
+/// The firmware reports events in the order they were logged, which is kept even if the
+/// firmware clock went backwards in between.
+fn recent_events(events: &[Event]) -> Vec<&Event> {
+    events
+        .iter()
+        .rev()
+        .filter(|e| e.timestamp != 0)
+        .take(MAX_RECENT_EVENTS)
+        .collect()
+}
+
+/// Style an event by its severity.
+fn severity_style(severity: Severity) -> Style {
+    match severity {
+        Severity::Info => Style::default(),
+        Severity::Warn => Style::default().fg(Color::Yellow),
+        Severity::Error => Style::default().fg(Color::Red),
+    }
+}
+
+/// Format a Unix timestamp as a UTC time of day.
+fn format_timestamp(timestamp: u64) -> String {
+    Utc.timestamp_opt(timestamp as i64, 0)
+        .single()
+        .map(|t| t.format("%H:%M:%S").to_string())
+        .unwrap_or_else(|| timestamp.to_string())
+}
+
 /// Poll stdin.
-async fn poll_stdin() {
-    for e in std::io::stdin().keys() {
-        if let Ok(Char(e)) = e {
-            if e == 'q' {
-                QUIT.store(true, Ordering::Relaxed);
+async fn poll_stdin(state: Arc<Mutex<State>>, tx_commands: UnboundedSender<ControlRequest>) {
+    for key in std::io::stdin().keys().flatten() {
+        let request = match state.lock() {
+            Ok(mut state) => handle_key(&mut state, key),
+            Err(_) => None,
+        };
+        if let Some(request) = request {
+            let _ = tx_commands.send(request);
+        }
+    }
+}
+
+/// Handle a key press, returning a request to send, if any.
+fn handle_key(state: &mut State, key: Key) -> Option<ControlRequest> {
+    if let Some(mut input) = state.input.take() {
+        match key {
+            Char('\n') if state.input_mode == InputMode::Filter => {
+                state.log_filter = Some(input).filter(|x| !x.is_empty());
+                state.log_scroll_back = 0;
+            }
+            Char('\n') => match parse_burn(&input, state.time) {
+                Ok(burn) => {
+                    state.log_message(format!("scheduling maneuver: {}", burn));
+                    return Some(ControlRequest::Maneuver { burns: vec![burn] });
+                }
+                Err(e) => state.log_message(format!("invalid maneuver: {:#}", e)),
+            },
+            Key::Esc => {}
+            Key::Backspace => {
+                input.pop();
+                state.input = Some(input);
+            }
+            Char(c) => {
+                input.push(c);
+                state.input = Some(input);
             }
+            _ => state.input = Some(input),
         }
+        return None;
+    }
+
+    match key {
+        Char('q') => QUIT.store(true, Ordering::Relaxed),
+        Char('m') => {
+            state.input_mode = InputMode::Maneuver;
+            state.input = Some(String::new());
+        }
+        Char('f') if state.log_filter.is_some() => {
+            state.log_filter = None;
+            state.log_scroll_back = 0;
+        }
+        Char('f') => {
+            state.input_mode = InputMode::Filter;
+            state.input = Some(String::new());
+        }
+        Key::PageUp => state.scroll_log_up(LOG_PAGE),
+        Key::PageDown => state.scroll_log_down(LOG_PAGE),
+        Key::End => state.log_scroll_back = 0,
+        Key::Up => state.selected_module = state.selected_module.saturating_sub(1),
+        Key::Down => {
+            if state.selected_module + 1 < state.modules.len() {
+                state.selected_module += 1;
+            }
+        }
+        Char(c @ 'e') | Char(c @ 'd') if state.selected_module < state.modules.len() => {
+            let enable = c == 'e';
+            state.log_message(format!(
+                "{} module {}",
+                if enable { "enabling" } else { "disabling" },
+                state.selected_module
+            ));
+            return Some(ControlRequest::EnableModule {
+                id: state.selected_module as u8,
+                enable,
+            });
+        }
+        _ => {}
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncReadExt;
+
+    #[test]
+    fn test_observe_intervals() {
+        let args = [
+            "rad_client",
+            "observe",
+            "-g",
+            "127.0.0.1:1337",
+            "-t",
+            "token",
+        ];
+        let parse = |extra: &[&str]| {
+            let args = args.iter().chain(extra);
+            match Config::from_iter_safe(args).map(|x| x.command) {
+                Ok(Command::Observe(x)) => Ok(x),
+                Ok(_) => panic!("expected observe command"),
+                Err(e) => Err(e),
+            }
+        };
+
+        let observe = parse(&[]).expect("defaults");
+        assert_eq!(observe.redraw_delay(), Duration::from_secs(1));
+
+        let observe = parse(&["--poll_interval", "30", "--redraw_interval", "250"]).expect("parse");
+        assert!(matches!(
+            observe.subscribe_request(),
+            ControlRequest::Subscribe { interval_secs: 30 }
+        ));
+        assert_eq!(observe.redraw_delay(), Duration::from_millis(250));
+
+        assert!(parse(&["--poll_interval", "0"]).is_err());
+        assert!(parse(&["--poll_interval", "86400"]).is_err());
+        assert!(parse(&["--redraw_interval", "1"]).is_err());
+    }
+
+    #[test]
+    fn test_reconnect_backoff() {
+        use rand::rngs::StdRng;
+        use rand::SeedableRng;
+
+        let mut rng = StdRng::seed_from_u64(7);
+        let mut backoff = Backoff::default();
+        let delays: Vec<_> = (0..10).map(|_| backoff.next_delay(&mut rng)).collect();
+        for (i, delay) in delays.iter().enumerate() {
+            let step = (RECONNECT_INITIAL * (1 << i)).min(RECONNECT_MAX);
+            assert!(
+                *delay >= step / 2 && *delay <= step,
+                "delay {}: {:?}",
+                i,
+                delay
+            );
+        }
+        assert!(delays[4] > delays[0]);
+        assert!(delays[9] <= RECONNECT_MAX);
+
+        // Clients at the same attempt do not all wait the same time
+        let jittered: Vec<_> = (0..8)
+            .map(|_| Backoff::default().next_delay(&mut rng))
+            .collect();
+        assert!(jittered.iter().any(|x| *x != jittered[0]));
+
+        backoff.reset();
+        assert!(backoff.next_delay(&mut rng) <= RECONNECT_INITIAL);
+    }
+
+    #[test]
+    fn test_parse_burn() {
+        let burn = parse_burn("60 10 0.5 1 0 -1", 1_620_000_000).expect("burn");
+        assert_eq!(
+            burn,
+            Burn {
+                start: 1_620_000_060,
+                length: 10,
+                thrust: 0.5,
+                vector: (1.0, 0.0, -1.0),
+            }
+        );
+        assert!(parse_burn("60 10 1.5 1 0 0", 0).is_err());
+        assert!(parse_burn("60 10 -0.1 1 0 0", 0).is_err());
+        assert!(parse_burn("60 300 0.5 1 0 0", 0).is_err());
+        assert!(parse_burn("60 10 0.5 1 0", 0).is_err());
+        assert!(parse_burn("60 10 0.5 NaN 0 0", 0).is_err());
+        assert!(parse_burn("soon 10 0.5 1 0 0", 0).is_err());
+    }
+
+    #[test]
+    fn test_maneuver_entry() {
+        let mut state = State::new();
+        state.time = 1_620_000_000;
+        let type_keys = |state: &mut State, keys: &str| {
+            keys.chars()
+                .filter_map(|c| handle_key(state, Char(c)))
+                .collect::<Vec<_>>()
+        };
+
+        // Malformed input is rejected and leaves entry mode
+        assert!(type_keys(&mut state, "m60 10 2 1 0 0\n").is_empty());
+        assert!(state.input.is_none());
+
+        assert!(type_keys(&mut state, "m60 10 0.55").is_empty());
+        assert_eq!(handle_key(&mut state, Key::Backspace), None);
+        let requests = type_keys(&mut state, " 1 0 0\n");
+        assert_eq!(
+            requests,
+            vec![ControlRequest::Maneuver {
+                burns: vec![Burn {
+                    start: 1_620_000_060,
+                    length: 10,
+                    thrust: 0.5,
+                    vector: (1.0, 0.0, 0.0),
+                }]
+            }]
+        );
+        assert!(state.input.is_none());
+    }
+
+    #[test]
+    fn test_log_scroll_offset() {
+        // Following the newest entries shows the last page
+        assert_eq!(log_scroll_offset(100, 10, 0), 90);
+        assert_eq!(log_scroll_offset(100, 10, 25), 65);
+        assert_eq!(log_scroll_offset(100, 10, 500), 0);
+        // Everything fits, so there is nothing to scroll
+        assert_eq!(log_scroll_offset(5, 10, 3), 0);
+
+        let mut state = State::new();
+        for i in 0..20 {
+            state.log_message(format!("entry {}", i));
+        }
+        assert_eq!(handle_key(&mut state, Key::PageUp), None);
+        assert_eq!(state.log_scroll_back, LOG_PAGE);
+
+        // A scrolled view stays on the same entries as new ones arrive
+        state.log_message("entry 20".to_string());
+        assert_eq!(state.log_scroll_back, LOG_PAGE + 1);
+        for _ in 0..10 {
+            handle_key(&mut state, Key::PageUp);
+        }
+        assert_eq!(state.log_scroll_back, 21);
+        handle_key(&mut state, Key::End);
+        assert_eq!(state.log_scroll_back, 0);
+        state.log_message("entry 21".to_string());
+        assert_eq!(state.log_scroll_back, 0);
+    }
+
+    #[test]
+    fn test_log_filter() {
+        let mut state = State::new();
+        state.log_message("round trip time: 5ms".to_string());
+        state.log_message("ground channel error: reset".to_string());
+        state.log_message("round trip time: 6ms".to_string());
+
+        for c in "fround\n".chars() {
+            assert_eq!(handle_key(&mut state, Char(c)), None);
+        }
+        assert_eq!(state.log_filter.as_deref(), Some("round"));
+        assert_eq!(state.visible_log().len(), 3);
+        state.log_filter = Some("trip".to_string());
+        assert_eq!(state.visible_log().len(), 2);
+
+        // Maneuver entry still works after filtering, and 'f' clears the filter
+        assert_eq!(handle_key(&mut state, Char('f')), None);
+        assert!(state.log_filter.is_none());
+        assert_eq!(handle_key(&mut state, Char('m')), None);
+        assert_eq!(state.input_mode, InputMode::Maneuver);
+    }
+
+    #[test]
+    fn test_module_controls() {
+        let mut state = State::new();
+        assert_eq!(handle_key(&mut state, Char('e')), None);
+
+        state.modules = (0..3).map(|_| ModuleStatus::new(true, true, 0)).collect();
+        assert_eq!(handle_key(&mut state, Key::Up), None);
+        assert_eq!(state.selected_module, 0);
+        for _ in 0..3 {
+            assert_eq!(handle_key(&mut state, Key::Down), None);
+        }
+        assert_eq!(state.selected_module, 2);
+        assert_eq!(
+            handle_key(&mut state, Char('d')),
+            Some(ControlRequest::EnableModule {
+                id: 2,
+                enable: false
+            })
+        );
+        assert_eq!(handle_key(&mut state, Key::Up), None);
+        assert_eq!(
+            handle_key(&mut state, Char('e')),
+            Some(ControlRequest::EnableModule {
+                id: 1,
+                enable: true
+            })
+        );
+    }
+
+    #[test]
+    fn test_radiation_belt() {
+        let belt = RadiationBelt::sample();
+        assert!(belt.low.contains(&(-12.0, -4.0)));
+        assert!(belt.med.contains(&(-11.0, -2.0)));
+        assert!(belt.high.contains(&(-10.0, -3.0)));
+        assert_eq!(belt.high.len(), 24);
+
+        // Earth and deep space are clear of the belt
+        for p in &[(0.0, 0.0), (29.0, 29.0)] {
+            assert!(!belt.low.contains(p) && !belt.med.contains(p) && !belt.high.contains(p));
+        }
+    }
+
+    #[test]
+    fn test_most_repaired() {
+        let fields = vec![
+            FieldRepairs::new("restarts".to_string(), 1, 10),
+            FieldRepairs::new("modules[0]".to_string(), 5, 5),
+            FieldRepairs::new("events[3]".to_string(), 1, 20),
+            FieldRepairs::new("modules[2]".to_string(), 2, 1),
+            FieldRepairs::new("repairs".to_string(), 1, 0),
+        ];
+        let names: Vec<_> = most_repaired(&fields)
+            .into_iter()
+            .map(|f| f.field.as_str())
+            .collect();
+        assert_eq!(
+            names,
+            vec!["modules[0]", "modules[2]", "events[3]", "restarts"]
+        );
+    }
+
+    #[test]
+    fn test_recent_events() {
+        let events = vec![
+            Event::new(0, vec![]),
+            Event::new(5, b"e".to_vec()),
+            Event::new(10, b"a".to_vec()),
+            Event::with_severity(30, b"b".to_vec(), Severity::Error),
+            Event::new(20, b"c".to_vec()),
+            Event::with_severity(40, b"d".to_vec(), Severity::Warn),
+        ];
+        let recent: Vec<_> = recent_events(&events)
+            .into_iter()
+            .map(|e| (e.timestamp, e.severity))
+            .collect();
+        assert_eq!(
+            recent,
+            vec![
+                (40, Severity::Warn),
+                (20, Severity::Info),
+                (30, Severity::Error),
+                (10, Severity::Info)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_update_request() {
+        let dir = std::env::temp_dir().join(format!("rad_client_update_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).expect("create directory");
+        let module_path = dir.join("module.bin");
+        let signature_path = dir.join("module.sig");
+        std::fs::write(&module_path, [0x95, 0, 0, 0, 0, 0, 0, 0]).expect("write module");
+        std::fs::write(&signature_path, [7u8; SIGNATURE_SIZE]).expect("write signature");
+
+        let args = |module: &Path, signature: &Path| {
+            let args = vec![
+                "rad_client".into(),
+                "update".into(),
+                "-g".into(),
+                "127.0.0.1:1337".into(),
+                "-t".into(),
+                "token".into(),
+                "--id".into(),
+                "2".into(),
+                "--module_path".into(),
+                module.as_os_str().to_owned(),
+                "--signature_path".into(),
+                signature.as_os_str().to_owned(),
+                "--encoded".into(),
+            ];
+            match Config::from_iter_safe(args).expect("parse").command {
+                Command::Update(x) => x,
+                _ => panic!("expected update command"),
+            }
+        };
+        let update = args(&module_path, &signature_path);
+        assert_eq!(
+            update.request().expect("request"),
+            ControlRequest::UpdateModule {
+                id: 2,
+                module: vec![0x95, 0, 0, 0, 0, 0, 0, 0],
+                signature: vec![7u8; SIGNATURE_SIZE],
+                encoded: true,
+            }
+        );
+
+        // Oversized modules and malformed signatures are refused before connecting
+        let oversized_path = dir.join("oversized.bin");
+        std::fs::write(&oversized_path, vec![0u8; MAX_MODULE_SIZE + 1]).expect("write module");
+        let error = args(&oversized_path, &signature_path)
+            .request()
+            .unwrap_err();
+        assert!(error.to_string().contains("byte limit"));
+        let error = args(&module_path, &module_path).request().unwrap_err();
+        assert!(error.to_string().contains("signature"));
+
+        std::fs::remove_dir_all(&dir).expect("remove directory");
+    }
+
+    #[test]
+    fn test_record_elements() {
+        let mut state = State::new();
+        state.record_elements(ControlResponse::KeplerianElements {
+            success: true,
+            dt: 1_620_000_000,
+            sma: 7000.0,
+            ecc: 0.01,
+            inc: 51.6,
+            raan: 30.0,
+            aop: 10.0,
+            ta: 20.0,
+        });
+        assert_eq!(
+            state.elements,
+            Some(OrbitElements {
+                sma: 7000.0,
+                ecc: 0.01,
+                inc: 51.6,
+                raan: 30.0,
+                aop: 10.0,
+                ta: 20.0,
+            })
+        );
+
+        // A failed request keeps the last known orbit
+        state.record_elements(ControlRequest::KeplerianElements.to_failure());
+        assert_eq!(state.elements.map(|x| x.sma), Some(7000.0));
+        assert_eq!(state.log.len(), 1);
+    }
+
+    #[test]
+    fn test_telemetry_record() {
+        let record = TelemetryRecord {
+            timestamp: 1_620_000_000,
+            position: (7000.5, -1.0, 0.0),
+            velocity: (0.0, 7.5, 0.25),
+            fuel: 50.0,
+            radiation: 0.125,
+        };
+        assert_eq!(
+            record.to_string(),
+            "1620000000 position=(7000.5, -1, 0) velocity=(0, 7.5, 0.25) fuel=50 radiation=0.125"
+        );
+        assert_eq!(
+            serde_json::to_string(&record).expect("json"),
+            r#"{"timestamp":1620000000,"position":[7000.5,-1.0,0.0],"velocity":[0.0,7.5,0.25],"fuel":50.0,"radiation":0.125}"#
+        );
+    }
+
+    #[test]
+    fn test_handshake_rejects_mismatch() {
+        let firmware = ControlResponse::Firmware {
+            success: true,
+            repairs: 0,
+            restarts: 0,
+            repairs_failed: 0,
+            events: vec![Event::new(0, vec![0u8; MAX_MESSAGE_SIZE])],
+            modules: vec![ModuleStatus::new(false, false, 0)],
+            field_repairs: vec![],
+        };
+        check_handshake(&ControlResponse::NoOp, &firmware).expect("valid handshake");
+
+        // A server sending a response variant this build does not know
+        let mut buffer = bincode::serialize(&firmware).expect("encode");
+        buffer[..4].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(bincode::deserialize::<ControlResponse>(&buffer).is_err());
+
+        // A server built with a different event message size
+        let firmware = ControlResponse::Firmware {
+            success: true,
+            repairs: 0,
+            restarts: 0,
+            repairs_failed: 0,
+            events: vec![Event::new(0, vec![0u8; MAX_MESSAGE_SIZE / 2])],
+            field_repairs: vec![],
+            modules: vec![],
+        };
+        assert!(check_handshake(&ControlResponse::NoOp, &firmware).is_err());
+        assert!(check_handshake(&ControlResponse::Disconnect, &firmware).is_err());
+    }
+
+    #[test]
+    fn test_backfill_after_reconnect() {
+        let mut state = State::new();
+        state.record_radiation(100, 1.0);
+        state.record_radiation(110, 2.0);
+
+        // Connection drops; the server keeps sampling, including one overlapping sample
+        let samples = vec![
+            SensorSample::new(110, 0.0, 2.0),
+            SensorSample::new(120, 0.0, 3.0),
+            SensorSample::new(130, 0.0, 4.0),
+        ];
+        assert_eq!(state.last_sample(), Some(110));
+        state.backfill_radiation(&samples);
+
+        // Live telemetry is keyed by the server's time, like the backfilled samples
+        state.record_telemetry(ControlResponse::Telemetry {
+            success: true,
+            t: 140,
+            position: (7000.0, 0.0, 0.0),
+            velocity: (0.0, 7.5, 0.0),
+            fuel: 20.0,
+            radiation: 5.0,
+            eclipse: Eclipse::Sunlit,
+            repairs: 0,
+            restarts: 0,
+            repairs_failed: 0,
+            events: vec![],
+            modules: vec![],
+            field_repairs: vec![],
+        });
+
+        let timestamps: Vec<_> = state.radiation.iter().map(|(t, _)| *t).collect();
+        assert_eq!(timestamps, vec![100, 110, 120, 130, 140]);
+        let levels: Vec<_> = state.radiation.iter().map(|(_, r)| *r).collect();
+        assert_eq!(levels, vec![1.0, 2.0, 3.0, 4.0, 5.0]);
+    }
+
+    #[tokio::test]
+    async fn test_debug_protocol() {
+        let path =
+            std::env::temp_dir().join(format!("rad_client_protocol_{}.log", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let protocol_log = ProtocolLog::open(path.to_str().expect("path")).expect("open");
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("bind");
+        let address = listener.local_addr().expect("address");
+        let server = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.expect("accept");
+            let size = socket.read_u32().await.expect("read request length");
+            let mut buffer = vec![0u8; size as _];
+            socket.read_exact(&mut buffer).await.expect("read request");
+            let request: ControlRequest = message::decode(&buffer).expect("decode");
+            let (request_id, _) = request.untag();
+            let response = ControlResponse::Sensors {
+                success: true,
+                fuel: 19.5,
+                radiation: 0.25,
+                eclipse: Eclipse::Penumbra,
+            }
+            .tag(request_id);
+            let buffer = message::encode(&response).expect("encode");
+            socket
+                .write_u32(buffer.len() as _)
+                .await
+                .expect("write length");
+            socket.write_all(&buffer).await.expect("write response");
+        });
+
+        let mut socket = TcpStream::connect(address).await.expect("connect");
+        send_request(&mut socket, ControlRequest::Sensors, Some(&protocol_log))
+            .await
+            .expect("send request");
+        server.await.expect("server");
+
+        let log = std::fs::read_to_string(&path).expect("read log");
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(log.lines().count(), 1);
+        assert!(log
+            .contains("Sensors { success: true, fuel: 19.5, radiation: 0.25, eclipse: Penumbra }"));
     }
 }
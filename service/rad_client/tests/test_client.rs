@@ -2,7 +2,6 @@
 
 use anyhow::{anyhow, Result};
 use rad_common::*;
-use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, CHACHA20_POLY1305};
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::TcpStream;
 use tokio::time::{timeout, Duration};
@@ -83,7 +82,7 @@ async fn do_test_observe() -> Result<()> {
         )
         .await??;
         match response {
-            ControlResponse::EnableModule { success } => {
+            ControlResponse::EnableModule { success, .. } => {
                 assert!(success);
             }
             _ => panic!("expected enable module response"),
@@ -102,17 +101,7 @@ async fn do_test_observe() -> Result<()> {
 
 async fn authenticate(socket: &mut TcpStream) -> Result<()> {
     let timeout_duration = Duration::from_secs(5);
-    let auth_key = UnboundKey::new(&CHACHA20_POLY1305, &RAD_AUTH_KEY)
-        .map_err(|_| anyhow!("create auth key"))?;
-    let auth_key = LessSafeKey::new(auth_key);
-    let nonce = Nonce::assume_unique_for_key([0u8; 12]);
-    let mut token = TEST_TOKEN.as_bytes().to_vec();
-    auth_key.seal_in_place_append_tag(nonce, Aad::empty(), &mut token)?;
-    let nonce = Nonce::assume_unique_for_key([0u8; 12]);
-    let request = ControlRequest::Authenticate {
-        token,
-        nonce: nonce.as_ref().to_vec(),
-    };
+    let request = auth::authenticate(RAD_AUTH_KEY, TEST_TOKEN).map_err(|e| anyhow!(e))?;
     match timeout(timeout_duration, send(socket, request)).await?? {
         ControlResponse::Authenticate {
             authenticated,
@@ -123,16 +112,24 @@ async fn authenticate(socket: &mut TcpStream) -> Result<()> {
         }
         _ => panic!("expected authentication response"),
     }
+    let request = ControlRequest::Hello {
+        version: message::PROTOCOL_VERSION,
+        key_version: 0,
+    };
+    match timeout(timeout_duration, send(socket, request)).await?? {
+        ControlResponse::Hello { accepted, .. } => assert!(accepted),
+        _ => panic!("expected hello response"),
+    }
     Ok(())
 }
 
 async fn send(socket: &mut TcpStream, request: ControlRequest) -> Result<ControlResponse> {
-    let buffer = bincode::serialize(&request)?;
+    let buffer = message::encode(&request)?;
     socket.write_u32(buffer.len() as _).await?;
     socket.write_all(&buffer).await?;
     let size = socket.read_u32().await?;
     let mut buffer = vec![0u8; size as _];
     socket.read_exact(&mut buffer).await?;
-    let response = bincode::deserialize(&buffer)?;
+    let response = message::decode(&buffer)?;
     Ok(response)
 }
@@ -0,0 +1,63 @@
+//! Control response read path benchmarks.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use rad_common::frame::{read_frame, write_frame, MAX_FRAME_SIZE};
+use rad_common::{ControlResponse, Event, ModuleStatus, MAX_MESSAGE_SIZE};
+use std::io::{Cursor, Read};
+
+const POLLS: usize = 64;
+
+/// Build a stream of firmware responses as seen by a polling client.
+fn firmware_stream() -> Vec<u8> {
+    let response = ControlResponse::Firmware {
+        success: true,
+        repairs: 0,
+        restarts: 0,
+        repairs_failed: 0,
+        events: (0..32)
+            .map(|i| Event::new(i, vec![0u8; MAX_MESSAGE_SIZE]))
+            .collect(),
+        modules: (0..4).map(|_| ModuleStatus::new(false, false, 0)).collect(),
+        field_repairs: vec![],
+    };
+    let data = bincode::serialize(&response).expect("encode");
+    let mut stream = vec![];
+    for _ in 0..POLLS {
+        write_frame(&mut stream, &data).expect("write frame");
+    }
+    stream
+}
+
+/// Read frames allocating a fresh buffer per message.
+fn read_fresh(stream: &[u8]) {
+    let mut reader = Cursor::new(stream);
+    for _ in 0..POLLS {
+        let mut size = [0u8; 4];
+        reader.read_exact(&mut size).expect("read size");
+        let mut buffer = vec![0u8; u32::from_be_bytes(size) as usize];
+        reader.read_exact(&mut buffer).expect("read frame");
+        black_box(&buffer);
+    }
+}
+
+/// Read frames into a per-connection scratch buffer.
+fn read_reused(stream: &[u8]) {
+    let mut reader = Cursor::new(stream);
+    let mut buffer = vec![];
+    for _ in 0..POLLS {
+        read_frame(&mut reader, &mut buffer, MAX_FRAME_SIZE).expect("read frame");
+        black_box(&buffer);
+    }
+}
+
+fn bench_read(c: &mut Criterion) {
+    let stream = firmware_stream();
+    let mut group = c.benchmark_group("read_frame");
+    group.throughput(Throughput::Bytes(stream.len() as u64));
+    group.bench_function("fresh", |b| b.iter(|| read_fresh(&stream)));
+    group.bench_function("reused", |b| b.iter(|| read_reused(&stream)));
+    group.finish();
+}
+
+criterion_group!(benches, bench_read);
+criterion_main!(benches);
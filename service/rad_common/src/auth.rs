@@ -0,0 +1,30 @@
+//! Ground control authentication.
+
+use crate::ControlRequest;
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, CHACHA20_POLY1305, NONCE_LEN};
+use ring::rand::{SecureRandom, SystemRandom};
+
+/// Build an authentication request, sealing a team token under an auth key.
+///
+/// Each request draws a fresh random nonce, so that no two sessions reuse one under the same key.
+pub fn authenticate(auth_key: &[u8], token: &str) -> Result<ControlRequest, String> {
+    let auth_key =
+        UnboundKey::new(&CHACHA20_POLY1305, auth_key).map_err(|_| "create auth key".to_string())?;
+    let auth_key = LessSafeKey::new(auth_key);
+    let mut nonce = [0u8; NONCE_LEN];
+    SystemRandom::new()
+        .fill(&mut nonce)
+        .map_err(|_| "generate nonce".to_string())?;
+    let mut token = token.as_bytes().to_vec();
+    auth_key
+        .seal_in_place_append_tag(
+            Nonce::assume_unique_for_key(nonce),
+            Aad::empty(),
+            &mut token,
+        )
+        .map_err(|_| "seal token".to_string())?;
+    Ok(ControlRequest::Authenticate {
+        token,
+        nonce: nonce.to_vec(),
+    })
+}
@@ -2,9 +2,44 @@ extern crate nyx_space as nyx;
 
 use chrono::{Datelike, Timelike, Utc};
 use nyx::celestia::{Cosm, Epoch, State};
-use rad_common::compute_radiation;
+use rad_common::{check_ephemeris, compute_radiation, ephemeris_path};
+use std::fmt::Write;
+use structopt::StructOpt;
+
+/// Sample the radiation model into plot point arrays
+#[derive(StructOpt)]
+#[structopt(rename_all = "snake_case")]
+struct Config {
+    /// Lowest grid coordinate (Mm)
+    #[structopt(long, default_value = "-30", allow_hyphen_values = true)]
+    min: i32,
+    /// Highest grid coordinate, exclusive (Mm)
+    #[structopt(long, default_value = "30", allow_hyphen_values = true)]
+    max: i32,
+    /// Radiation above which a point is in the low band
+    #[structopt(long, default_value = "10")]
+    low: f64,
+    /// Radiation above which a point is in the medium band
+    #[structopt(long, default_value = "100")]
+    med: f64,
+    /// Radiation above which a point is in the high band
+    #[structopt(long, default_value = "350")]
+    high: f64,
+}
 
 fn main() {
+    let conf = Config::from_args();
+    if conf.min >= conf.max || !(conf.low <= conf.med && conf.med <= conf.high) {
+        eprintln!("grid bounds and thresholds must be increasing");
+        std::process::exit(1);
+    }
+
+    let ephemeris_path = ephemeris_path();
+    if let Err(e) = check_ephemeris(&ephemeris_path) {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+
     let ts = Utc::now();
     let dt = Epoch::from_gregorian_utc(
         ts.year(),
@@ -15,29 +50,81 @@ fn main() {
         ts.second() as _,
         ts.nanosecond(),
     );
-    let cosm = Cosm::from_xb("data/de438s");
+    let cosm = Cosm::from_xb(&ephemeris_path);
     let eme2k = cosm.frame("EME2000");
 
     let mut low = vec![];
     let mut med = vec![];
     let mut high = vec![];
 
-    for x in -30..30 {
-        for y in -30..30 {
+    for x in conf.min..conf.max {
+        for y in conf.min..conf.max {
             let state = State::from_position((x * 1000) as _, (y * 1000) as _, 0.0, dt, eme2k);
             let level = compute_radiation(state.geodetic_latitude(), state.geodetic_height());
-            // println!("({}, {}) {}", x, y, level);
-            if level > 350.0 {
+            if level > conf.high {
                 high.push((x as f64, y as f64));
-            } else if level > 100.0 {
+            } else if level > conf.med {
                 med.push((x as f64, y as f64));
-            } else if level > 10.0 {
+            } else if level > conf.low {
                 low.push((x as f64, y as f64));
             }
         }
     }
 
-    println!("L {:?}", low);
-    println!("M {:?}", med);
-    println!("H {:?}", high);
+    print!("{}", points_const("RAD_PTS_LOW", &low));
+    print!("{}", points_const("RAD_PTS_MED", &med));
+    print!("{}", points_const("RAD_PTS_HIGH", &high));
+}
+
+/// Render points as a Rust `&[(f64, f64)]` constant declaration.
+fn points_const(name: &str, points: &[(f64, f64)]) -> String {
+    let mut output = format!("pub const {}: &[(f64, f64)] = &[\n", name);
+    for (x, y) in points {
+        let _ = writeln!(output, "    ({:?}, {:?}),", x, y);
+    }
+    output.push_str("];\n");
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Parse a declaration rendered by `points_const` back into its name and points.
+    fn parse_points_const(text: &str) -> Option<(&str, Vec<(f64, f64)>)> {
+        let mut lines = text.lines();
+        let name = lines
+            .next()?
+            .strip_prefix("pub const ")?
+            .strip_suffix(": &[(f64, f64)] = &[")?;
+        let mut points = vec![];
+        for line in lines {
+            if line == "];" {
+                return Some((name, points));
+            }
+            let (x, y) = line
+                .trim()
+                .strip_prefix('(')?
+                .strip_suffix("),")?
+                .split_once(", ")?;
+            points.push((x.parse().ok()?, y.parse().ok()?));
+        }
+        None
+    }
+
+    #[test]
+    fn test_points_const() {
+        let points = vec![(-30.0, 2.0), (0.0, -0.5), (29.0, 29.0)];
+        let text = points_const("RAD_PTS_MED", &points);
+        assert_eq!(
+            parse_points_const(&text),
+            Some(("RAD_PTS_MED", points.clone()))
+        );
+        // Floats keep a decimal point so they are not integer literals
+        assert!(text.contains("(-30.0, 2.0),"));
+
+        let text = points_const("RAD_PTS_HIGH", &[]);
+        assert_eq!(text, "pub const RAD_PTS_HIGH: &[(f64, f64)] = &[\n];\n");
+        assert_eq!(parse_points_const(&text), Some(("RAD_PTS_HIGH", vec![])));
+    }
 }
@@ -1,15 +1,31 @@
-use jsonwebtoken::dangerous_insecure_decode;
+use jsonwebtoken::{dangerous_insecure_decode, decode, Algorithm, DecodingKey, Validation};
+use rad_common::hash_ring::{self, HashRing, VIRTUAL_NODES};
+use rad_common::TEST_TOKEN;
 use ring::digest::{digest, SHA256};
 use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io::{self, Write};
+use std::net::SocketAddr;
 use structopt::StructOpt;
 
 /// Output team identifiers
 #[derive(StructOpt)]
 #[structopt(rename_all = "snake_case")]
 struct Config {
-    /// Number of nodes
-    #[structopt(short, long, default_value = "4")]
-    nodes: usize,
+    /// Node addresses, in the order the proxy configuration lists them
+    #[structopt(
+        short,
+        long,
+        use_delimiter = true,
+        default_value = "10.116.0.2:1338,10.116.0.4:1338,10.116.0.3:1338,10.116.0.5:1338"
+    )]
+    nodes: Vec<SocketAddr>,
+    /// Secret verifying token signatures, read from RAD_TOKEN_SECRET if unset
+    #[structopt(long)]
+    token_secret: Option<String>,
+    /// Output JSON
+    #[structopt(long)]
+    json: bool,
     #[structopt(subcommand)]
     command: Command,
 }
@@ -69,46 +85,153 @@ struct Token {
     user_id: usize,
 }
 
+/// Team identifiers.
+#[derive(Serialize)]
+struct Mapping {
+    team: usize,
+    node: usize,
+    port: usize,
+}
+
+impl Mapping {
+    /// Look up a team's identifiers.
+    fn new(team: usize, nodes: &HashRing) -> Self {
+        let (node, port) = get_identifiers(team, nodes);
+        Self { team, node, port }
+    }
+}
+
+impl fmt::Display for Mapping {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "team={} node={} port={}", self.team, self.node, self.port)
+    }
+}
+
+/// Write a mapping as a line of text or a JSON object.
+fn write_mapping<W: Write>(mut out: W, mapping: &Mapping, json: bool) -> io::Result<()> {
+    if json {
+        serde_json::to_writer(&mut out, mapping)?;
+        writeln!(out)
+    } else {
+        writeln!(out, "{}", mapping)
+    }
+}
+
+/// Write mappings one per line, or as a JSON array.
+fn write_mappings<W: Write>(mut out: W, mappings: &[Mapping], json: bool) -> io::Result<()> {
+    if json {
+        serde_json::to_writer(&mut out, mappings)?;
+        writeln!(out)
+    } else {
+        mappings.iter().try_for_each(|x| writeln!(out, "{}", x))
+    }
+}
+
+/// Decode a token, verifying its signature and expiry.
+fn decode_token(secret: Option<&str>, token: &str) -> usize {
+    if token == TEST_TOKEN {
+        return insecure_decode_test_token(token);
+    }
+    let secret = secret.expect("no token secret configured");
+    let data = decode::<Token>(
+        token,
+        &DecodingKey::from_secret(secret.as_bytes()),
+        &Validation::new(Algorithm::HS256),
+    )
+    .expect("decode");
+    data.claims.user_id
+}
+
+/// Decode the test token without verifying it; its signing secret is not deployed.
+fn insecure_decode_test_token(token: &str) -> usize {
+    let data = dangerous_insecure_decode::<Token>(token).expect("decode");
+    data.claims.user_id
+}
+
 fn main() {
-    let conf = Config::from_args();
+    let mut conf = Config::from_args();
+    if conf.token_secret.is_none() {
+        conf.token_secret = std::env::var("RAD_TOKEN_SECRET").ok();
+    }
+    let secret = conf.token_secret.as_deref();
+    let nodes = HashRing::new(&conf.nodes, VIRTUAL_NODES);
+    let stdout = io::stdout();
+    let out = stdout.lock();
     match conf.command {
         Command::AllTeams(ref cmd) => {
-            for i in 0..cmd.max_id {
-                let (node_index, team_port) = get_identifiers(i, conf.nodes);
-                println!("team={} node={} port={}", i, node_index, team_port);
-            }
+            let mappings: Vec<_> = (0..cmd.max_id)
+                .map(|i| Mapping::new(i, &nodes))
+                .collect();
+            write_mappings(out, &mappings, conf.json).expect("write");
         }
         Command::FromTeam(ref cmd) => {
-            let data = dangerous_insecure_decode::<Token>(&cmd.token).expect("decode");
-            let (node_index, team_port) = get_identifiers(data.claims.user_id, conf.nodes);
-            println!(
-                "team={} node={} port={}",
-                data.claims.user_id, node_index, team_port
-            );
+            let team_id = decode_token(secret, &cmd.token);
+            write_mapping(out, &Mapping::new(team_id, &nodes), conf.json).expect("write");
         }
         Command::ToTeam(ref cmd) => {
-            for i in 0..1024 {
-                let (node_index, team_port) = get_identifiers(i, conf.nodes);
-                if team_port == cmd.port as usize {
-                    println!("team={} node={} port={}", i, node_index, team_port);
-                }
-            }
+            let mappings: Vec<_> = (0..1024)
+                .map(|i| Mapping::new(i, &nodes))
+                .filter(|x| x.port == cmd.port as usize)
+                .collect();
+            write_mappings(out, &mappings, conf.json).expect("write");
         }
         Command::TestAuth(ref cmd) => {
             let url = format!("{}/{}", cmd.auth_url, cmd.token);
-            let data = dangerous_insecure_decode::<Token>(&cmd.token).expect("decode");
+            let team_id = decode_token(secret, &cmd.token);
             let response = reqwest::blocking::get(url).expect("get");
-            println!("team={} authenticated={}", data.claims.user_id, response.status().is_success());
+            let authenticated = response.status().is_success();
+            if conf.json {
+                let result = serde_json::json!({"team": team_id, "authenticated": authenticated});
+                println!("{}", result);
+            } else {
+                println!("team={} authenticated={}", team_id, authenticated);
+            }
         }
     }
 }
 
-fn get_identifiers(id: usize, nodes: usize) -> (usize, usize) {
+/// Look up the node owning a team on the proxy's hash ring, and the team's service port.
+fn get_identifiers(id: usize, nodes: &HashRing) -> (usize, usize) {
+    let node_index = nodes.successors(hash_ring::hash(&id.to_be_bytes()))[0];
     let team_digest = digest(&SHA256, &id.to_be_bytes());
     let mut team_bytes = [0u8; 8];
     team_bytes.copy_from_slice(&team_digest.as_ref()[..8]);
     let team_index = usize::from_be_bytes(team_bytes);
-    let node_index = team_index % nodes;
     let team_port = 1024 + (team_index % 64000);
     (node_index, team_port)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+
+    #[test]
+    fn test_json_output() {
+        let addresses: Vec<SocketAddr> = (0..4)
+            .map(|i| format!("10.116.0.{}:1338", i + 2).parse().expect("address"))
+            .collect();
+        let nodes = HashRing::new(&addresses, VIRTUAL_NODES);
+        let mappings: Vec<_> = (0..4).map(|i| Mapping::new(i, &nodes)).collect();
+        let mut output = vec![];
+        write_mappings(&mut output, &mappings, true).expect("write");
+        let value: Value = serde_json::from_slice(&output).expect("json");
+        let entries = value.as_array().expect("array");
+        assert_eq!(entries.len(), 4);
+        for (i, entry) in entries.iter().enumerate() {
+            let (node, port) = get_identifiers(i, &nodes);
+            assert_eq!(entry["team"], i);
+            assert_eq!(entry["node"], node);
+            assert_eq!(entry["port"], port);
+        }
+
+        let mut output = vec![];
+        write_mapping(&mut output, &mappings[1], true).expect("write");
+        let value: Value = serde_json::from_slice(&output).expect("json");
+        assert_eq!(value["team"], 1);
+
+        let mut output = vec![];
+        write_mappings(&mut output, &mappings[..1], false).expect("write");
+        assert_eq!(String::from_utf8(output).expect("utf-8"), format!("{}\n", mappings[0]));
+    }
+}
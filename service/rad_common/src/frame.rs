@@ -0,0 +1,116 @@
+//! Length-prefixed message framing.
+
+use std::io::{Error, ErrorKind, Read, Result, Write};
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+/// Default maximum frame length, comfortably above the largest control message.
+#[cfg(not(feature = "json"))]
+pub const MAX_FRAME_SIZE: usize = 16 * 1024;
+/// Default maximum frame length, allowing for JSON spelling out byte arrays as decimal lists.
+#[cfg(feature = "json")]
+pub const MAX_FRAME_SIZE: usize = 64 * 1024;
+/// Maximum firmware service request length, large enough for a protected state checkpoint.
+#[cfg(not(feature = "json"))]
+pub const MAX_CHECKPOINT_FRAME_SIZE: usize = 128 * 1024;
+/// Maximum firmware service request length, allowing for JSON spelling out every checkpoint
+/// byte as up to four characters.
+#[cfg(feature = "json")]
+pub const MAX_CHECKPOINT_FRAME_SIZE: usize = 512 * 1024;
+
+/// Check a frame length against a maximum before anything is allocated for it.
+pub fn check_size(size: u32, max: usize) -> Result<usize> {
+    let size = size as usize;
+    if size > max {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("frame size {} exceeds maximum {}", size, max),
+        ));
+    }
+    Ok(size)
+}
+
+/// Read a length-prefixed frame of at most `max` bytes into a reusable buffer.
+///
+/// The buffer is cleared and resized to the frame length, so its allocation is only grown when a
+/// frame is larger than any previously read on the same buffer.
+pub fn read_frame<R>(reader: &mut R, buffer: &mut Vec<u8>, max: usize) -> Result<()>
+where
+    R: Read,
+{
+    let mut size = [0u8; 4];
+    reader.read_exact(&mut size)?;
+    let size = check_size(u32::from_be_bytes(size), max)?;
+    buffer.clear();
+    buffer.resize(size, 0);
+    reader.read_exact(buffer)
+}
+
+/// Read a length-prefixed frame of at most `max` bytes from an asynchronous reader.
+pub async fn read_framed<R>(reader: &mut R, buffer: &mut Vec<u8>, max: usize) -> Result<()>
+where
+    R: AsyncRead + Unpin,
+{
+    let size = check_size(reader.read_u32().await?, max)?;
+    buffer.clear();
+    buffer.resize(size, 0);
+    reader.read_exact(buffer).await?;
+    Ok(())
+}
+
+/// Write a length-prefixed frame.
+pub fn write_frame<W>(writer: &mut W, data: &[u8]) -> Result<()>
+where
+    W: Write,
+{
+    writer.write_all(&(data.len() as u32).to_be_bytes())?;
+    writer.write_all(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_reuse_buffer() {
+        let mut stream = vec![];
+        write_frame(&mut stream, &[1u8; 16]).expect("write");
+        write_frame(&mut stream, &[2u8; 4]).expect("write");
+        let mut stream = Cursor::new(stream);
+
+        let mut buffer = vec![];
+        read_frame(&mut stream, &mut buffer, MAX_FRAME_SIZE).expect("read");
+        assert_eq!(buffer, [1u8; 16]);
+        let capacity = buffer.capacity();
+        read_frame(&mut stream, &mut buffer, MAX_FRAME_SIZE).expect("read");
+        assert_eq!(buffer, [2u8; 4]);
+        assert_eq!(buffer.capacity(), capacity);
+    }
+
+    #[test]
+    fn test_oversized_frame() {
+        let mut stream = Cursor::new([0xffu8; 4]);
+        let mut buffer = vec![];
+        let e = read_frame(&mut stream, &mut buffer, MAX_FRAME_SIZE).expect_err("oversized");
+        assert_eq!(e.kind(), ErrorKind::InvalidData);
+        assert_eq!(buffer.capacity(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_oversized_framed() {
+        let mut stream = &[0xffu8; 4][..];
+        let mut buffer = vec![];
+        let e = read_framed(&mut stream, &mut buffer, MAX_FRAME_SIZE)
+            .await
+            .expect_err("oversized");
+        assert_eq!(e.kind(), ErrorKind::InvalidData);
+        assert_eq!(buffer.capacity(), 0);
+
+        let mut stream = vec![];
+        write_frame(&mut stream, &[3u8; 8]).expect("write");
+        read_framed(&mut &stream[..], &mut buffer, MAX_FRAME_SIZE)
+            .await
+            .expect("read");
+        assert_eq!(buffer, [3u8; 8]);
+    }
+}
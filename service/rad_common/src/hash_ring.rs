@@ -0,0 +1,94 @@
+//! Consistent hashing of teams onto nodes.
+
+use ring::digest::{digest, SHA256};
+use std::net::SocketAddr;
+
+/// Virtual nodes placed on the ring per node.
+pub const VIRTUAL_NODES: usize = 160;
+
+/// Hash ring mapping keys to node indices.
+#[derive(Debug, Clone)]
+pub struct HashRing {
+    points: Vec<(u64, usize)>,
+}
+
+impl HashRing {
+    /// Build a ring with `replicas` virtual nodes per node.
+    pub fn new(nodes: &[SocketAddr], replicas: usize) -> Self {
+        let mut points = vec![];
+        for (i, node) in nodes.iter().enumerate() {
+            for replica in 0..replicas {
+                points.push((hash(format!("{}#{}", node, replica).as_bytes()), i));
+            }
+        }
+        points.sort_unstable();
+        Self { points }
+    }
+
+    /// List the distinct node indices in ring order, starting from the owner of a key hash.
+    pub fn successors(&self, key: u64) -> Vec<usize> {
+        let i = self.points.partition_point(|(point, _)| *point < key);
+        let mut nodes = vec![];
+        for (_, node) in self.points[i..].iter().chain(self.points[..i].iter()) {
+            if !nodes.contains(node) {
+                nodes.push(*node);
+            }
+        }
+        nodes
+    }
+}
+
+/// Hash data onto the ring.
+pub fn hash(data: &[u8]) -> u64 {
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&digest(&SHA256, data).as_ref()[..8]);
+    u64::from_be_bytes(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn nodes(n: usize) -> Vec<SocketAddr> {
+        (0..n)
+            .map(|i| format!("10.116.0.{}:1338", i + 2).parse().expect("address"))
+            .collect()
+    }
+
+    #[test]
+    fn test_add_node() {
+        let before = HashRing::new(&nodes(4), VIRTUAL_NODES);
+        let after = HashRing::new(&nodes(5), VIRTUAL_NODES);
+
+        let teams = 10_000usize;
+        let mut moved = 0;
+        let mut counts = [0usize; 5];
+        for team_id in 0..teams {
+            let key = hash(&team_id.to_be_bytes());
+            let a = before.successors(key)[0];
+            let b = after.successors(key)[0];
+            counts[b] += 1;
+            if a != b {
+                // Teams only move onto the new node
+                assert_eq!(b, 4);
+                moved += 1;
+            }
+        }
+
+        // Ideally a fifth of the teams move
+        assert!(moved < teams * 3 / 10, "{} teams moved", moved);
+        assert!(counts.iter().all(|x| *x > teams / 10), "{:?}", counts);
+        assert!(HashRing::new(&[], VIRTUAL_NODES).successors(0).is_empty());
+    }
+
+    #[test]
+    fn test_successors() {
+        let ring = HashRing::new(&nodes(4), VIRTUAL_NODES);
+        for team_id in 0..64usize {
+            let key = hash(&team_id.to_be_bytes());
+            let mut sorted = ring.successors(key);
+            sorted.sort_unstable();
+            assert_eq!(sorted, vec![0, 1, 2, 3]);
+        }
+    }
+}
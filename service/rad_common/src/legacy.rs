@@ -0,0 +1,258 @@
+//! Legacy control protocol.
+//!
+//! Ground control clients that predate the hello speak protocol version 0.  Their requests still
+//! decode as the first variants of `ControlRequest`, but responses have gained fields since, so
+//! responses to them are converted down to the layouts below.
+
+use serde::{Deserialize, Serialize};
+
+/// Ground control response, as laid out before the hello.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub enum ControlResponse {
+    NoOp,
+    Authenticate {
+        authenticated: bool,
+        connected: bool,
+    },
+    Reset {
+        success: bool,
+    },
+    Firmware {
+        success: bool,
+        repairs: u64,
+        restarts: u64,
+        events: Vec<Event>,
+        modules: Vec<ModuleStatus>,
+    },
+    PositionVelocity {
+        success: bool,
+        t: u64,
+        p: (f64, f64, f64),
+        v: (f64, f64, f64),
+    },
+    KeplerianElements {
+        success: bool,
+        dt: u64,
+        sma: f64,
+        ecc: f64,
+        inc: f64,
+        raan: f64,
+        aop: f64,
+        ta: f64,
+    },
+    Sensors {
+        success: bool,
+        fuel: f64,
+        radiation: f64,
+    },
+    EnableModule {
+        success: bool,
+    },
+    UpdateModule {
+        success: bool,
+        checksum: u64,
+        verified: bool,
+        enabled: bool,
+    },
+    Maneuver {
+        success: bool,
+    },
+    Custom {
+        data: Vec<u8>,
+    },
+    Disconnect,
+}
+
+impl ControlResponse {
+    /// Convert a response down to the legacy layout, if it has one.
+    pub fn downgrade(response: &crate::ControlResponse) -> Option<Self> {
+        use crate::ControlResponse as Current;
+        let response = match *response {
+            Current::NoOp => ControlResponse::NoOp,
+            Current::Authenticate {
+                authenticated,
+                connected,
+            } => ControlResponse::Authenticate {
+                authenticated,
+                connected,
+            },
+            Current::Reset { success } => ControlResponse::Reset { success },
+            Current::Firmware {
+                success,
+                repairs,
+                restarts,
+                ref events,
+                ref modules,
+                ..
+            } => ControlResponse::Firmware {
+                success,
+                repairs,
+                restarts,
+                events: events.iter().map(Event::from).collect(),
+                modules: modules.iter().map(ModuleStatus::from).collect(),
+            },
+            Current::PositionVelocity { success, t, p, v } => {
+                ControlResponse::PositionVelocity { success, t, p, v }
+            }
+            Current::KeplerianElements {
+                success,
+                dt,
+                sma,
+                ecc,
+                inc,
+                raan,
+                aop,
+                ta,
+            } => ControlResponse::KeplerianElements {
+                success,
+                dt,
+                sma,
+                ecc,
+                inc,
+                raan,
+                aop,
+                ta,
+            },
+            Current::Sensors {
+                success,
+                fuel,
+                radiation,
+                ..
+            } => ControlResponse::Sensors {
+                success,
+                fuel,
+                radiation,
+            },
+            Current::EnableModule { success, .. } => ControlResponse::EnableModule { success },
+            Current::UpdateModule {
+                success,
+                checksum,
+                verified,
+                enabled,
+                ..
+            } => ControlResponse::UpdateModule {
+                success,
+                checksum,
+                verified,
+                enabled,
+            },
+            Current::Maneuver { success, .. } => ControlResponse::Maneuver { success },
+            Current::Custom { ref data } => ControlResponse::Custom { data: data.clone() },
+            Current::Disconnect => ControlResponse::Disconnect,
+            _ => return None,
+        };
+        Some(response)
+    }
+}
+
+/// Event, without a severity.
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Event {
+    pub timestamp: u64,
+    pub message: Vec<u8>,
+}
+
+impl From<&crate::Event> for Event {
+    fn from(event: &crate::Event) -> Self {
+        Self {
+            timestamp: event.timestamp,
+            message: event.message.clone(),
+        }
+    }
+}
+
+impl From<Event> for crate::Event {
+    fn from(event: Event) -> Self {
+        crate::Event::new(event.timestamp, event.message)
+    }
+}
+
+/// Module status, without the update time or code length.
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ModuleStatus {
+    pub enabled: bool,
+    pub verified: bool,
+    pub checksum: u64,
+}
+
+impl From<&crate::ModuleStatus> for ModuleStatus {
+    fn from(status: &crate::ModuleStatus) -> Self {
+        Self {
+            enabled: status.enabled,
+            verified: status.verified,
+            checksum: status.checksum,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{message, Severity};
+
+    #[test]
+    fn test_downgrade() {
+        let response = crate::ControlResponse::Firmware {
+            success: true,
+            repairs: 2,
+            restarts: 1,
+            repairs_failed: 1,
+            events: vec![crate::Event::with_severity(
+                5,
+                b"error".to_vec(),
+                Severity::Error,
+            )],
+            modules: vec![crate::ModuleStatus::with_code(true, true, 0x1234, 9, 16)],
+            field_repairs: vec![],
+        };
+        let legacy = ControlResponse::downgrade(&response).expect("downgrade");
+        assert_eq!(
+            legacy,
+            ControlResponse::Firmware {
+                success: true,
+                repairs: 2,
+                restarts: 1,
+                events: vec![Event {
+                    timestamp: 5,
+                    message: b"error".to_vec(),
+                }],
+                modules: vec![ModuleStatus {
+                    enabled: true,
+                    verified: true,
+                    checksum: 0x1234,
+                }],
+            }
+        );
+        let data = message::encode(&legacy).expect("encode");
+        assert_eq!(
+            message::decode::<ControlResponse>(&data).expect("decode"),
+            legacy
+        );
+
+        // Responses added since have no legacy layout
+        let response = crate::ControlResponse::Subscribe { success: true };
+        assert_eq!(ControlResponse::downgrade(&response), None);
+        let response = crate::ControlResponse::NoOp.tag(Some(1));
+        assert_eq!(ControlResponse::downgrade(&response), None);
+    }
+
+    #[test]
+    fn test_event_upgrade() {
+        let event = Event {
+            timestamp: 1,
+            message: b"r".to_vec(),
+        };
+        let data = bincode::serialize(&event).expect("encode");
+
+        // Bincode has no field names to default the severity by, so the old layout decodes as a
+        // legacy event and is upgraded from there
+        assert!(bincode::deserialize::<crate::Event>(&data).is_err());
+        let event: crate::Event = bincode::deserialize::<Event>(&data).expect("decode").into();
+        assert_eq!(event, crate::Event::new(1, b"r".to_vec()));
+        assert_eq!(event.severity, Severity::Info);
+        assert_eq!(
+            Event::from(&event),
+            bincode::deserialize::<Event>(&data).expect("decode")
+        );
+    }
+}
@@ -0,0 +1,316 @@
+//! Message encoding.
+//!
+//! Messages are bincode by default. Building with the `json` feature switches every channel to
+//! serde_json so the wire can be read with standard tools; both ends must be built the same way,
+//! and non-finite floats do not survive the JSON encoding.
+
+use serde::{Deserialize, Serialize};
+use std::io::{Error, ErrorKind, Result, Write};
+
+/// Control protocol version, bumped whenever the message layout changes.
+pub const PROTOCOL_VERSION: u32 = 9;
+/// Control protocol version of peers that open without a hello, which predate it, and the oldest
+/// one served.  They are sent responses in the `legacy` layouts and refused newer requests.
+///
+/// Every version since has changed a response layout, so a peer that says hello must speak
+/// `PROTOCOL_VERSION` itself.
+pub const LEGACY_PROTOCOL_VERSION: u32 = 0;
+
+#[cfg(not(feature = "json"))]
+use binary as codec;
+#[cfg(feature = "json")]
+use json as codec;
+
+/// Encode a message.
+pub fn encode<T: Serialize>(message: &T) -> Result<Vec<u8>> {
+    let mut buffer = vec![];
+    encode_into(&mut buffer, message)?;
+    Ok(buffer)
+}
+
+/// Encode a message into a writer.
+pub fn encode_into<W: Write, T: Serialize>(writer: W, message: &T) -> Result<()> {
+    codec::encode_into(writer, message)
+}
+
+/// Decode a message, reading no more than the bytes it was framed in.
+pub fn decode<'a, T: Deserialize<'a>>(data: &'a [u8]) -> Result<T> {
+    decode_limited(data, data.len())
+}
+
+/// Decode a message, refusing any length field that would read more than `limit` bytes so a
+/// small frame cannot claim a huge allocation.
+pub fn decode_limited<'a, T: Deserialize<'a>>(data: &'a [u8], limit: usize) -> Result<T> {
+    codec::decode(data, limit)
+}
+
+/// Report an encoding failure as invalid data.
+fn invalid<E: std::fmt::Display>(e: E) -> Error {
+    Error::new(ErrorKind::InvalidData, e.to_string())
+}
+
+mod binary {
+    use super::*;
+    use bincode::Options;
+
+    pub fn encode_into<W: Write, T: Serialize>(writer: W, message: &T) -> Result<()> {
+        bincode::serialize_into(writer, message).map_err(invalid)
+    }
+
+    pub fn decode<'a, T: Deserialize<'a>>(data: &'a [u8], limit: usize) -> Result<T> {
+        // Same layout as `bincode::deserialize`, but `Options::deserialize` drops the limit for
+        // slices, so drive the deserializer directly
+        let options = bincode::options()
+            .with_fixint_encoding()
+            .with_limit(limit as u64);
+        T::deserialize(&mut bincode::Deserializer::from_slice(data, options)).map_err(invalid)
+    }
+}
+
+#[cfg(any(feature = "json", test))]
+mod json {
+    use super::*;
+
+    pub fn encode_into<W: Write, T: Serialize>(writer: W, message: &T) -> Result<()> {
+        serde_json::to_writer(writer, message).map_err(invalid)
+    }
+
+    pub fn decode<'a, T: Deserialize<'a>>(data: &'a [u8], limit: usize) -> Result<T> {
+        // Lengths are implicit in JSON, so nothing is allocated beyond the input itself
+        if data.len() > limit {
+            return Err(invalid(format!("message exceeds {} bytes", limit)));
+        }
+        serde_json::from_slice(data).map_err(invalid)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::control_requests;
+    use crate::*;
+    use std::fmt::Debug;
+
+    fn round_trip<T>(message: &T)
+    where
+        T: Serialize + for<'a> Deserialize<'a> + PartialEq + Debug,
+    {
+        let mut data = vec![];
+        binary::encode_into(&mut data, message).expect("encode bincode");
+        assert_eq!(
+            &binary::decode::<T>(&data, data.len()).expect("decode bincode"),
+            message
+        );
+
+        let mut data = vec![];
+        json::encode_into(&mut data, message).expect("encode json");
+        assert_eq!(
+            &json::decode::<T>(&data, data.len()).expect("decode json"),
+            message
+        );
+
+        assert_eq!(
+            &decode::<T>(&encode(message).expect("encode")).expect("decode"),
+            message
+        );
+    }
+
+    fn burn() -> Burn {
+        Burn::new(1_620_000_000, 10, 0.5, (1.0, 0.0, -1.0)).expect("burn")
+    }
+
+    #[test]
+    fn test_control_round_trip() {
+        let requests = control_requests();
+        for request in &requests {
+            round_trip(request);
+            round_trip(&request.to_failure());
+        }
+        round_trip(&ControlResponse::Custom {
+            data: vec![0, 1, 255],
+        });
+        round_trip(&ControlRequest::Maneuver {
+            burns: vec![burn()],
+        });
+        round_trip(&ControlResponse::Firmware {
+            success: true,
+            repairs: 2,
+            restarts: 1,
+            repairs_failed: 1,
+            events: vec![
+                Event::new(1, b"event".to_vec()),
+                Event::with_severity(2, b"error".to_vec(), Severity::Error),
+            ],
+            modules: vec![ModuleStatus::with_code(
+                true,
+                false,
+                0x1234,
+                1_620_000_000,
+                16,
+            )],
+            field_repairs: vec![FieldRepairs::new("fuel".to_string(), 3, 4)],
+        });
+    }
+
+    #[test]
+    fn test_event_severity_default() {
+        let data = br#"{"timestamp":1,"message":[114]}"#;
+        let event: Event = json::decode(data, data.len()).expect("decode");
+        assert_eq!(event, Event::new(1, b"r".to_vec()));
+        assert_eq!(event.severity, Severity::Info);
+    }
+
+    #[test]
+    fn test_oversized_length_rejected() {
+        // A module update claiming far more bytes than the frame holds
+        let request = ControlRequest::UpdateModule {
+            id: 0,
+            module: vec![0; 16],
+            signature: vec![],
+            encoded: false,
+        };
+        let mut data = vec![];
+        binary::encode_into(&mut data, &request).expect("encode");
+        data[5..13].copy_from_slice(&(u64::MAX / 2).to_le_bytes());
+        assert!(binary::decode::<ControlRequest>(&data, data.len()).is_err());
+
+        // A maneuver claiming billions of burns
+        let mut data = vec![];
+        binary::encode_into(
+            &mut data,
+            &ControlRequest::Maneuver {
+                burns: vec![burn()],
+            },
+        )
+        .expect("encode");
+        data[4..12].copy_from_slice(&(1u64 << 40).to_le_bytes());
+        assert!(binary::decode::<ControlRequest>(&data, data.len()).is_err());
+
+        // Messages within the limit still decode, and a tighter limit is enforced
+        let request = ControlRequest::Maneuver {
+            burns: vec![burn()],
+        };
+        let data = encode(&request).expect("encode");
+        assert_eq!(decode::<ControlRequest>(&data).expect("decode"), request);
+        let e = binary::decode::<ControlRequest>(&data, 8).expect_err("limit");
+        assert!(e.to_string().contains("size limit"), "{}", e);
+    }
+
+    #[test]
+    fn test_executive_round_trip() {
+        let requests = vec![
+            ExecutiveRequest::Checkpoint {
+                state: vec![1, 2, 3],
+            },
+            ExecutiveRequest::PositionVelocity,
+            ExecutiveRequest::KeplerianElements,
+            ExecutiveRequest::Sensors,
+            ExecutiveRequest::Maneuver {
+                burns: vec![burn()],
+            },
+            ExecutiveRequest::SensorHistory { since: 5 },
+            ExecutiveRequest::ManeuverHistory,
+            ExecutiveRequest::Telemetry,
+            ExecutiveRequest::AbortManeuver,
+            ExecutiveRequest::OrbitSummary,
+            ExecutiveRequest::NextPass {
+                station_lat: 0.0,
+                station_lon: 0.0,
+                min_elevation: 10.0,
+            },
+        ];
+        for request in &requests {
+            // Adding a request variant fails to compile here until it is listed above
+            match request {
+                ExecutiveRequest::Checkpoint { .. }
+                | ExecutiveRequest::PositionVelocity
+                | ExecutiveRequest::KeplerianElements
+                | ExecutiveRequest::Sensors
+                | ExecutiveRequest::Maneuver { .. }
+                | ExecutiveRequest::SensorHistory { .. }
+                | ExecutiveRequest::ManeuverHistory
+                | ExecutiveRequest::Telemetry
+                | ExecutiveRequest::AbortManeuver
+                | ExecutiveRequest::OrbitSummary
+                | ExecutiveRequest::NextPass { .. } => round_trip(request),
+            }
+        }
+
+        let responses = vec![
+            ExecutiveResponse::Checkpoint { success: true },
+            ExecutiveResponse::PositionVelocity {
+                success: true,
+                t: 1,
+                p: (7000.0, 1.0, 2.0),
+                v: (3.0, 7.5, 4.0),
+            },
+            ExecutiveResponse::KeplerianElements {
+                success: true,
+                dt: 1,
+                sma: 7000.0,
+                ecc: 0.01,
+                inc: 51.6,
+                raan: 30.0,
+                aop: 10.0,
+                ta: 20.0,
+            },
+            ExecutiveResponse::Sensors {
+                success: true,
+                fuel: 19.5,
+                radiation: 0.25,
+                eclipse: Eclipse::Penumbra,
+            },
+            ExecutiveResponse::Maneuver {
+                success: true,
+                last_burn_fuel: Some(1.5),
+            },
+            ExecutiveResponse::SensorHistory {
+                success: true,
+                samples: vec![SensorSample::new(1, 19.5, 0.25)],
+            },
+            ExecutiveResponse::ManeuverHistory {
+                success: true,
+                maneuvers: vec![ManeuverRecord::new(1, vec![burn()])],
+            },
+            ExecutiveResponse::Telemetry {
+                success: true,
+                t: 1,
+                p: (7000.0, 1.0, 2.0),
+                v: (3.0, 7.5, 4.0),
+                fuel: 19.5,
+                radiation: 0.25,
+                eclipse: Eclipse::Umbra,
+            },
+            ExecutiveResponse::AbortManeuver {
+                success: true,
+                aborted: true,
+            },
+            ExecutiveResponse::OrbitSummary {
+                success: true,
+                periapsis_altitude: 551.8,
+                apoapsis_altitude: 691.8,
+                period: 5828.5,
+                periapsis_unsafe: false,
+            },
+            ExecutiveResponse::NextPass {
+                success: true,
+                pass: Some(Pass::new(1_620_000_000, 1_620_000_600, 85.0)),
+            },
+        ];
+        for response in &responses {
+            match response {
+                ExecutiveResponse::Checkpoint { .. }
+                | ExecutiveResponse::PositionVelocity { .. }
+                | ExecutiveResponse::KeplerianElements { .. }
+                | ExecutiveResponse::Sensors { .. }
+                | ExecutiveResponse::Maneuver { .. }
+                | ExecutiveResponse::SensorHistory { .. }
+                | ExecutiveResponse::ManeuverHistory { .. }
+                | ExecutiveResponse::Telemetry { .. }
+                | ExecutiveResponse::AbortManeuver { .. }
+                | ExecutiveResponse::OrbitSummary { .. }
+                | ExecutiveResponse::NextPass { .. } => round_trip(response),
+            }
+        }
+    }
+}
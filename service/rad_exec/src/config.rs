@@ -0,0 +1,250 @@
+//! Executive configuration.
+
+use crate::orbit::orbit_summary;
+use crate::{MAX_ALTITUDE, MIN_ALTITUDE};
+use anyhow::{anyhow, Context, Result};
+use nyx::celestia::{Frame, State};
+use nyx::time::Epoch;
+use serde::Deserialize;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Read a configuration value from the environment, falling back to a default.
+pub fn env_or<T>(name: &str, default: T) -> Result<T>
+where
+    T: FromStr,
+    T::Err: std::fmt::Display,
+{
+    match std::env::var(name) {
+        Ok(value) => value
+            .parse()
+            .map_err(|e| anyhow!("invalid {}={}: {}", name, value, e)),
+        Err(_) => Ok(default),
+    }
+}
+
+/// Read a comma-separated list from the environment, falling back to a default.
+pub fn env_list_or<T>(name: &str, default: Vec<T>) -> Result<Vec<T>>
+where
+    T: FromStr,
+    T::Err: std::fmt::Display,
+{
+    match std::env::var(name) {
+        Ok(value) => value
+            .split(',')
+            .filter(|x| !x.trim().is_empty())
+            .map(|x| {
+                x.trim()
+                    .parse()
+                    .map_err(|e| anyhow!("invalid {}={}: {}", name, value, e))
+            })
+            .collect(),
+        Err(_) => Ok(default),
+    }
+}
+
+/// Initial spacecraft orbit, loaded from the TOML file named by RAD_ORBIT.
+///
+/// ```toml
+/// type = "keplerian"
+/// sma = 42164.0
+/// ecc = 0.2
+/// inc = 26.0179
+/// raan = 93.9503
+/// aop = 22.5731
+/// ta = 356.6008
+/// ```
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum InitialOrbit {
+    /// Position over a point on the surface (deg, deg, km).
+    Geodesic {
+        latitude: f64,
+        longitude: f64,
+        altitude: f64,
+    },
+    /// Keplerian elements (km, deg).
+    Keplerian {
+        sma: f64,
+        ecc: f64,
+        inc: f64,
+        raan: f64,
+        aop: f64,
+        ta: f64,
+    },
+}
+
+impl Default for InitialOrbit {
+    /// High radiation orbit.
+    fn default() -> Self {
+        InitialOrbit::Geodesic {
+            latitude: 0.0,
+            longitude: 0.0,
+            altitude: 6000.0,
+        }
+    }
+}
+
+impl InitialOrbit {
+    /// Load the initial orbit from RAD_ORBIT if set, or use the default.
+    pub fn from_env() -> Result<Self> {
+        match std::env::var_os("RAD_ORBIT") {
+            Some(path) => Self::load(Path::new(&path)),
+            None => Ok(Self::default()),
+        }
+    }
+
+    /// Load an initial orbit file.
+    pub fn load(path: &Path) -> Result<Self> {
+        let data = std::fs::read_to_string(path)
+            .with_context(|| format!("read orbit {}", path.display()))?;
+        toml::from_str(&data).with_context(|| format!("parse orbit {}", path.display()))
+    }
+
+    /// Build the orbit state, checking it stays within the altitude limits.
+    pub fn state(&self, dt: Epoch, frame: Frame) -> Result<State> {
+        match *self {
+            InitialOrbit::Geodesic {
+                latitude,
+                longitude,
+                altitude,
+            } => {
+                check_altitude("altitude", altitude)?;
+                Ok(State::from_geodesic(
+                    latitude, longitude, altitude, dt, frame,
+                ))
+            }
+            InitialOrbit::Keplerian {
+                sma,
+                ecc,
+                inc,
+                raan,
+                aop,
+                ta,
+            } => {
+                if !(0.0..1.0).contains(&ecc) {
+                    return Err(anyhow!("eccentricity {} is not a closed orbit", ecc));
+                }
+                let (periapsis, apoapsis, _) =
+                    orbit_summary(sma, ecc, frame.gm(), frame.equatorial_radius());
+                check_altitude("periapsis altitude", periapsis)?;
+                check_altitude("apoapsis altitude", apoapsis)?;
+                Ok(State::keplerian(sma, ecc, inc, raan, aop, ta, dt, frame))
+            }
+        }
+    }
+}
+
+/// Check an altitude (km) lies between the crash and lost contact limits.
+fn check_altitude(name: &str, altitude: f64) -> Result<()> {
+    if (MIN_ALTITUDE..=MAX_ALTITUDE).contains(&altitude) {
+        Ok(())
+    } else {
+        Err(anyhow!(
+            "{} {} km outside {}..={} km",
+            name,
+            altitude,
+            MIN_ALTITUDE,
+            MAX_ALTITUDE
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nyx::celestia::Cosm;
+
+    const GEODESIC: &str = r#"
+        type = "geodesic"
+        latitude = 42.3601
+        longitude = 71.0589
+        altitude = 16384.0
+    "#;
+
+    const KEPLERIAN: &str = r#"
+        type = "keplerian"
+        sma = 42164.0
+        ecc = 0.2
+        inc = 26.0179
+        raan = 93.9503
+        aop = 22.5731
+        ta = 356.6008
+    "#;
+
+    #[test]
+    fn test_env_list_or() {
+        std::env::set_var("RAD_TEST_LIST", "25, 10,");
+        assert_eq!(
+            env_list_or("RAD_TEST_LIST", vec![1.0]).expect("list"),
+            vec![25.0, 10.0]
+        );
+        std::env::set_var("RAD_TEST_LIST", "25,low");
+        assert!(env_list_or::<f64>("RAD_TEST_LIST", vec![]).is_err());
+        std::env::remove_var("RAD_TEST_LIST");
+        assert_eq!(
+            env_list_or("RAD_TEST_LIST", vec![1.0]).expect("default"),
+            vec![1.0]
+        );
+    }
+
+    #[test]
+    fn test_parse_initial_orbit() {
+        assert_eq!(
+            toml::from_str::<InitialOrbit>(GEODESIC).expect("geodesic"),
+            InitialOrbit::Geodesic {
+                latitude: 42.3601,
+                longitude: 71.0589,
+                altitude: 16384.0,
+            }
+        );
+        assert_eq!(
+            toml::from_str::<InitialOrbit>(KEPLERIAN).expect("keplerian"),
+            InitialOrbit::Keplerian {
+                sma: 42164.0,
+                ecc: 0.2,
+                inc: 26.0179,
+                raan: 93.9503,
+                aop: 22.5731,
+                ta: 356.6008,
+            }
+        );
+        assert!(toml::from_str::<InitialOrbit>("type = \"cartesian\"").is_err());
+    }
+
+    #[test]
+    fn test_initial_orbit_state() {
+        let cosm = Cosm::from_xb(concat!(env!("CARGO_MANIFEST_DIR"), "/../data/de438s"));
+        let eme2k = cosm.frame("EME2000");
+        let dt = Epoch::from_gregorian_utc(2021, 4, 30, 0, 0, 0, 0);
+
+        let orbit: InitialOrbit = toml::from_str(GEODESIC).expect("geodesic");
+        let state = orbit.state(dt, eme2k).expect("geodesic state");
+        assert!((state.geodetic_latitude() - 42.3601).abs() < 1e-6);
+        assert!((state.geodetic_longitude() - 71.0589).abs() < 1e-6);
+        assert!((state.geodetic_height() - 16384.0).abs() < 1e-6);
+
+        let orbit: InitialOrbit = toml::from_str(KEPLERIAN).expect("keplerian");
+        let state = orbit.state(dt, eme2k).expect("keplerian state");
+        assert!((state.sma() - 42164.0).abs() < 1e-3);
+        assert!((state.ecc() - 0.2).abs() < 1e-6);
+        assert!((state.inc() - 26.0179).abs() < 1e-6);
+        assert!((state.raan() - 93.9503).abs() < 1e-6);
+
+        let low = InitialOrbit::Geodesic {
+            latitude: 0.0,
+            longitude: 0.0,
+            altitude: 10.0,
+        };
+        assert!(low.state(dt, eme2k).is_err());
+        let escape = InitialOrbit::Keplerian {
+            sma: 7000.0,
+            ecc: 1.5,
+            inc: 0.0,
+            raan: 0.0,
+            aop: 0.0,
+            ta: 0.0,
+        };
+        assert!(escape.state(dt, eme2k).is_err());
+    }
+}
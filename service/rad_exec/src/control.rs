@@ -2,14 +2,29 @@
 
 use crate::CONTROL_PORT;
 use anyhow::{anyhow, Context, Result};
-use rad_common::{ControlRequest, ControlResponse, COMMAND_PATH};
+use rad_common::frame::{read_framed, MAX_FRAME_SIZE};
+use rad_common::message::{self, LEGACY_PROTOCOL_VERSION, PROTOCOL_VERSION};
+use rad_common::{legacy, ControlRequest, ControlResponse, COMMAND_PATH};
 use std::net::SocketAddr;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::io::AsyncWriteExt;
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
 use tokio::net::{TcpListener, TcpStream, UnixStream};
-use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::sync::mpsc::{channel, Receiver, Sender};
+use tokio::time::{interval, sleep_until, timeout, Instant, Interval};
+use tokio_util::sync::CancellationToken;
+
+/// Longest telemetry subscription push interval (sec).
+const MAX_SUBSCRIBE_INTERVAL: u64 = 3600;
+/// Time a subscriber has to accept a pushed frame before it is disconnected (sec).
+const PUSH_TIMEOUT: u64 = 10;
+/// Seconds an open connection may keep running after shutdown, inside the task shutdown timeout
+const DRAIN_TIMEOUT: u64 = 3;
 
 /// Process ground control connections.
 pub async fn process_connections(
+    shutdown: &CancellationToken,
+    idle_timeout: Duration,
     tx_requests: &Sender<ControlRequest>,
     rx_responses: &mut Receiver<ControlResponse>,
 ) -> Result<()> {
@@ -20,8 +35,23 @@ pub async fn process_connections(
     );
     let listener = TcpListener::bind(server_address).await?;
     loop {
-        let (socket, address) = listener.accept().await?;
-        if let Err(e) = process_connection(socket, address, tx_requests, rx_responses).await {
+        let (socket, address) = tokio::select! {
+            _ = shutdown.cancelled() => return Ok(()),
+            accepted = listener.accept() => accepted?,
+        };
+        let connection =
+            process_connection(socket, address, idle_timeout, tx_requests, rx_responses);
+        tokio::pin!(connection);
+        let result = tokio::select! {
+            result = &mut connection => result,
+            _ = shutdown.cancelled() => {
+                // Let the current exchange finish, but not an open-ended subscription
+                timeout(Duration::from_secs(DRAIN_TIMEOUT), connection)
+                    .await
+                    .unwrap_or_else(|_| Err(anyhow!("connection still open at shutdown")))
+            }
+        };
+        if let Err(e) = result {
             error!("[{}] service control connection: {}", address, e);
         }
     }
@@ -29,23 +59,115 @@ pub async fn process_connections(
 
 /// Process a ground control connection.
 async fn process_connection(
-    mut socket: TcpStream,
+    socket: TcpStream,
     address: SocketAddr,
+    idle_timeout: Duration,
     tx_requests: &Sender<ControlRequest>,
     rx_responses: &mut Receiver<ControlResponse>,
 ) -> Result<()> {
     info!("[{}] processing ground control connection", address);
 
+    // Read on a separate task so a request is never torn by a telemetry push
+    let (reader, mut writer) = socket.into_split();
+    let (tx_incoming, mut rx_incoming) = channel(1);
+    let reader = tokio::spawn(read_requests(reader, tx_incoming));
+    let result = serve_requests(
+        &mut rx_incoming,
+        &mut writer,
+        address,
+        idle_timeout,
+        tx_requests,
+        rx_responses,
+    )
+    .await;
+    // Stop reading even if the client holds its end open
+    reader.abort();
+    result
+}
+
+/// Read and decode requests until the connection fails or the receiver goes away.
+async fn read_requests(mut reader: OwnedReadHalf, tx_incoming: Sender<Result<ControlRequest>>) {
+    let mut buffer = vec![];
+    loop {
+        let request = match read_framed(&mut reader, &mut buffer, MAX_FRAME_SIZE).await {
+            Ok(()) => message::decode(&buffer).context("decode request"),
+            Err(e) => Err(anyhow!(e).context("receive request")),
+        };
+        let failed = request.is_err();
+        if tx_incoming.send(request).await.is_err() || failed {
+            return;
+        }
+    }
+}
+
+/// Answer requests and push subscribed telemetry until the client disconnects, or goes idle
+/// without a subscription.
+async fn serve_requests(
+    rx_incoming: &mut Receiver<Result<ControlRequest>>,
+    writer: &mut OwnedWriteHalf,
+    address: SocketAddr,
+    idle_timeout: Duration,
+    tx_requests: &Sender<ControlRequest>,
+    rx_responses: &mut Receiver<ControlResponse>,
+) -> Result<()> {
+    let mut buffer = vec![];
     let mut disconnect = false;
+    let mut mismatch = None;
+    let mut negotiated = None;
+    let mut subscription: Option<(Option<u64>, Interval)> = None;
+    let mut idle_deadline = Instant::now() + idle_timeout;
     while !disconnect {
-        let size = socket.read_u32().await.context("receive request size")?;
-        let mut buffer = vec![0u8; size as _];
-        socket
-            .read_exact(&mut buffer)
-            .await
-            .context("receive request")?;
-        let request: ControlRequest = bincode::deserialize(&buffer).context("decode request")?;
-        debug!("control request: {}", request);
+        let request = tokio::select! {
+            request = rx_incoming.recv() => {
+                request.ok_or_else(|| anyhow!("receive request"))??
+            }
+            _ = sleep_until(idle_deadline), if subscription.is_none() => {
+                info!(
+                    "[{}] ground control idle for {}s, closing",
+                    address,
+                    idle_timeout.as_secs()
+                );
+                return Ok(());
+            }
+            request_id = next_push(&mut subscription) => {
+                let response = proxy_request(tx_requests, rx_responses, ControlRequest::Telemetry)
+                    .await
+                    .unwrap_or_else(|_| ControlRequest::Telemetry.to_failure())
+                    .tag(request_id);
+                let version = negotiated.unwrap_or(PROTOCOL_VERSION);
+                let push = write_response(writer, &mut buffer, version, &response);
+                timeout(Duration::from_secs(PUSH_TIMEOUT), push)
+                    .await
+                    .map_err(|_| anyhow!("subscriber stopped reading telemetry"))??;
+                continue;
+            }
+        };
+        idle_deadline = Instant::now() + idle_timeout;
+        let request_version = request.protocol_version();
+        let (request_id, request) = request.untag();
+        match request_id {
+            Some(request_id) => debug!("control request #{}: {}", request_id, request),
+            None => debug!("control request: {}", request),
+        }
+
+        // Peers that open without a hello predate it
+        if negotiated.is_none() && !matches!(request, ControlRequest::Hello { .. }) {
+            info!(
+                "[{}] no hello, speaking legacy protocol version {}",
+                address, LEGACY_PROTOCOL_VERSION
+            );
+            negotiated = Some(LEGACY_PROTOCOL_VERSION);
+        }
+
+        // Only legacy peers can be behind, and they could not decode a reply to a newer request
+        let peer_version = negotiated.unwrap_or(PROTOCOL_VERSION);
+        if request_version > peer_version {
+            return Err(anyhow!(
+                "{} request is newer than protocol version {}",
+                request,
+                peer_version
+            ));
+        }
 
         let failure_response = request.to_failure();
         let response = match request {
@@ -54,44 +176,72 @@ async fn process_connection(
                 authenticated: true,
                 connected: true,
             },
-            ControlRequest::Reset => ControlResponse::Reset { success: false },
-            ControlRequest::Firmware => proxy_request(tx_requests, rx_responses, request)
-                .await
-                .unwrap_or(failure_response),
-            ControlRequest::PositionVelocity => proxy_request(tx_requests, rx_responses, request)
-                .await
-                .unwrap_or(failure_response),
-            ControlRequest::KeplerianElements => proxy_request(tx_requests, rx_responses, request)
-                .await
-                .unwrap_or(failure_response),
-            ControlRequest::Sensors => proxy_request(tx_requests, rx_responses, request)
-                .await
-                .unwrap_or(failure_response),
-            ControlRequest::EnableModule { .. } => {
-                proxy_request(tx_requests, rx_responses, request)
-                    .await
-                    .unwrap_or(failure_response)
-            }
-            ControlRequest::UpdateModule { .. } => {
-                proxy_request(tx_requests, rx_responses, request)
+            ControlRequest::Ping { nonce } => ControlResponse::Pong {
+                nonce,
+                server_time: SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map(|x| x.as_millis() as u64)
+                    .unwrap_or(0),
+            },
+            ControlRequest::Firmware
+            | ControlRequest::PositionVelocity
+            | ControlRequest::KeplerianElements
+            | ControlRequest::OrbitSummary
+            | ControlRequest::NextPass { .. }
+            | ControlRequest::Sensors
+            | ControlRequest::EnableModule { .. }
+            | ControlRequest::UpdateModule { .. }
+            | ControlRequest::Maneuver { .. }
+            | ControlRequest::SensorHistory { .. }
+            | ControlRequest::ManeuverHistory
+            | ControlRequest::Diagnostics
+            | ControlRequest::Capabilities
+            | ControlRequest::ModuleBudget { .. }
+            | ControlRequest::ModuleOutput { .. }
+            | ControlRequest::Telemetry
+            | ControlRequest::AbortManeuver
+            | ControlRequest::Reset => {
+                proxy_request(tx_requests, rx_responses, request.tag(request_id))
                     .await
+                    .map(|response| response.untag().1)
                     .unwrap_or(failure_response)
             }
-            ControlRequest::Maneuver { .. } => proxy_request(tx_requests, rx_responses, request)
-                .await
-                .unwrap_or(failure_response),
             ControlRequest::Disconnect => {
                 disconnect = true;
                 ControlResponse::Disconnect
             }
+            ControlRequest::Hello { version, .. } => {
+                if version == PROTOCOL_VERSION {
+                    negotiated = Some(version);
+                } else {
+                    mismatch = Some(version);
+                }
+                ControlResponse::Hello {
+                    version: PROTOCOL_VERSION,
+                    accepted: mismatch.is_none(),
+                }
+            }
+            ControlRequest::Subscribe { interval_secs } => {
+                if (1..=MAX_SUBSCRIBE_INTERVAL).contains(&interval_secs) {
+                    info!("[{}] pushing telemetry every {}s", address, interval_secs);
+                    let ticker = interval(Duration::from_secs(interval_secs));
+                    subscription = Some((request_id, ticker));
+                    ControlResponse::Subscribe { success: true }
+                } else {
+                    failure_response
+                }
+            }
+            ControlRequest::Tagged { .. } => return Err(anyhow!("nested request ID")),
         };
+        write_response(writer, &mut buffer, peer_version, &response.tag(request_id)).await?;
 
-        let buffer = bincode::serialize(&response).context("encode response")?;
-        socket
-            .write_u32(buffer.len() as _)
-            .await
-            .context("send response size")?;
-        socket.write_all(&buffer).await.context("send response")?;
+        if let Some(version) = mismatch {
+            return Err(anyhow!(
+                "protocol version mismatch: peer speaks {}, expected {}",
+                version,
+                PROTOCOL_VERSION
+            ));
+        }
     }
 
     if disconnect {
@@ -101,6 +251,42 @@ async fn process_connection(
     Ok(())
 }
 
+/// Wait for the next telemetry push, returning the subscription's request ID, or forever if there
+/// is no subscription.
+async fn next_push(subscription: &mut Option<(Option<u64>, Interval)>) -> Option<u64> {
+    match subscription {
+        Some((request_id, ticker)) => {
+            ticker.tick().await;
+            *request_id
+        }
+        None => std::future::pending().await,
+    }
+}
+
+/// Send a response in the layout of the peer's protocol version.
+async fn write_response(
+    writer: &mut OwnedWriteHalf,
+    buffer: &mut Vec<u8>,
+    version: u32,
+    response: &ControlResponse,
+) -> Result<()> {
+    buffer.clear();
+    if version == LEGACY_PROTOCOL_VERSION {
+        let response = legacy::ControlResponse::downgrade(response)
+            .ok_or_else(|| anyhow!("{} response has no legacy layout", response))?;
+        message::encode_into(&mut *buffer, &response)
+    } else {
+        message::encode_into(&mut *buffer, response)
+    }
+    .context("encode response")?;
+    writer
+        .write_u32(buffer.len() as _)
+        .await
+        .context("send response size")?;
+    writer.write_all(buffer).await.context("send response")?;
+    Ok(())
+}
+
 /// Proxy a request.
 async fn proxy_request<Request, Response>(
     tx_requests: &Sender<Request>,
@@ -124,11 +310,8 @@ pub async fn proxy_requests_to_firmware(
 ) -> Result<()> {
     info!("proxying control requests to {}", COMMAND_PATH);
 
-    loop {
-        let request = rx_requests
-            .recv()
-            .await
-            .ok_or_else(|| anyhow!("sender closed"))?;
+    // Runs until the control side closes its channels
+    while let Some(request) = rx_requests.recv().await {
         let response = match proxy_request_to_firmware(&request).await {
             Ok(response) => response,
             Err(e) => {
@@ -136,11 +319,11 @@ pub async fn proxy_requests_to_firmware(
                 request.to_failure()
             }
         };
-        tx_responses
-            .send(response)
-            .await
-            .map_err(|_| anyhow!("receiver closed"))?;
+        if tx_responses.send(response).await.is_err() {
+            break;
+        }
     }
+    Ok(())
 }
 
 /// Proxy a request to firmware.
@@ -148,7 +331,7 @@ async fn proxy_request_to_firmware(request: &ControlRequest) -> Result<ControlRe
     let mut socket = UnixStream::connect(COMMAND_PATH)
         .await
         .context("connect to control socket")?;
-    let buffer = bincode::serialize(request).context("encode control request")?;
+    let mut buffer = message::encode(request).context("encode control request")?;
     socket
         .write_u32(buffer.len() as _)
         .await
@@ -157,16 +340,397 @@ async fn proxy_request_to_firmware(request: &ControlRequest) -> Result<ControlRe
         .write_all(&buffer)
         .await
         .context("proxy control request")?;
-    let size = socket
-        .read_u32()
-        .await
-        .context("proxy control response length")?;
-    let mut buffer = vec![0u8; size as _];
-    socket
-        .read_exact(&mut buffer)
+    read_framed(&mut socket, &mut buffer, MAX_FRAME_SIZE)
         .await
         .context("proxy control response")?;
-    let response: ControlResponse =
-        bincode::deserialize(&buffer).context("decode control response")?;
+    let response: ControlResponse = message::decode(&buffer).context("decode control response")?;
     Ok(response)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use tokio::io::AsyncReadExt;
+
+    const IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+    async fn write_request(socket: &mut TcpStream, request: &ControlRequest) {
+        let buffer = message::encode(request).expect("encode");
+        socket
+            .write_u32(buffer.len() as _)
+            .await
+            .expect("write length");
+        socket.write_all(&buffer).await.expect("write request");
+    }
+
+    #[tokio::test]
+    async fn test_pipelined_requests() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let mut client = TcpStream::connect(listener.local_addr().expect("address"))
+            .await
+            .expect("connect");
+        let (server, address) = listener.accept().await.expect("accept");
+
+        // Firmware stand-in answering every proxied request with its failure response
+        let (tx_requests, mut rx_requests) = channel::<ControlRequest>(8);
+        let (tx_responses, mut rx_responses) = channel(8);
+        tokio::spawn(async move {
+            while let Some(request) = rx_requests.recv().await {
+                let _ = tx_responses.send(request.to_failure()).await;
+            }
+        });
+        let connection = tokio::spawn(async move {
+            process_connection(
+                server,
+                address,
+                IDLE_TIMEOUT,
+                &tx_requests,
+                &mut rx_responses,
+            )
+            .await
+        });
+
+        greet(&mut client).await;
+        let requests = vec![
+            (3, ControlRequest::NoOp),
+            (1, ControlRequest::Firmware),
+            (2, ControlRequest::Sensors),
+            (4, ControlRequest::Ping { nonce: 0x1337 }),
+        ];
+        for (request_id, request) in requests {
+            write_request(&mut client, &request.tag(Some(request_id))).await;
+        }
+
+        let mut responses = HashMap::new();
+        for _ in 0..4 {
+            let size = client.read_u32().await.expect("read length");
+            let mut buffer = vec![0u8; size as _];
+            client.read_exact(&mut buffer).await.expect("read response");
+            let response: ControlResponse = message::decode(&buffer).expect("decode");
+            match response.untag() {
+                (Some(request_id), response) => {
+                    responses.insert(request_id, response);
+                }
+                (None, response) => panic!("untagged response {}", response),
+            }
+        }
+        assert_eq!(responses[&3], ControlResponse::NoOp);
+        assert_eq!(responses[&1], ControlRequest::Firmware.to_failure());
+        assert_eq!(responses[&2], ControlRequest::Sensors.to_failure());
+        match responses[&4] {
+            ControlResponse::Pong { nonce, server_time } => {
+                assert_eq!(nonce, 0x1337);
+                assert_ne!(server_time, 0);
+            }
+            ref response => panic!("unexpected response {}", response),
+        }
+
+        write_request(&mut client, &ControlRequest::Disconnect).await;
+        connection.await.expect("join").expect("process connection");
+    }
+
+    #[tokio::test]
+    async fn test_reset_forwarded() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let mut client = TcpStream::connect(listener.local_addr().expect("address"))
+            .await
+            .expect("connect");
+        let (server, address) = listener.accept().await.expect("accept");
+
+        // Firmware stand-in that accepts resets
+        let (tx_requests, mut rx_requests) = channel::<ControlRequest>(8);
+        let (tx_responses, mut rx_responses) = channel(8);
+        tokio::spawn(async move {
+            while let Some(request) = rx_requests.recv().await {
+                let response = match request.untag() {
+                    (request_id, ControlRequest::Reset) => {
+                        ControlResponse::Reset { success: true }.tag(request_id)
+                    }
+                    (_, request) => request.to_failure(),
+                };
+                let _ = tx_responses.send(response).await;
+            }
+        });
+        let connection = tokio::spawn(async move {
+            process_connection(
+                server,
+                address,
+                IDLE_TIMEOUT,
+                &tx_requests,
+                &mut rx_responses,
+            )
+            .await
+        });
+
+        greet(&mut client).await;
+        write_request(&mut client, &ControlRequest::Reset.tag(Some(5))).await;
+        assert_eq!(
+            read_response(&mut client).await.untag(),
+            (Some(5), ControlResponse::Reset { success: true })
+        );
+
+        write_request(&mut client, &ControlRequest::Disconnect).await;
+        connection.await.expect("join").expect("process connection");
+    }
+
+    async fn read_response(socket: &mut TcpStream) -> ControlResponse {
+        let mut buffer = vec![];
+        read_framed(socket, &mut buffer, MAX_FRAME_SIZE)
+            .await
+            .expect("read response");
+        message::decode(&buffer).expect("decode")
+    }
+
+    async fn read_legacy_response(socket: &mut TcpStream) -> legacy::ControlResponse {
+        let mut buffer = vec![];
+        read_framed(socket, &mut buffer, MAX_FRAME_SIZE)
+            .await
+            .expect("read response");
+        message::decode(&buffer).expect("decode")
+    }
+
+    /// Open a session with a matching hello.
+    async fn greet(socket: &mut TcpStream) {
+        let hello = ControlRequest::Hello {
+            version: PROTOCOL_VERSION,
+            key_version: 0,
+        };
+        write_request(socket, &hello).await;
+        assert_eq!(
+            read_response(socket).await,
+            ControlResponse::Hello {
+                version: PROTOCOL_VERSION,
+                accepted: true,
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_subscribe_telemetry() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let mut client = TcpStream::connect(listener.local_addr().expect("address"))
+            .await
+            .expect("connect");
+        let (server, address) = listener.accept().await.expect("accept");
+
+        let (tx_requests, mut rx_requests) = channel::<ControlRequest>(8);
+        let (tx_responses, mut rx_responses) = channel(8);
+        tokio::spawn(async move {
+            while let Some(request) = rx_requests.recv().await {
+                let _ = tx_responses.send(request.to_failure()).await;
+            }
+        });
+        let connection = tokio::spawn(async move {
+            process_connection(
+                server,
+                address,
+                IDLE_TIMEOUT,
+                &tx_requests,
+                &mut rx_responses,
+            )
+            .await
+        });
+
+        greet(&mut client).await;
+        let request = ControlRequest::Subscribe { interval_secs: 1 }.tag(Some(7));
+        write_request(&mut client, &request).await;
+        assert_eq!(
+            read_response(&mut client).await.untag(),
+            (Some(7), ControlResponse::Subscribe { success: true })
+        );
+
+        // Pushes arrive immediately and then once a second; only the first two are certain to land
+        // in the window on a loaded machine
+        let window = tokio::time::sleep(Duration::from_millis(2500));
+        tokio::pin!(window);
+        let mut pushed = 0;
+        loop {
+            tokio::select! {
+                _ = &mut window => break,
+                response = read_response(&mut client) => {
+                    assert_eq!(
+                        response.untag(),
+                        (Some(7), ControlRequest::Telemetry.to_failure())
+                    );
+                    pushed += 1;
+                }
+            }
+        }
+        assert!(pushed >= 2, "only {} telemetry pushes", pushed);
+
+        // Requests are still answered between pushes
+        write_request(&mut client, &ControlRequest::NoOp).await;
+        loop {
+            match read_response(&mut client).await {
+                ControlResponse::NoOp => break,
+                response => assert_eq!(response.untag().1, ControlRequest::Telemetry.to_failure()),
+            }
+        }
+
+        write_request(&mut client, &ControlRequest::Disconnect).await;
+        connection.await.expect("join").expect("process connection");
+    }
+
+    /// Send a hello with the given version and return the response and connection result.
+    async fn hello(version: u32) -> (ControlResponse, Result<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let mut client = TcpStream::connect(listener.local_addr().expect("address"))
+            .await
+            .expect("connect");
+        let (server, address) = listener.accept().await.expect("accept");
+        let (tx_requests, _rx_requests) = channel(8);
+        let (_tx_responses, mut rx_responses) = channel(8);
+        let connection = tokio::spawn(async move {
+            process_connection(
+                server,
+                address,
+                IDLE_TIMEOUT,
+                &tx_requests,
+                &mut rx_responses,
+            )
+            .await
+        });
+
+        let hello = ControlRequest::Hello {
+            version,
+            key_version: 0,
+        };
+        write_request(&mut client, &hello).await;
+        let size = client.read_u32().await.expect("read length");
+        let mut buffer = vec![0u8; size as _];
+        client.read_exact(&mut buffer).await.expect("read response");
+        let response = message::decode(&buffer).expect("decode");
+        if let ControlResponse::Hello { accepted: true, .. } = response {
+            write_request(&mut client, &ControlRequest::Disconnect).await;
+        }
+        (response, connection.await.expect("join"))
+    }
+
+    #[tokio::test]
+    async fn test_hello_version() {
+        let (response, result) = hello(PROTOCOL_VERSION).await;
+        assert_eq!(
+            response,
+            ControlResponse::Hello {
+                version: PROTOCOL_VERSION,
+                accepted: true,
+            }
+        );
+        result.expect("process connection");
+
+        for version in &[PROTOCOL_VERSION - 1, PROTOCOL_VERSION + 1] {
+            let (response, result) = hello(*version).await;
+            assert_eq!(
+                response,
+                ControlResponse::Hello {
+                    version: PROTOCOL_VERSION,
+                    accepted: false,
+                }
+            );
+            let e = result.expect_err("version mismatch");
+            assert!(e.to_string().contains("protocol version mismatch"));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_legacy_peer() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let mut client = TcpStream::connect(listener.local_addr().expect("address"))
+            .await
+            .expect("connect");
+        let (server, address) = listener.accept().await.expect("accept");
+        let (tx_requests, mut rx_requests) = channel::<ControlRequest>(8);
+        let (tx_responses, mut rx_responses) = channel(8);
+        tokio::spawn(async move {
+            while let Some(request) = rx_requests.recv().await {
+                let _ = tx_responses.send(request.to_failure()).await;
+            }
+        });
+        let connection = tokio::spawn(async move {
+            process_connection(
+                server,
+                address,
+                IDLE_TIMEOUT,
+                &tx_requests,
+                &mut rx_responses,
+            )
+            .await
+        });
+
+        // Without a hello, responses come back in the legacy layout
+        write_request(&mut client, &ControlRequest::NoOp).await;
+        assert_eq!(
+            read_legacy_response(&mut client).await,
+            legacy::ControlResponse::NoOp
+        );
+        write_request(&mut client, &ControlRequest::Firmware).await;
+        assert_eq!(
+            read_legacy_response(&mut client).await,
+            legacy::ControlResponse::Firmware {
+                success: false,
+                repairs: 0,
+                restarts: 0,
+                events: vec![],
+                modules: vec![],
+            }
+        );
+
+        // Requests added since the hello are refused
+        write_request(&mut client, &ControlRequest::Ping { nonce: 1 }).await;
+        let e = connection
+            .await
+            .expect("join")
+            .expect_err("newer request from legacy peer");
+        assert!(
+            e.to_string().contains("newer than protocol version 0"),
+            "{}",
+            e
+        );
+    }
+
+    #[tokio::test]
+    async fn test_idle_timeout() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let mut client = TcpStream::connect(listener.local_addr().expect("address"))
+            .await
+            .expect("connect");
+        let (server, address) = listener.accept().await.expect("accept");
+        let (tx_requests, _rx_requests) = channel(8);
+        let (_tx_responses, mut rx_responses) = channel(8);
+        let idle_timeout = Duration::from_millis(200);
+        let connection = tokio::spawn(async move {
+            process_connection(
+                server,
+                address,
+                idle_timeout,
+                &tx_requests,
+                &mut rx_responses,
+            )
+            .await
+        });
+
+        greet(&mut client).await;
+        let request = ControlRequest::Authenticate {
+            token: vec![],
+            nonce: vec![],
+        };
+        write_request(&mut client, &request).await;
+        assert_eq!(
+            read_response(&mut client).await,
+            ControlResponse::Authenticate {
+                authenticated: true,
+                connected: true,
+            }
+        );
+
+        // The silent client is dropped and the server closes its end
+        timeout(Duration::from_secs(5), connection)
+            .await
+            .expect("idle connection closed")
+            .expect("join")
+            .expect("process connection");
+        let mut buffer = [0u8; 1];
+        assert_eq!(client.read(&mut buffer).await.expect("read"), 0);
+    }
+}
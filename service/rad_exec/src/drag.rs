@@ -0,0 +1,43 @@
+//! Atmospheric drag.
+
+use nyx::celestia::State;
+use nyx::dimensions::Vector3;
+use nyx::dynamics::AccelModel;
+
+/// Reference altitude of the exponential atmosphere (km).
+const REFERENCE_ALTITUDE: f64 = 200.0;
+/// Atmospheric density at the reference altitude (kg/m^3).
+const REFERENCE_DENSITY: f64 = 2.789e-10;
+/// Density scale height (km).
+const SCALE_HEIGHT: f64 = 37.105;
+/// Spacecraft ballistic coefficient Cd*A/m (m^2/kg).
+const BALLISTIC_COEFFICIENT: f64 = 2.2 * 1.0 / 120.0;
+
+/// Drag through an exponential atmosphere, applied below a ceiling altitude.
+pub struct Drag {
+    ceiling: f64,
+}
+
+impl Drag {
+    /// Create a drag model acting below `ceiling` (km).
+    pub fn new(ceiling: f64) -> Self {
+        Self { ceiling }
+    }
+}
+
+/// Atmospheric density at an altitude (kg/m^3).
+fn density(altitude: f64) -> f64 {
+    REFERENCE_DENSITY * (-(altitude - REFERENCE_ALTITUDE) / SCALE_HEIGHT).exp()
+}
+
+impl AccelModel for Drag {
+    fn eom(&self, osc: &State) -> Vector3<f64> {
+        let altitude = osc.geodetic_height();
+        if altitude >= self.ceiling {
+            return Vector3::zeros();
+        }
+        // Velocity is in km/s and the result in km/s^2, hence the factor of 1e3
+        let velocity = Vector3::new(osc.vx, osc.vy, osc.vz);
+        velocity * (-0.5e3 * density(altitude) * BALLISTIC_COEFFICIENT * velocity.norm())
+    }
+}
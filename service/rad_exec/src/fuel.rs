@@ -0,0 +1,111 @@
+//! Fuel accounting.
+
+use rad_common::Burn;
+use std::cmp::Ordering;
+
+/// Fuel change noticed between readings.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FuelEvent {
+    /// Fuel fell below a threshold (percent of capacity)
+    Low(f64),
+    /// A burn completed, consuming this much fuel (kg)
+    Burned(f64),
+}
+
+/// Watches fuel readings for low-fuel thresholds and the cost of each burn.
+pub struct FuelGauge {
+    capacity: f64,
+    /// Thresholds not yet crossed, highest first (percent)
+    thresholds: Vec<f64>,
+    /// End time and starting fuel of the burn in progress
+    burn: Option<(u64, f64)>,
+    /// Fuel consumed by the most recently completed burn (kg)
+    last_burn: Option<f64>,
+}
+
+impl FuelGauge {
+    /// Create a gauge for a tank of `capacity` (kg) reporting the given thresholds (percent).
+    pub fn new(capacity: f64, mut thresholds: Vec<f64>) -> Self {
+        thresholds.sort_by(|a, b| b.partial_cmp(a).unwrap_or(Ordering::Equal));
+        Self {
+            capacity,
+            thresholds,
+            burn: None,
+            last_burn: None,
+        }
+    }
+
+    /// Fuel consumed by the most recently completed burn (kg).
+    pub fn last_burn(&self) -> Option<f64> {
+        self.last_burn
+    }
+
+    /// Record a fuel reading (kg) at `now` under a burn schedule, returning what changed.
+    pub fn observe(&mut self, now: u64, fuel: f64, burns: &[Burn]) -> Vec<FuelEvent> {
+        let mut events = vec![];
+        if let Some((end, start_fuel)) = self.burn {
+            if now >= end {
+                let consumed = (start_fuel - fuel).max(0.0);
+                self.last_burn = Some(consumed);
+                self.burn = None;
+                events.push(FuelEvent::Burned(consumed));
+            }
+        }
+        if self.burn.is_none() {
+            self.burn = burns
+                .iter()
+                .map(|b| (b.start, b.start + b.length as u64))
+                .find(|&(start, end)| start <= now && now < end)
+                .map(|(_, end)| (end, fuel));
+        }
+
+        let percent = 100.0 * fuel / self.capacity;
+        let crossed = self
+            .thresholds
+            .iter()
+            .take_while(|&&threshold| percent < threshold)
+            .count();
+        events.extend(self.thresholds.drain(..crossed).map(FuelEvent::Low));
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn burn(start: u64, length: u8) -> Burn {
+        Burn::new(start, length, 1.0, (1.0, 0.0, 0.0)).expect("burn")
+    }
+
+    #[test]
+    fn test_fuel_gauge() {
+        let mut gauge = FuelGauge::new(20.0, vec![10.0, 25.0]);
+        let burns = vec![burn(100, 10)];
+        assert_eq!(gauge.observe(90, 6.0, &burns), vec![]);
+        assert_eq!(gauge.last_burn(), None);
+
+        // The burn spends fuel, crossing the 25% threshold partway through
+        assert_eq!(gauge.observe(100, 6.0, &burns), vec![]);
+        assert_eq!(gauge.observe(105, 4.5, &burns), vec![FuelEvent::Low(25.0)]);
+        assert_eq!(
+            gauge.observe(110, 3.0, &burns),
+            vec![FuelEvent::Burned(3.0)]
+        );
+        assert_eq!(gauge.last_burn(), Some(3.0));
+
+        // Thresholds are reported once
+        assert_eq!(gauge.observe(120, 3.0, &burns), vec![]);
+        assert_eq!(gauge.observe(130, 1.0, &[]), vec![FuelEvent::Low(10.0)]);
+        assert_eq!(gauge.observe(140, 0.5, &[]), vec![]);
+    }
+
+    #[test]
+    fn test_fuel_gauge_crosses_several() {
+        let mut gauge = FuelGauge::new(20.0, vec![25.0, 10.0]);
+        assert_eq!(
+            gauge.observe(0, 1.0, &[]),
+            vec![FuelEvent::Low(25.0), FuelEvent::Low(10.0)]
+        );
+    }
+}
@@ -6,27 +6,43 @@ extern crate lazy_static;
 extern crate log;
 extern crate nyx_space as nyx;
 
+use std::collections::VecDeque;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
 use anyhow::{anyhow, Result};
 use chrono::prelude::*;
 use nyx::celestia::bodies::{EARTH_MOON, SUN};
-use nyx::celestia::{Cosm, State};
+use nyx::celestia::{Cosm, LTCorr, State};
 use nyx::dimensions::Vector3;
 use nyx::dynamics::orbital::OrbitalDynamics;
 use nyx::dynamics::propulsion::{Propulsion, Thruster};
 use nyx::dynamics::spacecraft::{Spacecraft, SpacecraftState};
+use nyx::dynamics::sph_harmonics::Harmonics;
 use nyx::dynamics::thrustctrl::{FiniteBurns, Mnvr};
+use nyx::io::gravity::HarmonicsMem;
 use nyx::propagators::{CashKarp45, PropOpts, Propagator, RSSStepPV};
 use nyx::time::Epoch;
-use rad_common::{compute_radiation, Burn};
+use rad_common::frame::MAX_FRAME_SIZE;
+use rad_common::message;
+use rad_common::{
+    check_ephemeris, compute_radiation_3d, ephemeris_path, Burn, Eclipse, ManeuverRecord,
+    SensorSample, CHECKPOINT_KEYS_PATH, CHECKPOINT_PUB_KEY_PATH,
+};
 use tokio::sync::mpsc::channel;
-use tokio::time::sleep;
+use tokio::time::{sleep, timeout};
+use tokio_util::sync::CancellationToken;
 
+mod config;
 mod control;
+mod drag;
+mod fuel;
 mod monitor;
+mod orbit;
 mod service;
+mod shutdown;
 
 const FIRMWARE_PATH: &str = "./rad_fw";
 const CONTROL_PORT: u16 = 1337;
@@ -35,11 +51,34 @@ const MAX_ALTITUDE: f64 = 300000.0;
 const REPORT_INTERVAL: i64 = 5;
 const DRY_MASS: f64 = 100.0;
 const FUEL_MASS: f64 = 20.0;
+const HISTORY_INTERVAL: u64 = 10;
+const MAX_HISTORY_SAMPLES: usize = 360;
+const MAX_MANEUVER_HISTORY: usize = 64;
+/// Encoded size of the maneuver history, leaving room in a frame for the response around it.
+const MAX_MANEUVER_HISTORY_SIZE: usize = MAX_FRAME_SIZE - 256;
+const SHUTDOWN_TIMEOUT: u64 = 5;
+const CONTROL_IDLE_TIMEOUT: u64 = 300;
+const DRAG_ALTITUDE: f64 = 1000.0;
+const LOW_FUEL_THRESHOLDS: [f64; 2] = [25.0, 10.0];
+/// Seconds from the 1900 epoch of nyx UTC times to the Unix epoch.
+const UNIX_EPOCH_OFFSET: f64 = 2_208_988_800.0;
+
+/// Simulated Unix time of the latest spacecraft state, zero until the simulation starts.
+static SIM_TIME: AtomicU64 = AtomicU64::new(0);
+/// Set when an abort has cut the active burn schedule short and the simulation must restart
+/// with it.
+static RESTART_BURNS: AtomicBool = AtomicBool::new(false);
 
 lazy_static! {
     static ref STATE: Arc<Mutex<Option<SpacecraftState>>> = Arc::new(Mutex::new(None));
     static ref BURNS: Arc<Mutex<Option<Vec<Burn>>>> = Arc::new(Mutex::new(None));
+    static ref ACTIVE_BURNS: Mutex<Vec<Burn>> = Mutex::new(vec![]);
     static ref RAD: Mutex<f64> = Mutex::new(0.0);
+    static ref ECLIPSE: Mutex<Eclipse> = Mutex::new(Eclipse::Sunlit);
+    static ref HISTORY: Mutex<VecDeque<SensorSample>> = Mutex::new(VecDeque::new());
+    static ref FUEL_GAUGE: Mutex<fuel::FuelGauge> =
+        Mutex::new(fuel::FuelGauge::new(FUEL_MASS, vec![]));
+    static ref MANEUVERS: Mutex<VecDeque<ManeuverRecord>> = Mutex::new(VecDeque::new());
 }
 
 pub type RadCraft<'a> = Propagator<'a, Spacecraft<'a, OrbitalDynamics<'a>>, RSSStepPV>;
@@ -49,116 +88,328 @@ pub type RadCraft<'a> = Propagator<'a, Spacecraft<'a, OrbitalDynamics<'a>>, RSSS
 async fn main() {
     env_logger::init();
 
+    let ephemeris_path = ephemeris_path();
+    if let Err(e) = check_ephemeris(&ephemeris_path) {
+        error!("{}", e);
+        return;
+    }
+    info!("loading ephemeris from {}", ephemeris_path);
+    let cosm = Cosm::from_xb(&ephemeris_path);
+    let j2 = match config::env_or("RAD_J2", false) {
+        Ok(j2) => j2,
+        Err(e) => {
+            error!("{}", e);
+            return;
+        }
+    };
+    if j2 {
+        info!("J2 perturbation enabled");
+    }
+    let drag = match (
+        config::env_or("RAD_DRAG", false),
+        config::env_or("RAD_DRAG_ALTITUDE", DRAG_ALTITUDE),
+    ) {
+        (Ok(false), Ok(_)) => None,
+        (Ok(true), Ok(altitude)) if altitude > MIN_ALTITUDE => {
+            info!("atmospheric drag enabled below {} km", altitude);
+            Some(altitude)
+        }
+        (Ok(true), Ok(altitude)) => {
+            error!(
+                "invalid RAD_DRAG_ALTITUDE={}: must be above {} km",
+                altitude, MIN_ALTITUDE
+            );
+            return;
+        }
+        (Err(e), _) | (_, Err(e)) => {
+            error!("{}", e);
+            return;
+        }
+    };
+    let perturbations = Perturbations { j2, drag };
+    let thresholds =
+        match config::env_list_or("RAD_LOW_FUEL_THRESHOLDS", LOW_FUEL_THRESHOLDS.to_vec()) {
+            Ok(thresholds) if thresholds.iter().all(|x| *x > 0.0 && *x <= 100.0) => thresholds,
+            Ok(thresholds) => {
+                error!(
+                    "invalid RAD_LOW_FUEL_THRESHOLDS={:?}: must be percentages",
+                    thresholds
+                );
+                return;
+            }
+            Err(e) => {
+                error!("{}", e);
+                return;
+            }
+        };
+    match FUEL_GAUGE.lock() {
+        Ok(mut gauge) => *gauge = fuel::FuelGauge::new(FUEL_MASS, thresholds),
+        Err(_) => {
+            error!("fuel gauge lock");
+            return;
+        }
+    }
+    let time_scale = match config::env_or("RAD_TIME_SCALE", 1.0) {
+        Ok(scale) if scale > 0.0 && f64::is_finite(scale) => scale,
+        Ok(scale) => {
+            error!("invalid RAD_TIME_SCALE={}: must be positive", scale);
+            return;
+        }
+        Err(e) => {
+            error!("{}", e);
+            return;
+        }
+    };
+    if time_scale != 1.0 {
+        warn!(
+            "simulation time accelerated {}x by RAD_TIME_SCALE",
+            time_scale
+        );
+    }
+    let idle_timeout = match config::env_or("RAD_CONTROL_IDLE_TIMEOUT", CONTROL_IDLE_TIMEOUT) {
+        Ok(secs) if secs > 0 => Duration::from_secs(secs),
+        Ok(_) => {
+            error!("invalid RAD_CONTROL_IDLE_TIMEOUT=0: must be positive");
+            return;
+        }
+        Err(e) => {
+            error!("{}", e);
+            return;
+        }
+    };
+    let faults = match monitor::FaultConfig::from_env() {
+        Ok(faults) => faults,
+        Err(e) => {
+            error!("{}", e);
+            return;
+        }
+    };
+    let checkpoint_keys = match service::checkpoint_keys(
+        Path::new(CHECKPOINT_KEYS_PATH),
+        Path::new(CHECKPOINT_PUB_KEY_PATH),
+    ) {
+        Ok(keys) => Arc::new(keys),
+        Err(e) => {
+            error!("checkpoint keys: {}", e);
+            return;
+        }
+    };
+    let orbit = match config::InitialOrbit::from_env()
+        .and_then(|x| x.state(epoch(Utc::now()), cosm.frame("EME2000")))
+    {
+        Ok(orbit) => orbit,
+        Err(e) => {
+            error!("initial orbit: {}", e);
+            return;
+        }
+    };
+
     let (tx_command_requests, mut rx_command_requests) = channel(256);
     let (tx_command_responses, mut rx_command_responses) = channel(256);
 
+    // Ground control stops first so open connections can drain through the running firmware
+    let shutdown = CancellationToken::new();
+    let firmware_shutdown = CancellationToken::new();
+    let mut tasks = vec![];
+    let mut firmware_tasks = vec![];
+
     tokio::spawn({
+        let shutdown = shutdown.clone();
         async move {
-            loop {
-                if let Err(e) = service::process_connections().await {
-                    error!("service firmware: {}", e);
+            match shutdown::until_cancelled(&shutdown, shutdown::signalled()).await {
+                Some(Ok(())) => {
+                    info!("received shutdown signal");
+                    shutdown.cancel();
                 }
+                Some(Err(e)) => error!("install signal handlers: {}", e),
+                None => {}
             }
         }
     });
 
-    tokio::spawn(async move {
-        loop {
-            if let Err(e) =
-                control::process_connections(&tx_command_requests, &mut rx_command_responses).await
+    firmware_tasks.push(tokio::spawn({
+        let shutdown = firmware_shutdown.clone();
+        async move {
+            while let Some(result) = shutdown::until_cancelled(
+                &shutdown,
+                service::process_connections(checkpoint_keys.clone()),
+            )
+            .await
             {
-                error!("service control: {}", e);
+                if let Err(e) = result {
+                    error!("service firmware: {}", e);
+                }
             }
         }
-    });
+    }));
 
-    tokio::spawn(async move {
-        loop {
-            if let Err(e) =
-                control::proxy_requests_to_firmware(&mut rx_command_requests, &tx_command_responses)
-                    .await
-            {
-                error!("proxy control: {}", e);
+    tasks.push(tokio::spawn({
+        let shutdown = shutdown.clone();
+        async move {
+            while !shutdown.is_cancelled() {
+                let result = control::process_connections(
+                    &shutdown,
+                    idle_timeout,
+                    &tx_command_requests,
+                    &mut rx_command_responses,
+                )
+                .await;
+                if let Err(e) = result {
+                    error!("service control: {}", e);
+                }
             }
         }
-    });
+    }));
+
+    tasks.push(tokio::spawn(async move {
+        let result =
+            control::proxy_requests_to_firmware(&mut rx_command_requests, &tx_command_responses)
+                .await;
+        if let Err(e) = result {
+            error!("proxy control: {}", e);
+        }
+    }));
 
-    tokio::spawn(async move {
-        loop {
-            if let Err(e) = monitor::execute_firmware().await {
-                error!("execute firmware: {}", e);
+    firmware_tasks.push(tokio::spawn({
+        let shutdown = firmware_shutdown.clone();
+        async move {
+            while let Some(result) =
+                shutdown::until_cancelled(&shutdown, monitor::execute_firmware(faults)).await
+            {
+                if let Err(e) = result {
+                    error!("execute firmware: {}", e);
+                }
             }
         }
-    });
+    }));
 
-    let mut orbit = None;
+    let mut orbit = orbit;
     let mut dry_mass = DRY_MASS;
     let mut fuel_mass = FUEL_MASS;
     let mut burns = vec![];
 
     loop {
-        match simulate_spacecraft(orbit, dry_mass, fuel_mass, burns).await {
-            Ok((o, d, f, b)) => {
-                orbit = Some(o);
+        let simulation = simulate_spacecraft(
+            &cosm,
+            perturbations,
+            time_scale,
+            orbit,
+            dry_mass,
+            fuel_mass,
+            burns,
+        );
+        match shutdown::until_cancelled(&shutdown, simulation).await {
+            Some(Ok((o, d, f, b))) => {
+                orbit = o;
                 dry_mass = d;
                 fuel_mass = f;
                 burns = b;
             }
-            Err(e) => {
+            Some(Err(e)) => {
                 error!("simulate spacecraft: {}", e);
                 break;
             }
+            None => break,
         }
     }
+
+    info!("shutting down");
+    shutdown.cancel();
+    join_tasks(tasks).await;
+    firmware_shutdown.cancel();
+    join_tasks(firmware_tasks).await;
 }
 
-/// Run the simulation.
-async fn simulate_spacecraft(
-    orbit: Option<State>,
-    dry_mass: f64,
-    fuel_mass: f64,
-    burns: Vec<Burn>,
-) -> Result<(State, f64, f64, Vec<Burn>)> {
-    info!(
-        "simulating spacecraft dry_mass={} fuel_mass={}",
-        dry_mass, fuel_mass
-    );
-    if let Some(orbit) = orbit {
-        info!("initial orbit: {}", orbit);
+/// Wait for tasks to exit, giving up on each after the shutdown timeout.
+async fn join_tasks(tasks: Vec<tokio::task::JoinHandle<()>>) {
+    for task in tasks {
+        match timeout(Duration::from_secs(SHUTDOWN_TIMEOUT), task).await {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => error!("task failed: {}", e),
+            Err(_) => warn!("task did not exit within {}s", SHUTDOWN_TIMEOUT),
+        }
     }
-    info!("burn schedule: {:#?}", burns);
+}
 
-    let ts_start = Utc::now();
-    let point_masses = vec![EARTH_MOON, SUN];
-    let dt = Epoch::from_gregorian_utc(
-        ts_start.year(),
-        ts_start.month() as _,
-        ts_start.day() as _,
-        ts_start.hour() as _,
-        ts_start.minute() as _,
-        ts_start.second() as _,
-        ts_start.nanosecond(),
-    );
-    let cosm = Cosm::from_xb("data/de438s");
-    let eme2k = cosm.frame("EME2000");
+/// Maps elapsed wall-clock time onto simulated time, running the simulation `scale` times faster.
+struct SimClock {
+    scale: f64,
+    last: DateTime<Utc>,
+}
 
-    let orbit = orbit.unwrap_or_else(|| {
-        // High earth orbit (IBEX)
-        // State::keplerian(
-        //     202_811.0, 0.6586277, 26.0179, 93.9503, 22.5731, 356.6008, dt, eme2k,
-        // )
+impl SimClock {
+    /// Create a clock starting at `start`.
+    fn new(scale: f64, start: DateTime<Utc>) -> Self {
+        Self { scale, last: start }
+    }
 
-        // High radiation orbit
-        State::from_geodesic(0.0, 0.0, 6000.0, dt, eme2k)
+    /// Return the simulated seconds elapsed since the previous tick.
+    fn tick(&mut self, now: DateTime<Utc>) -> f64 {
+        let elapsed = (now - self.last).num_milliseconds() as f64 / 1000.0;
+        self.last = now;
+        elapsed * self.scale
+    }
+}
 
-        // Potential start orbit above (but not too far from) inner belt
-        // State::from_geodesic(42.3601, 71.0589, 16384.0, dt, eme2k)
-    });
+/// Convert a UTC timestamp to an epoch.
+fn epoch(ts: DateTime<Utc>) -> Epoch {
+    Epoch::from_gregorian_utc(
+        ts.year(),
+        ts.month() as _,
+        ts.day() as _,
+        ts.hour() as _,
+        ts.minute() as _,
+        ts.second() as _,
+        ts.nanosecond(),
+    )
+}
 
-    // Orbital dynamics
-    let dynamics = OrbitalDynamics::point_masses(orbit, point_masses, &cosm);
+/// Convert an epoch to a Unix timestamp.
+fn unix_time(dt: Epoch) -> u64 {
+    (dt.as_utc_seconds() - UNIX_EPOCH_OFFSET) as u64
+}
+
+/// Return the simulated Unix time, or the wall clock time before the simulation has started,
+/// since it starts at the current time.
+fn sim_time() -> u64 {
+    match SIM_TIME.load(Ordering::SeqCst) {
+        0 => Utc::now().timestamp() as u64,
+        now => now,
+    }
+}
+
+/// Optional perturbations of the orbit.
+#[derive(Clone, Copy)]
+struct Perturbations {
+    /// Earth's J2 oblateness
+    j2: bool,
+    /// Atmospheric drag below this altitude (km)
+    drag: Option<f64>,
+}
+
+/// Build the orbital dynamics: lunar and solar point masses, plus any enabled perturbations.
+fn orbital_dynamics(
+    orbit: State,
+    cosm: &Cosm,
+    perturbations: Perturbations,
+) -> OrbitalDynamics<'_> {
+    let mut dynamics = OrbitalDynamics::point_masses(orbit, vec![EARTH_MOON, SUN], cosm);
+    if perturbations.j2 {
+        let iau_earth = cosm.frame("IAU Earth");
+        dynamics.add_model(Box::new(Harmonics::from_stor(
+            iau_earth,
+            HarmonicsMem::j2_jgm3(),
+            cosm,
+        )));
+    }
+    if let Some(altitude) = perturbations.drag {
+        dynamics.add_model(Box::new(drag::Drag::new(altitude)));
+    }
+    dynamics
+}
 
-    // Thrusters and finite burn schedule
+/// Build the thrusters flying a finite burn schedule, with burn starts in Unix time.
+fn propulsion(burns: Vec<Burn>) -> Propulsion {
     let thrusters = vec![Thruster {
         thrust: 1000.0,
         isp: 300.0,
@@ -167,7 +418,7 @@ async fn simulate_spacecraft(
         burns
             .into_iter()
             .map(|b| {
-                let start = Epoch::from_tai_seconds(b.start as _);
+                let start = Epoch::from_utc_seconds(b.start as f64 + UNIX_EPOCH_OFFSET);
                 Mnvr {
                     start,
                     end: start + b.length as f64,
@@ -177,28 +428,65 @@ async fn simulate_spacecraft(
             })
             .collect(),
     );
-    let prop_subsys = Propulsion::new(Box::new(schedule), thrusters.clone(), true);
+    Propulsion::new(Box::new(schedule), thrusters, true)
+}
+
+/// Run the simulation.
+async fn simulate_spacecraft(
+    cosm: &Cosm,
+    perturbations: Perturbations,
+    time_scale: f64,
+    orbit: State,
+    dry_mass: f64,
+    fuel_mass: f64,
+    burns: Vec<Burn>,
+) -> Result<(State, f64, f64, Vec<Burn>)> {
+    info!(
+        "simulating spacecraft dry_mass={} fuel_mass={}",
+        dry_mass, fuel_mass
+    );
+    info!("initial orbit: {}", orbit);
+    info!("burn schedule: {:#?}", burns);
+
+    let ts_start = Utc::now();
+    let eme2k = cosm.frame("EME2000");
+
+    // Orbital dynamics
+    let dynamics = orbital_dynamics(orbit, cosm, perturbations);
 
     // Spacecraft
-    let mut craft = Spacecraft::with_prop(dynamics, prop_subsys, dry_mass, fuel_mass);
+    let mut craft = Spacecraft::with_prop(dynamics, propulsion(burns), dry_mass, fuel_mass);
 
     // Propagator
     let prop_opts = PropOpts::default();
     let mut prop = Propagator::new::<CashKarp45>(&mut craft, &prop_opts);
 
-    let mut ts_last = ts_start;
+    let mut clock = SimClock::new(time_scale, ts_start);
     let mut ts_last_report = ts_start;
+    let mut last_sample = unix_time(orbit.dt);
     loop {
         let ts_now = Utc::now();
 
         // Update the spacecraft's state
-        let current_state =
-            prop.until_time_elapsed((ts_now.timestamp() - ts_last.timestamp()) as f64);
+        let current_state = prop.until_time_elapsed(clock.tick(ts_now));
         *STATE.lock().map_err(|_| anyhow!("state lock"))? = Some(current_state);
-        *RAD.lock().map_err(|_| anyhow!("flux lock"))? = compute_radiation(
+        let now = unix_time(current_state.orbit.dt);
+        SIM_TIME.store(now, Ordering::SeqCst);
+        *RAD.lock().map_err(|_| anyhow!("flux lock"))? = compute_radiation_3d(
             current_state.orbit.geodetic_latitude(),
+            current_state.orbit.geodetic_longitude(),
             current_state.orbit.geodetic_height(),
         );
+        let sun = cosm.celestial_state(SUN, current_state.orbit.dt, eme2k, LTCorr::None);
+        *ECLIPSE.lock().map_err(|_| anyhow!("eclipse lock"))? = service::eclipse_state(
+            (
+                current_state.orbit.x,
+                current_state.orbit.y,
+                current_state.orbit.z,
+            ),
+            (sun.x, sun.y, sun.z),
+            eme2k.equatorial_radius(),
+        );
 
         // Check if we should report current position
         if (ts_now - ts_last_report).num_seconds() > REPORT_INTERVAL {
@@ -213,6 +501,42 @@ async fn simulate_spacecraft(
             ts_last_report = ts_now;
         }
 
+        // Check if we should record a sensor sample
+        if now.saturating_sub(last_sample) >= HISTORY_INTERVAL {
+            let mut history = HISTORY.lock().map_err(|_| anyhow!("history lock"))?;
+            history.push_back(SensorSample::new(
+                now,
+                current_state.fuel_mass,
+                *RAD.lock().map_err(|_| anyhow!("flux lock"))?,
+            ));
+            if history.len() > MAX_HISTORY_SAMPLES {
+                history.pop_front();
+            }
+            last_sample = now;
+        }
+
+        // Check for low fuel and completed burns
+        let events = {
+            let active_burns = ACTIVE_BURNS
+                .lock()
+                .map_err(|_| anyhow!("active burns lock"))?;
+            FUEL_GAUGE
+                .lock()
+                .map_err(|_| anyhow!("fuel gauge lock"))?
+                .observe(now, current_state.fuel_mass, &active_burns)
+        };
+        for event in events {
+            match event {
+                fuel::FuelEvent::Low(threshold) => warn!(
+                    "fuel below {}%: {:.3} kg remaining",
+                    threshold, current_state.fuel_mass
+                ),
+                fuel::FuelEvent::Burned(consumed) => {
+                    info!("burn consumed {:.3} kg of fuel", consumed)
+                }
+            }
+        }
+
         // Check if a physical failure condition has occurred
         let altitude = current_state.orbit.geodetic_height();
         if altitude < MIN_ALTITUDE {
@@ -225,7 +549,7 @@ async fn simulate_spacecraft(
         }
 
         // Check if we need to update the craft's orbital maneuvers
-        if let Some(burns) = BURNS.lock().map_err(|_| anyhow!("burns lock"))?.take() {
+        if let Some(burns) = take_burns(now)? {
             return Ok((
                 current_state.orbit,
                 current_state.dry_mass,
@@ -234,7 +558,213 @@ async fn simulate_spacecraft(
             ));
         }
 
-        ts_last = ts_now;
         sleep(Duration::from_millis(100)).await;
     }
 }
+
+/// Take the pending burn schedule, if any, recording it in the maneuver history, or the active
+/// schedule if an abort has cut it short.
+fn take_burns(applied: u64) -> Result<Option<Vec<Burn>>> {
+    let burns = BURNS.lock().map_err(|_| anyhow!("burns lock"))?.take();
+    let mut active = ACTIVE_BURNS
+        .lock()
+        .map_err(|_| anyhow!("active burns lock"))?;
+    if let Some(ref burns) = burns {
+        let mut maneuvers = MANEUVERS.lock().map_err(|_| anyhow!("maneuvers lock"))?;
+        record_maneuver(&mut maneuvers, ManeuverRecord::new(applied, burns.clone()))?;
+        *active = burns.clone();
+    } else if RESTART_BURNS.swap(false, Ordering::SeqCst) {
+        return Ok(Some(active.clone()));
+    }
+    Ok(burns)
+}
+
+/// Add a maneuver to the history, dropping the oldest so it can still be sent in one frame.
+fn record_maneuver(
+    maneuvers: &mut VecDeque<ManeuverRecord>,
+    maneuver: ManeuverRecord,
+) -> Result<()> {
+    maneuvers.push_back(maneuver);
+    while maneuvers.len() > MAX_MANEUVER_HISTORY
+        || message::encode(&*maneuvers)?.len() > MAX_MANEUVER_HISTORY_SIZE
+    {
+        maneuvers.pop_front();
+    }
+    Ok(())
+}
+
+/// Cancel the pending burn schedule and cut the simulated one short at `now`, marking its
+/// maneuver aborted, and return whether either had burns left to cancel.
+fn abort_burns(now: u64) -> Result<bool> {
+    let mut pending = BURNS.lock().map_err(|_| anyhow!("burns lock"))?;
+    let mut active = ACTIVE_BURNS
+        .lock()
+        .map_err(|_| anyhow!("active burns lock"))?;
+    let cancelled = pending.take().is_some();
+    let truncated = truncate_burns(&active, now);
+    let truncating = truncated != *active;
+    if truncating {
+        if let Some(maneuver) = MANEUVERS
+            .lock()
+            .map_err(|_| anyhow!("maneuvers lock"))?
+            .back_mut()
+        {
+            maneuver.aborted = Some(now);
+        }
+        // Restart the simulation with nothing burning past now
+        *active = truncated;
+        RESTART_BURNS.store(true, Ordering::SeqCst);
+    }
+    Ok(cancelled || truncating)
+}
+
+/// Drop burns starting at or after `now` and shorten any burn still in progress.
+fn truncate_burns(burns: &[Burn], now: u64) -> Vec<Burn> {
+    burns
+        .iter()
+        .filter(|b| b.start < now)
+        .map(|b| Burn {
+            length: b.length.min((now - b.start).min(u8::MAX as u64) as u8),
+            ..b.clone()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Propagate a low inclined orbit for a day and return the change in RAAN (degrees).
+    fn raan_drift(cosm: &Cosm, j2: bool) -> f64 {
+        let dt = Epoch::from_gregorian_utc(2021, 4, 30, 0, 0, 0, 0);
+        let eme2k = cosm.frame("EME2000");
+        let orbit = State::keplerian(7000.0, 0.001, 51.6, 30.0, 0.0, 0.0, dt, eme2k);
+        let mut dynamics = orbital_dynamics(orbit, cosm, Perturbations { j2, drag: None });
+        let prop_opts = PropOpts::default();
+        let mut prop: Propagator<_, RSSStepPV> =
+            Propagator::new::<CashKarp45>(&mut dynamics, &prop_opts);
+        let final_state = prop.until_time_elapsed(86400.0);
+        final_state.raan() - orbit.raan()
+    }
+
+    /// Propagate a low circular orbit for six hours and return its final specific energy
+    /// (km^2/s^2).
+    fn final_energy(cosm: &Cosm, drag: Option<f64>) -> f64 {
+        let dt = Epoch::from_gregorian_utc(2021, 4, 30, 0, 0, 0, 0);
+        let eme2k = cosm.frame("EME2000");
+        let sma = eme2k.equatorial_radius() + 250.0;
+        let orbit = State::keplerian(sma, 0.001, 51.6, 30.0, 0.0, 0.0, dt, eme2k);
+        let mut dynamics = orbital_dynamics(orbit, cosm, Perturbations { j2: false, drag });
+        let prop_opts = PropOpts::default();
+        let mut prop: Propagator<_, RSSStepPV> =
+            Propagator::new::<CashKarp45>(&mut dynamics, &prop_opts);
+        let final_state = prop.until_time_elapsed(6.0 * 3600.0);
+        -eme2k.gm() / (2.0 * final_state.sma())
+    }
+
+    #[test]
+    fn test_time_scale() {
+        let start = Utc::now();
+        let mut clock = SimClock::new(10.0, start);
+        let mut simulated = 0.0;
+        for i in 1..=50 {
+            simulated += clock.tick(start + chrono::Duration::milliseconds(100 * i));
+        }
+        // Five wall-clock seconds advance the simulation fifty
+        assert!((simulated - 50.0).abs() < 1e-6, "simulated {}s", simulated);
+
+        let mut clock = SimClock::new(1.0, start);
+        assert_eq!(clock.tick(start + chrono::Duration::seconds(5)), 5.0);
+    }
+
+    #[test]
+    fn test_maneuver_history_fits_frame() {
+        let burns: Vec<_> = (0..rad_common::MAX_BURNS as u64)
+            .map(|i| Burn::new(1_620_000_000 + 10 * i, 10, 0.5, (1.0, 0.0, 0.0)).expect("burn"))
+            .collect();
+        let mut maneuvers = VecDeque::new();
+        for i in 0..MAX_MANEUVER_HISTORY as u64 {
+            record_maneuver(&mut maneuvers, ManeuverRecord::new(i, burns.clone()))
+                .expect("record maneuver");
+        }
+
+        // Full schedules are trimmed to what one frame can carry, newest kept
+        assert!(maneuvers.len() < MAX_MANEUVER_HISTORY);
+        assert_eq!(
+            maneuvers.back().map(|x| x.applied),
+            Some(MAX_MANEUVER_HISTORY as u64 - 1)
+        );
+        let response = rad_common::ExecutiveResponse::ManeuverHistory {
+            success: true,
+            maneuvers: maneuvers.into_iter().collect(),
+        };
+        assert!(message::encode(&response).expect("encode").len() <= MAX_FRAME_SIZE);
+
+        // Small schedules are limited by count
+        let mut maneuvers = VecDeque::new();
+        for i in 0..2 * MAX_MANEUVER_HISTORY as u64 {
+            record_maneuver(&mut maneuvers, ManeuverRecord::new(i, vec![]))
+                .expect("record maneuver");
+        }
+        assert_eq!(maneuvers.len(), MAX_MANEUVER_HISTORY);
+    }
+
+    #[test]
+    fn test_unix_time() {
+        let ts = Utc.ymd(2021, 4, 30).and_hms(12, 34, 56);
+        assert_eq!(unix_time(epoch(ts)), ts.timestamp() as u64);
+    }
+
+    #[test]
+    fn test_j2_nodal_regression() {
+        let cosm = Cosm::from_xb(concat!(env!("CARGO_MANIFEST_DIR"), "/../data/de438s"));
+        // J2 regresses the node of this orbit by roughly 5 degrees per day
+        let drift = raan_drift(&cosm, true);
+        assert!(drift < -4.0 && drift > -6.0, "J2 RAAN drift {}", drift);
+        // Third-body perturbations alone barely move it
+        let drift = raan_drift(&cosm, false);
+        assert!(drift.abs() < 0.1, "point mass RAAN drift {}", drift);
+    }
+
+    #[test]
+    fn test_abort_stops_fuel_use() {
+        let cosm = Cosm::from_xb(concat!(env!("CARGO_MANIFEST_DIR"), "/../data/de438s"));
+        let dt = Epoch::from_gregorian_utc(2021, 4, 30, 0, 0, 0, 0);
+        let eme2k = cosm.frame("EME2000");
+        let orbit = State::keplerian(7000.0, 0.001, 51.6, 30.0, 0.0, 0.0, dt, eme2k);
+        let start = unix_time(dt);
+
+        // Abort ten seconds into a minute-long burn
+        let burn = Burn::new(start + 10, 60, 1.0, (1.0, 0.0, 0.0)).expect("burn");
+        let burns = truncate_burns(&[burn], start + 20);
+        let perturbations = Perturbations {
+            j2: false,
+            drag: None,
+        };
+        let dynamics = orbital_dynamics(orbit, &cosm, perturbations);
+        let mut craft = Spacecraft::with_prop(dynamics, propulsion(burns), 100.0, 20.0);
+        let prop_opts = PropOpts::default();
+        let mut prop = Propagator::new::<CashKarp45>(&mut craft, &prop_opts);
+        let aborted = prop.until_time_elapsed(30.0);
+        assert!(aborted.fuel_mass < 20.0, "no fuel spent before the abort");
+
+        // The next step spends nothing, though the original burn would still be firing
+        let later = prop.until_time_elapsed(30.0);
+        assert_eq!(later.fuel_mass, aborted.fuel_mass);
+    }
+
+    #[test]
+    fn test_drag_decay() {
+        let cosm = Cosm::from_xb(concat!(env!("CARGO_MANIFEST_DIR"), "/../data/de438s"));
+        let free = final_energy(&cosm, None);
+        let dragged = final_energy(&cosm, Some(DRAG_ALTITUDE));
+        assert!(
+            dragged < free,
+            "energy {} with drag, {} without",
+            dragged,
+            free
+        );
+        // A ceiling below the orbit leaves it untouched
+        assert_eq!(final_energy(&cosm, Some(100.0)), free);
+    }
+}
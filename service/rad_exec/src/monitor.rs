@@ -1,32 +1,83 @@
 //! Monitor firmware.
 
+use crate::config::env_or;
 use crate::{FIRMWARE_PATH, RAD};
 use anyhow::{anyhow, Context, Result};
-use rad_common::CHECKPOINT_PATH;
-use rand::Rng;
+use rad_common::{checkpoint_generation_path, CHECKPOINT_PATH};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
 use regex::Regex;
-use std::path::Path;
 use std::process::Stdio;
-use std::time::Duration;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
 use tokio::io::{AsyncBufReadExt, BufReader};
 use tokio::process::{ChildStderr, ChildStdout, Command};
-use tokio::time::sleep;
+use tokio::time::{sleep, timeout};
+
+const STATE_LOCATION_LABEL: &str = "protected state:";
+const STATE_LOCATION_TIMEOUT: u64 = 30;
+
+/// Fault injection settings.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FaultConfig {
+    /// Fault model
+    pub model: FaultModel,
+    /// Injection RNG seed, logged so a run can be reproduced
+    pub seed: u64,
+    /// For testing only: leave firmware memory alone so the control protocol is deterministic
+    pub disabled: bool,
+}
+
+impl FaultConfig {
+    /// Read the fault injection settings, choosing a random seed unless RAD_FAULT_SEED is set.
+    pub fn from_env() -> Result<Self> {
+        Ok(Self {
+            model: env_or("RAD_FAULT_MODEL", FaultModel::SingleBit)?,
+            seed: env_or("RAD_FAULT_SEED", rand::random())?,
+            disabled: env_or("RAD_DISABLE_FAULTS", false)?,
+        })
+    }
+}
 
 /// Execute and monitor the firmware.
-pub async fn execute_firmware() -> Result<()> {
+pub async fn execute_firmware(faults: FaultConfig) -> Result<()> {
     info!("executing firmware at {}", FIRMWARE_PATH);
     let mut p = Command::new(&FIRMWARE_PATH);
-    p.stdout(Stdio::piped()).stderr(Stdio::piped());
-    let checkpoint_path = Path::new(CHECKPOINT_PATH);
+    p.stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+    let checkpoint_path = checkpoint_generation_path(CHECKPOINT_PATH, 0);
     if checkpoint_path.is_file() {
         p.arg(checkpoint_path);
     }
+    if env_or("RAD_NO_MODULES", false)? {
+        p.arg("--no-modules");
+    }
+    if std::env::var_os("RAD_MODULE_UPDATE_COOLDOWN").is_some() {
+        let cooldown: u64 = env_or("RAD_MODULE_UPDATE_COOLDOWN", 0)?;
+        p.arg(format!("--module-update-cooldown={}", cooldown));
+    }
+    if std::env::var_os("RAD_CHECKPOINTS").is_some() {
+        let checkpoints: usize = env_or("RAD_CHECKPOINTS", 0)?;
+        p.arg(format!("--checkpoints={}", checkpoints));
+    }
+    if let Some(paths) = std::env::var_os("RAD_FILE_READ_ALLOW") {
+        for path in std::env::split_paths(&paths) {
+            let mut arg = std::ffi::OsString::from("--file-read-allow=");
+            arg.push(path);
+            p.arg(arg);
+        }
+    }
+    if env_or("RAD_INSECURE_FILE_READ", false)? {
+        p.arg("--insecure-file-read");
+    }
 
     let mut p = p.spawn().context("execute firmware")?;
     if let (Some(id), Some(stdout), Some(stderr)) = (p.id(), p.stdout.take(), p.stderr.take()) {
         tokio::spawn(async move {
-            if let Err(e) = inject_faults(id, stdout, stderr).await {
-                error!("inject faults: {}", e);
+            match inject_faults(id, stdout, stderr, faults).await {
+                Ok(injected) => info!("injected {} faults", injected),
+                Err(e) => error!("inject faults: {}", e),
             }
         });
     }
@@ -36,74 +87,506 @@ pub async fn execute_firmware() -> Result<()> {
     Ok(())
 }
 
-/// Inject memory faults into firmware.
-async fn inject_faults(id: u32, _stdout: ChildStdout, stderr: ChildStderr) -> Result<()> {
+/// Inject memory faults into firmware until its output closes, returning the number injected.
+async fn inject_faults(
+    id: u32,
+    _stdout: ChildStdout,
+    stderr: ChildStderr,
+    faults: FaultConfig,
+) -> Result<u64> {
     info!("waiting for protected state address in process {}", id);
-    let addr_re = Regex::new(r"protected state at 0x([[:xdigit:]]+)-0x([[:xdigit:]]+)")?;
+    let addr_re = state_location_regex()?;
     let mut reader = BufReader::new(stderr).lines();
-    let mut state_addr = 0;
-    let mut state_size = 0;
-    while let Some(line) = reader.next_line().await? {
-        info!("FW: {}", line);
-        if let Some(m) = addr_re.captures(&line) {
-            if let (Some(start), Some(end)) = (m.get(1), m.get(2)) {
-                state_addr = u64::from_str_radix(start.as_str(), 16)?;
-                state_size = u64::from_str_radix(end.as_str(), 16)? - state_addr;
-                break;
+    let mut location = None;
+    let wait = timeout(Duration::from_secs(STATE_LOCATION_TIMEOUT), async {
+        while let Some(line) = reader.next_line().await? {
+            info!("FW: {}", line);
+            if line.contains(STATE_LOCATION_LABEL) {
+                location = parse_state_location(&addr_re, &line);
+                if location.is_some() {
+                    break;
+                }
+                warn!("unable to parse protected state location from: {}", line);
             }
         }
+        Ok::<_, anyhow::Error>(())
+    })
+    .await;
+    match wait {
+        Ok(result) => result?,
+        Err(_) => warn!(
+            "no protected state location from firmware within {}s",
+            STATE_LOCATION_TIMEOUT
+        ),
+    }
+    let (state_addr, state_size) = location.unwrap_or((0, 0));
+    if state_addr == 0 {
+        warn!("protected state location unknown, fault injection disabled");
     }
 
-    tokio::spawn(async move {
+    let output = tokio::spawn(async move {
         while let Ok(Some(line)) = reader.next_line().await {
             info!("FW: {}", line);
         }
     });
 
-    if state_addr != 0 {
+    let mut injected = 0;
+    if faults.disabled {
+        warn!("fault injection disabled by RAD_DISABLE_FAULTS");
+    } else if state_addr != 0 {
         info!(
-            "injecting faults into protected state at 0x{:x}",
-            state_addr
+            "injecting {:?} faults into protected state at 0x{:x} with seed {}",
+            faults.model, state_addr, faults.seed
         );
 
-        loop {
+        let rng = StdRng::seed_from_u64(faults.seed);
+        let mut injector = Injector::new(faults.model, rng, state_addr, state_size);
+        let mut target = ProcessMemory { pid: id as _ };
+        while !output.is_finished() {
             sleep(Duration::from_millis(100)).await;
 
-            let mut rng = rand::thread_rng();
             let radiation = *RAD.lock().map_err(|_| anyhow!("radiation lock"))? as usize;
-            if rng.gen_range(0..300) < radiation {
-                let fault_addr = rng.gen_range(state_addr..(state_addr + state_size)) & (!0x0f);
-                let fault_bit = rng.gen_range(0..64);
-                // debug!("flipping bit at 0x{:x}/{}", fault_addr, fault_bit);
-                unsafe {
-                    let mut x: [u64; 1] = [0];
-                    let mut local_iovec: libc::iovec = std::mem::zeroed();
-                    local_iovec.iov_base = x.as_mut_ptr() as *mut _;
-                    local_iovec.iov_len = 8;
-                    let mut remote_iovec: libc::iovec = std::mem::zeroed();
-                    remote_iovec.iov_base = fault_addr as *mut _;
-                    remote_iovec.iov_len = 8;
-                    if libc::process_vm_readv(id as i32, &local_iovec, 1, &remote_iovec, 1, 0) != 8
-                    {
-                        return Err(anyhow!(
-                            "unable to read memory at 0x{:x}/{}",
-                            fault_addr,
-                            fault_bit
-                        ));
-                    }
-                    x[0] ^= 1 << fault_bit;
-                    if libc::process_vm_writev(id as i32, &local_iovec, 1, &remote_iovec, 1, 0) != 8
-                    {
-                        return Err(anyhow!(
-                            "unable to write memory at 0x{:x}/{}",
-                            fault_addr,
-                            fault_bit
-                        ));
-                    }
+            if injector
+                .step(&mut target, radiation, Instant::now())?
+                .is_some()
+            {
+                injected += 1;
+            }
+        }
+    }
+
+    Ok(injected)
+}
+
+/// Build the expression matching the firmware's protected state location line.
+fn state_location_regex() -> Result<Regex> {
+    Ok(Regex::new(
+        r"protected state: addr=0x([[:xdigit:]]{16}) size=0x([[:xdigit:]]{16})(?:\s|$)",
+    )?)
+}
+
+/// Parse the protected state address and size from a firmware log line.
+fn parse_state_location(addr_re: &Regex, line: &str) -> Option<(u64, u64)> {
+    let m = addr_re.captures(line)?;
+    let addr = u64::from_str_radix(m.get(1)?.as_str(), 16).ok()?;
+    let size = u64::from_str_radix(m.get(2)?.as_str(), 16).ok()?;
+    if addr == 0 || size == 0 {
+        return None;
+    }
+    Some((addr, size))
+}
+
+/// Fault model.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FaultModel {
+    /// Flip a single bit in a word
+    SingleBit,
+    /// Flip several distinct bits in a word
+    MultiBit { bits: u32 },
+    /// Force a bit to a fixed value for a duration
+    StuckBit { duration: Duration },
+    /// Flip a run of adjacent bits in a word, up to a length scaled by radiation
+    Burst { bits: u32 },
+}
+
+impl FromStr for FaultModel {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let mut parts = s.splitn(2, ':');
+        let name = parts.next().unwrap_or("");
+        let arg = parts.next();
+        match (name, arg) {
+            ("single", None) => Ok(FaultModel::SingleBit),
+            ("multi", arg) => {
+                let bits = arg
+                    .map(|x| x.parse())
+                    .unwrap_or(Ok(2))
+                    .map_err(|e| format!("invalid bit count: {}", e))?;
+                if bits == 0 || bits > 64 {
+                    return Err(format!("bit count {} out of range", bits));
+                }
+                Ok(FaultModel::MultiBit { bits })
+            }
+            ("burst", arg) => {
+                let bits = arg
+                    .map(|x| x.parse())
+                    .unwrap_or(Ok(1))
+                    .map_err(|e| format!("invalid burst size: {}", e))?;
+                if bits == 0 || bits > 64 {
+                    return Err(format!("burst size {} out of range", bits));
+                }
+                Ok(FaultModel::Burst { bits })
+            }
+            ("stuck", arg) => {
+                let secs = arg
+                    .map(|x| x.parse())
+                    .unwrap_or(Ok(10))
+                    .map_err(|e| format!("invalid duration: {}", e))?;
+                Ok(FaultModel::StuckBit {
+                    duration: Duration::from_secs(secs),
+                })
+            }
+            _ => Err(format!("unknown fault model {}", s)),
+        }
+    }
+}
+
+/// Memory fault.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Fault {
+    /// Word address
+    pub addr: u64,
+    /// Affected bits
+    pub mask: u64,
+    /// Stuck bit value, or None to flip the affected bits
+    pub stuck: Option<bool>,
+}
+
+/// Fault injection target.
+pub trait FaultTarget {
+    /// Read a word.
+    fn read(&mut self, addr: u64) -> Result<u64>;
+
+    /// Write a word.
+    fn write(&mut self, addr: u64, value: u64) -> Result<()>;
+}
+
+/// Remote process memory.
+struct ProcessMemory {
+    pid: libc::pid_t,
+}
+
+impl FaultTarget for ProcessMemory {
+    fn read(&mut self, addr: u64) -> Result<u64> {
+        let mut x: [u64; 1] = [0];
+        unsafe {
+            let mut local_iovec: libc::iovec = std::mem::zeroed();
+            local_iovec.iov_base = x.as_mut_ptr() as *mut _;
+            local_iovec.iov_len = 8;
+            let mut remote_iovec: libc::iovec = std::mem::zeroed();
+            remote_iovec.iov_base = addr as *mut _;
+            remote_iovec.iov_len = 8;
+            if libc::process_vm_readv(self.pid, &local_iovec, 1, &remote_iovec, 1, 0) != 8 {
+                return Err(anyhow!("unable to read memory at 0x{:x}", addr));
+            }
+        }
+        Ok(x[0])
+    }
+
+    fn write(&mut self, addr: u64, value: u64) -> Result<()> {
+        let mut x: [u64; 1] = [value];
+        unsafe {
+            let mut local_iovec: libc::iovec = std::mem::zeroed();
+            local_iovec.iov_base = x.as_mut_ptr() as *mut _;
+            local_iovec.iov_len = 8;
+            let mut remote_iovec: libc::iovec = std::mem::zeroed();
+            remote_iovec.iov_base = addr as *mut _;
+            remote_iovec.iov_len = 8;
+            if libc::process_vm_writev(self.pid, &local_iovec, 1, &remote_iovec, 1, 0) != 8 {
+                return Err(anyhow!("unable to write memory at 0x{:x}", addr));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Fault injector.
+pub struct Injector<R> {
+    model: FaultModel,
+    rng: R,
+    state_addr: u64,
+    state_size: u64,
+    stuck: Vec<(Fault, Instant)>,
+}
+
+impl<R> Injector<R>
+where
+    R: Rng,
+{
+    /// Create a new injector over a memory range.
+    pub fn new(model: FaultModel, rng: R, state_addr: u64, state_size: u64) -> Self {
+        Self {
+            model,
+            rng,
+            state_addr,
+            state_size,
+            stuck: vec![],
+        }
+    }
+
+    /// Choose the next fault, if any, for a radiation level.
+    pub fn plan(&mut self, radiation: usize) -> Option<Fault> {
+        if self.rng.gen_range(0..300) >= radiation {
+            return None;
+        }
+
+        let addr = self
+            .rng
+            .gen_range(self.state_addr..(self.state_addr + self.state_size))
+            & (!0x0f);
+        let (mask, stuck) = match self.model {
+            FaultModel::SingleBit => (1u64 << self.rng.gen_range(0..64), None),
+            FaultModel::MultiBit { bits } => {
+                let mut mask = 0u64;
+                while mask.count_ones() < bits {
+                    mask |= 1u64 << self.rng.gen_range(0..64);
                 }
+                (mask, None)
+            }
+            FaultModel::StuckBit { .. } => (
+                1u64 << self.rng.gen_range(0..64),
+                Some(self.rng.gen::<bool>()),
+            ),
+            FaultModel::Burst { bits } => {
+                let length = burst_length(bits, radiation);
+                let run = if length == 64 {
+                    !0
+                } else {
+                    (1u64 << length) - 1
+                };
+                (run << self.rng.gen_range(0..=(64 - length)), None)
             }
+        };
+        Some(Fault { addr, mask, stuck })
+    }
+
+    /// Inject the next fault, if any, and reapply stuck bits.
+    pub fn step<T>(
+        &mut self,
+        target: &mut T,
+        radiation: usize,
+        now: Instant,
+    ) -> Result<Option<Fault>>
+    where
+        T: FaultTarget,
+    {
+        self.stuck.retain(|(_, until)| *until > now);
+        for (fault, _) in &self.stuck {
+            apply_fault(target, fault)?;
         }
+
+        let fault = self.plan(radiation);
+        if let Some(fault) = fault {
+            // debug!("injecting fault {:?}", fault);
+            apply_fault(target, &fault)?;
+            if let FaultModel::StuckBit { duration } = self.model {
+                self.stuck.push((fault, now + duration));
+            }
+        }
+        Ok(fault)
     }
+}
+
+/// Scale a burst length with radiation, from a single bit up to the configured size.
+fn burst_length(bits: u32, radiation: usize) -> u32 {
+    let length = (bits as usize * radiation + 299) / 300;
+    length.max(1).min(bits as usize) as u32
+}
 
+/// Apply a fault to a target.
+fn apply_fault<T>(target: &mut T, fault: &Fault) -> Result<()>
+where
+    T: FaultTarget,
+{
+    let x = target.read(fault.addr)?;
+    let y = match fault.stuck {
+        Some(true) => x | fault.mask,
+        Some(false) => x & !fault.mask,
+        None => x ^ fault.mask,
+    };
+    if x != y {
+        target.write(fault.addr, y)?;
+    }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_state_location() {
+        let addr_re = state_location_regex().expect("regex");
+        let line = format!(
+            "[2021-04-30T00:00:00Z INFO  rad_fw] {}",
+            rad_common::format_state_location(0x7f12_3456_7000, 0x26000)
+        );
+        assert_eq!(
+            parse_state_location(&addr_re, &line),
+            Some((0x7f12_3456_7000, 0x26000))
+        );
+
+        // Truncated in the pipe
+        let truncated = &line[..line.len() - 4];
+        assert!(truncated.contains(STATE_LOCATION_LABEL));
+        assert_eq!(parse_state_location(&addr_re, truncated), None);
+
+        // The old debug pointer format
+        let old = "loaded protected state at 0x7f1234567000-0x7f123458d000";
+        assert_eq!(parse_state_location(&addr_re, old), None);
+    }
+
+    /// Flat test memory starting at address zero.
+    struct Memory(Vec<u64>);
+
+    impl FaultTarget for Memory {
+        fn read(&mut self, addr: u64) -> Result<u64> {
+            Ok(self.0[(addr / 8) as usize])
+        }
+
+        fn write(&mut self, addr: u64, value: u64) -> Result<()> {
+            self.0[(addr / 8) as usize] = value;
+            Ok(())
+        }
+    }
+
+    /// Count the 4-byte halves of a word touched by a fault.
+    fn halves_touched(fault: &Fault) -> u32 {
+        ((fault.mask & 0xffff_ffff) != 0) as u32 + ((fault.mask >> 32) != 0) as u32
+    }
+
+    #[tokio::test]
+    async fn test_disable_faults() {
+        let mut p = Command::new("sh")
+            .arg("-c")
+            .arg(format!(
+                "echo '{}' >&2; sleep 10",
+                rad_common::format_state_location(0x1000, 0x1000)
+            ))
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .expect("spawn");
+        let id = p.id().expect("pid");
+        let (stdout, stderr) = (p.stdout.take().unwrap(), p.stderr.take().unwrap());
+
+        // The injector returns without a fault, even at a radiation level that faults every step
+        let radiation = std::mem::replace(&mut *RAD.lock().unwrap(), 300.0);
+        let faults = FaultConfig {
+            model: FaultModel::SingleBit,
+            seed: 0x5eed,
+            disabled: true,
+        };
+        let injected = timeout(
+            Duration::from_secs(5),
+            inject_faults(id, stdout, stderr, faults),
+        )
+        .await;
+        *RAD.lock().unwrap() = radiation;
+        let injected = injected.expect("injector stopped").expect("inject faults");
+        assert_eq!(injected, 0);
+    }
+
+    #[test]
+    fn test_parse_fault_model() {
+        assert_eq!(FaultModel::SingleBit, "single".parse().unwrap());
+        assert_eq!(FaultModel::MultiBit { bits: 3 }, "multi:3".parse().unwrap());
+        assert_eq!(
+            FaultModel::StuckBit {
+                duration: Duration::from_secs(5)
+            },
+            "stuck:5".parse().unwrap()
+        );
+        assert_eq!(FaultModel::Burst { bits: 1 }, "burst".parse().unwrap());
+        assert_eq!(FaultModel::Burst { bits: 3 }, "burst:3".parse().unwrap());
+        assert!("burst:65".parse::<FaultModel>().is_err());
+        assert!("multi:0".parse::<FaultModel>().is_err());
+        assert!("gamma".parse::<FaultModel>().is_err());
+    }
+
+    #[test]
+    fn test_multi_bit_spans_halves() {
+        let now = Instant::now();
+        let mut memory = Memory(vec![0; 512]);
+        let rng = StdRng::seed_from_u64(0x5eed);
+        let mut single = Injector::new(FaultModel::SingleBit, rng, 0, 4096);
+        for _ in 0..1000 {
+            let fault = single.step(&mut memory, 300, now).unwrap().unwrap();
+            assert_eq!(1, halves_touched(&fault));
+        }
+
+        let rng = StdRng::seed_from_u64(0x5eed);
+        let mut multi = Injector::new(FaultModel::MultiBit { bits: 3 }, rng, 0, 4096);
+        // Unlike single-bit faults, multi-bit ones can land in both halves of a word
+        let mut spanning = 0;
+        for _ in 0..1000 {
+            let fault = multi.step(&mut memory, 300, now).unwrap().unwrap();
+            assert_eq!(3, fault.mask.count_ones());
+            if halves_touched(&fault) > 1 {
+                spanning += 1;
+            }
+        }
+        assert!(spanning > 0);
+    }
+
+    #[test]
+    fn test_seeded_faults() {
+        let now = Instant::now();
+        let faults = |seed| {
+            let mut memory = Memory(vec![0; 512]);
+            let rng = StdRng::seed_from_u64(seed);
+            let mut injector = Injector::new(FaultModel::MultiBit { bits: 2 }, rng, 0, 4096);
+            (0..1000)
+                .map(|_| injector.step(&mut memory, 150, now).unwrap())
+                .collect::<Vec<_>>()
+        };
+        let a = faults(0x5eed);
+        assert!(a.iter().any(Option::is_some));
+        assert!(a.iter().any(Option::is_none));
+        assert_eq!(a, faults(0x5eed));
+        assert_ne!(a, faults(0x5eee));
+    }
+
+    #[test]
+    fn test_burst() {
+        let now = Instant::now();
+        let mut memory = Memory(vec![0; 512]);
+        let rng = StdRng::seed_from_u64(0x5eed);
+        let mut injector = Injector::new(FaultModel::Burst { bits: 3 }, rng, 0, 4096);
+        for _ in 0..100 {
+            let fault = injector.step(&mut memory, 300, now).unwrap().unwrap();
+            let offset = fault.mask.trailing_zeros();
+            assert_eq!(fault.mask, 0b111 << offset);
+
+            // Exactly the three adjacent bits at the offset flipped
+            let x = memory.read(fault.addr).unwrap();
+            assert_eq!((x >> offset) & 0b111, 0b111);
+            memory.write(fault.addr, x ^ fault.mask).unwrap();
+            assert!(memory.0.iter().all(|x| *x == 0));
+        }
+
+        // Weaker radiation produces shorter bursts
+        assert_eq!(burst_length(3, 50), 1);
+        assert_eq!(burst_length(3, 150), 2);
+        assert_eq!(burst_length(64, 300), 64);
+    }
+
+    #[test]
+    fn test_stuck_bit() {
+        let now = Instant::now();
+        let duration = Duration::from_secs(10);
+        let mut memory = Memory(vec![0; 512]);
+        let rng = StdRng::seed_from_u64(0x5eed);
+        let mut injector = Injector::new(FaultModel::StuckBit { duration }, rng, 0, 4096);
+        let fault = injector.step(&mut memory, 300, now).unwrap().unwrap();
+
+        // Scrubbing the word is undone while the bit is stuck
+        let stuck = fault.stuck.unwrap();
+        memory
+            .write(fault.addr, if stuck { 0 } else { !0 })
+            .unwrap();
+        injector.step(&mut memory, 0, now).unwrap();
+        let x = memory.read(fault.addr).unwrap();
+        assert_eq!(stuck, x & fault.mask != 0);
+
+        // ...but not after it expires
+        memory
+            .write(fault.addr, if stuck { 0 } else { !0 })
+            .unwrap();
+        injector.step(&mut memory, 0, now + duration).unwrap();
+        let x = memory.read(fault.addr).unwrap();
+        assert_eq!(stuck, x & fault.mask == 0);
+    }
+}
@@ -0,0 +1,38 @@
+//! Orbit geometry.
+
+/// Derive periapsis and apoapsis altitudes (km) and the orbital period (sec) from the semi-major
+/// axis (km) and eccentricity; open orbits have no apoapsis or period and report infinity.
+pub fn orbit_summary(sma: f64, ecc: f64, gm: f64, radius: f64) -> (f64, f64, f64) {
+    let periapsis = sma * (1.0 - ecc) - radius;
+    if ecc >= 1.0 {
+        return (periapsis, f64::INFINITY, f64::INFINITY);
+    }
+    let apoapsis = sma * (1.0 + ecc) - radius;
+    let period = 2.0 * std::f64::consts::PI * (sma.powi(3) / gm).sqrt();
+    (periapsis, apoapsis, period)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MIN_ALTITUDE;
+
+    #[test]
+    fn test_orbit_summary() {
+        let (gm, radius) = (398600.4415, 6378.1363);
+        let (periapsis, apoapsis, period) = orbit_summary(7000.0, 0.01, gm, radius);
+        assert!((periapsis - 551.8637).abs() < 1e-6);
+        assert!((apoapsis - 691.8637).abs() < 1e-6);
+        assert!((period - 5828.5166).abs() < 1e-3);
+        assert!(periapsis >= MIN_ALTITUDE);
+
+        // Periapsis inside the atmosphere
+        let (periapsis, _, _) = orbit_summary(6500.0, 0.02, gm, radius);
+        assert!(periapsis < MIN_ALTITUDE);
+
+        // Escape trajectory
+        let (periapsis, apoapsis, period) = orbit_summary(-20000.0, 1.5, gm, radius);
+        assert!((periapsis - 3621.8637).abs() < 1e-6);
+        assert!(apoapsis.is_infinite() && period.is_infinite());
+    }
+}
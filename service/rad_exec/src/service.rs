@@ -1,15 +1,58 @@
 //! Service channel.
 
-use crate::{BURNS, RAD, STATE};
+use crate::config::env_or;
+use crate::orbit::orbit_summary;
+use crate::{
+    abort_burns, sim_time, unix_time, BURNS, ECLIPSE, FUEL_GAUGE, HISTORY, MANEUVERS, MIN_ALTITUDE,
+    RAD, STATE,
+};
 use anyhow::{anyhow, Context, Result};
-use rad_common::{ExecutiveRequest, ExecutiveResponse, CHECKPOINT_PATH, SERVICE_PATH};
+use rad_common::frame::{read_framed, MAX_CHECKPOINT_FRAME_SIZE};
+use rad_common::message;
+use rad_common::{
+    checkpoint_generation_path, Burn, Eclipse, ExecutiveRequest, ExecutiveResponse, Pass,
+    CHECKPOINT_GENERATIONS, CHECKPOINT_PATH, MAX_BURNS, SERVICE_PATH,
+};
+use ring::rand::SystemRandom;
+use ring::signature::{Ed25519KeyPair, KeyPair};
 use std::io::Write;
 use std::path::Path;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use std::sync::Arc;
+use tokio::io::AsyncWriteExt;
 use tokio::net::{UnixListener, UnixStream};
 
+/// Mean solar radius (km).
+const SUN_RADIUS: f64 = 695_700.0;
+/// Maximum age of a burn start time when the schedule is received (sec).
+const MAX_BURN_AGE: u64 = 60;
+/// Earth's rotation rate (rad/sec).
+const EARTH_ROTATION_RATE: f64 = 7.292_115_146_7e-5;
+/// Propagation step when searching for a ground station pass (sec).
+const PASS_STEP: f64 = 10.0;
+/// How far ahead to search for a ground station pass (sec).
+const PASS_HORIZON: f64 = 86400.0;
+
+/// Load the checkpoint signing keys, generating them on first use.
+///
+/// These keys only sign checkpoints.  The public key is written next to them for the firmware.
+pub fn checkpoint_keys(keys_path: &Path, pub_key_path: &Path) -> Result<Ed25519KeyPair> {
+    let keys = if keys_path.is_file() {
+        let doc = std::fs::read(keys_path).context("read checkpoint keys")?;
+        Ed25519KeyPair::from_pkcs8(&doc).map_err(|_| anyhow!("invalid checkpoint keys"))?
+    } else {
+        info!("generating checkpoint keys at {}", keys_path.display());
+        let doc = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new())
+            .map_err(|_| anyhow!("generate checkpoint keys"))?;
+        write_checkpoint(keys_path, doc.as_ref()).context("store checkpoint keys")?;
+        Ed25519KeyPair::from_pkcs8(doc.as_ref()).map_err(|_| anyhow!("invalid checkpoint keys"))?
+    };
+    write_checkpoint(pub_key_path, keys.public_key().as_ref())
+        .context("store checkpoint public key")?;
+    Ok(keys)
+}
+
 /// Process firmware connections.
-pub async fn process_connections() -> Result<()> {
+pub async fn process_connections(keys: Arc<Ed25519KeyPair>) -> Result<()> {
     info!("listening for firmware requests on {}", SERVICE_PATH);
     let service_path = Path::new(SERVICE_PATH);
     if service_path.exists() {
@@ -18,107 +61,741 @@ pub async fn process_connections() -> Result<()> {
     let listener = UnixListener::bind(service_path)?;
     loop {
         let (socket, _address) = listener.accept().await?;
-        if let Err(e) = process_connection(socket).await {
+        if let Err(e) = process_connection(socket, &keys).await {
             error!("service firmware connection: {}", e);
         }
     }
 }
 
 /// Process a firmware connection.
-async fn process_connection(mut socket: UnixStream) -> Result<()> {
+async fn process_connection(mut socket: UnixStream, keys: &Ed25519KeyPair) -> Result<()> {
     info!("processing firmware service connection");
+    let mut buffer = vec![];
     loop {
-        let size = socket.read_u32().await.context("receive request size")?;
-        let mut buffer = vec![0u8; size as _];
-        socket
-            .read_exact(&mut buffer)
+        read_framed(&mut socket, &mut buffer, MAX_CHECKPOINT_FRAME_SIZE)
             .await
             .context("receive request")?;
-        let request: ExecutiveRequest = bincode::deserialize(&buffer).context("decode request")?;
+        let request: ExecutiveRequest = message::decode(&buffer).context("decode request")?;
         debug!("firmware request: {}", request);
 
         let response = match request {
-            ExecutiveRequest::Checkpoint { state } => {
-                let mut output =
-                    tempfile::NamedTempFile::new().context("create temporary checkpoint")?;
-                output
-                    .write_all(&state)
-                    .context("write temporary checkpoint")?;
-                // output
-                //     .persist(CHECKPOINT_PATH)
-                //     .context("persist checkpoint")?;
-                output.flush().context("flush temporary checkpoint")?;
-                std::fs::copy(output, CHECKPOINT_PATH).context("persist checkpoint")?;
-                ExecutiveResponse::Checkpoint { success: true }
+            ExecutiveRequest::Checkpoint { state } => store_checkpoint(keys, state)?,
+            request => handle_request(request)?,
+        };
+        buffer.clear();
+        message::encode_into(&mut buffer, &response).context("encode response")?;
+        socket
+            .write_u32(buffer.len() as _)
+            .await
+            .context("send response size")?;
+        socket.write_all(&buffer).await.context("send response")?;
+    }
+}
+
+/// Handle a firmware request.
+fn handle_request(request: ExecutiveRequest) -> Result<ExecutiveResponse> {
+    let response = match request {
+        ExecutiveRequest::Checkpoint { .. } => {
+            return Err(anyhow!("checkpoint request outside a firmware connection"));
+        }
+        ExecutiveRequest::PositionVelocity => {
+            if let Ok(Some(state)) = STATE.lock().map(|x| *x) {
+                ExecutiveResponse::PositionVelocity {
+                    success: true,
+                    t: unix_time(state.orbit.dt),
+                    p: (state.orbit.x, state.orbit.y, state.orbit.z),
+                    v: (state.orbit.vx, state.orbit.vy, state.orbit.vz),
+                }
+            } else {
+                ExecutiveResponse::PositionVelocity {
+                    success: false,
+                    t: 0,
+                    p: (0.0, 0.0, 0.0),
+                    v: (0.0, 0.0, 0.0),
+                }
             }
-            ExecutiveRequest::PositionVelocity => {
-                if let Ok(Some(state)) = STATE.lock().map(|x| *x) {
-                    ExecutiveResponse::PositionVelocity {
-                        success: true,
-                        t: state.orbit.dt.as_utc_seconds() as u64,
-                        p: (state.orbit.x, state.orbit.y, state.orbit.z),
-                        v: (state.orbit.vx, state.orbit.vy, state.orbit.vz),
-                    }
-                } else {
-                    ExecutiveResponse::PositionVelocity {
-                        success: false,
-                        t: 0,
-                        p: (0.0, 0.0, 0.0),
-                        v: (0.0, 0.0, 0.0),
-                    }
+        }
+        ExecutiveRequest::KeplerianElements => {
+            if let Ok(Some(state)) = STATE.lock().map(|x| *x) {
+                ExecutiveResponse::KeplerianElements {
+                    success: true,
+                    dt: unix_time(state.orbit.dt),
+                    sma: state.orbit.sma(),
+                    ecc: state.orbit.ecc(),
+                    inc: state.orbit.inc(),
+                    raan: state.orbit.raan(),
+                    aop: state.orbit.aop(),
+                    ta: state.orbit.ta(),
+                }
+            } else {
+                ExecutiveResponse::KeplerianElements {
+                    success: false,
+                    dt: 0,
+                    sma: 0.0,
+                    ecc: 0.0,
+                    inc: 0.0,
+                    raan: 0.0,
+                    aop: 0.0,
+                    ta: 0.0,
                 }
             }
-            ExecutiveRequest::KeplerianElements => {
-                if let Ok(Some(state)) = STATE.lock().map(|x| *x) {
-                    ExecutiveResponse::KeplerianElements {
+        }
+        ExecutiveRequest::OrbitSummary => {
+            if let Ok(Some(state)) = STATE.lock().map(|x| *x) {
+                let (periapsis_altitude, apoapsis_altitude, period) = orbit_summary(
+                    state.orbit.sma(),
+                    state.orbit.ecc(),
+                    state.orbit.frame.gm(),
+                    state.orbit.frame.equatorial_radius(),
+                );
+                ExecutiveResponse::OrbitSummary {
+                    success: true,
+                    periapsis_altitude,
+                    apoapsis_altitude,
+                    period,
+                    periapsis_unsafe: periapsis_altitude < MIN_ALTITUDE,
+                }
+            } else {
+                ExecutiveResponse::OrbitSummary {
+                    success: false,
+                    periapsis_altitude: 0.0,
+                    apoapsis_altitude: 0.0,
+                    period: 0.0,
+                    periapsis_unsafe: false,
+                }
+            }
+        }
+        ExecutiveRequest::NextPass {
+            station_lat,
+            station_lon,
+            min_elevation,
+        } => {
+            let valid = (-90.0..=90.0).contains(&station_lat)
+                && station_lon.is_finite()
+                && (0.0..90.0).contains(&min_elevation);
+            match STATE.lock().map(|x| *x) {
+                Ok(Some(state)) if valid => {
+                    let t = unix_time(state.orbit.dt);
+                    let pass = next_pass(
+                        (state.orbit.x, state.orbit.y, state.orbit.z),
+                        (state.orbit.vx, state.orbit.vy, state.orbit.vz),
+                        state.orbit.frame.gm(),
+                        state.orbit.frame.equatorial_radius(),
+                        earth_rotation_angle(state.orbit.dt.as_utc_seconds()),
+                        (station_lat, station_lon),
+                        min_elevation,
+                    );
+                    ExecutiveResponse::NextPass {
                         success: true,
-                        dt: state.orbit.dt.as_utc_seconds() as u64,
-                        sma: state.orbit.sma(),
-                        ecc: state.orbit.ecc(),
-                        inc: state.orbit.inc(),
-                        raan: state.orbit.raan(),
-                        aop: state.orbit.aop(),
-                        ta: state.orbit.ta(),
-                    }
-                } else {
-                    ExecutiveResponse::KeplerianElements {
-                        success: false,
-                        dt: 0,
-                        sma: 0.0,
-                        ecc: 0.0,
-                        inc: 0.0,
-                        raan: 0.0,
-                        aop: 0.0,
-                        ta: 0.0,
+                        pass: pass.map(|(aos, los, max_elevation)| {
+                            Pass::new(t + aos as u64, t + los as u64, max_elevation)
+                        }),
                     }
                 }
+                _ => ExecutiveResponse::NextPass {
+                    success: false,
+                    pass: None,
+                },
             }
-            ExecutiveRequest::Sensors => {
-                if let Ok(Some(state)) = STATE.lock().map(|x| *x) {
-                    ExecutiveResponse::Sensors {
+        }
+        ExecutiveRequest::Sensors => {
+            if let Ok(Some(state)) = STATE.lock().map(|x| *x) {
+                ExecutiveResponse::Sensors {
+                    success: true,
+                    fuel: state.fuel_mass,
+                    radiation: *RAD.lock().map_err(|_| anyhow!("flux lock"))?,
+                    eclipse: *ECLIPSE.lock().map_err(|_| anyhow!("eclipse lock"))?,
+                }
+            } else {
+                ExecutiveResponse::Sensors {
+                    success: false,
+                    fuel: 0.0,
+                    radiation: 0.0,
+                    eclipse: Eclipse::Sunlit,
+                }
+            }
+        }
+        ExecutiveRequest::Maneuver { burns } => {
+            let now = sim_time();
+            let last_burn_fuel = FUEL_GAUGE
+                .lock()
+                .map_err(|_| anyhow!("fuel gauge lock"))?
+                .last_burn();
+            match validate_burns(&burns, now) {
+                Ok(()) => {
+                    debug!("setting burn schedule: {:#?}", burns);
+                    *BURNS.lock().map_err(|_| anyhow!("burns lock"))? = Some(burns);
+                    ExecutiveResponse::Maneuver {
                         success: true,
-                        fuel: state.fuel_mass,
-                        radiation: *RAD.lock().map_err(|_| anyhow!("flux lock"))?,
+                        last_burn_fuel,
                     }
-                } else {
-                    ExecutiveResponse::Sensors {
+                }
+                Err(e) => {
+                    warn!("rejecting burn schedule: {}", e);
+                    ExecutiveResponse::Maneuver {
                         success: false,
-                        fuel: 0.0,
-                        radiation: 0.0,
+                        last_burn_fuel,
                     }
                 }
             }
-            ExecutiveRequest::Maneuver { burns } => {
-                debug!("setting burn schedule: {:#?}", burns);
-                *BURNS.lock().map_err(|_| anyhow!("burns lock"))? = Some(burns);
-                ExecutiveResponse::Maneuver { success: true }
+        }
+        ExecutiveRequest::ManeuverHistory => {
+            if let Ok(maneuvers) = MANEUVERS.lock() {
+                ExecutiveResponse::ManeuverHistory {
+                    success: true,
+                    maneuvers: maneuvers.iter().cloned().collect(),
+                }
+            } else {
+                ExecutiveResponse::ManeuverHistory {
+                    success: false,
+                    maneuvers: vec![],
+                }
+            }
+        }
+        ExecutiveRequest::SensorHistory { since } => {
+            if let Ok(history) = HISTORY.lock() {
+                ExecutiveResponse::SensorHistory {
+                    success: true,
+                    samples: history
+                        .iter()
+                        .filter(|x| x.timestamp > since)
+                        .cloned()
+                        .collect(),
+                }
+            } else {
+                ExecutiveResponse::SensorHistory {
+                    success: false,
+                    samples: vec![],
+                }
+            }
+        }
+        ExecutiveRequest::AbortManeuver => {
+            let now = sim_time();
+            let aborted = abort_burns(now)?;
+            info!("abort maneuver: aborted={}", aborted);
+            ExecutiveResponse::AbortManeuver {
+                success: true,
+                aborted,
+            }
+        }
+        ExecutiveRequest::Telemetry => {
+            if let Ok(Some(state)) = STATE.lock().map(|x| *x) {
+                ExecutiveResponse::Telemetry {
+                    success: true,
+                    t: unix_time(state.orbit.dt),
+                    p: (state.orbit.x, state.orbit.y, state.orbit.z),
+                    v: (state.orbit.vx, state.orbit.vy, state.orbit.vz),
+                    fuel: state.fuel_mass,
+                    radiation: *RAD.lock().map_err(|_| anyhow!("flux lock"))?,
+                    eclipse: *ECLIPSE.lock().map_err(|_| anyhow!("eclipse lock"))?,
+                }
+            } else {
+                ExecutiveResponse::Telemetry {
+                    success: false,
+                    t: 0,
+                    p: (0.0, 0.0, 0.0),
+                    v: (0.0, 0.0, 0.0),
+                    fuel: 0.0,
+                    radiation: 0.0,
+                    eclipse: Eclipse::Sunlit,
+                }
             }
+        }
+    };
+    Ok(response)
+}
+
+/// Classify the spacecraft's illumination with a conical shadow model, given the spacecraft and
+/// Sun positions relative to Earth's center and Earth's radius (km).
+pub fn eclipse_state(position: (f64, f64, f64), sun: (f64, f64, f64), radius: f64) -> Eclipse {
+    let norm = |v: (f64, f64, f64)| (v.0 * v.0 + v.1 * v.1 + v.2 * v.2).sqrt();
+    let to_sun = (sun.0 - position.0, sun.1 - position.1, sun.2 - position.2);
+    let (sun_distance, earth_distance) = (norm(to_sun), norm(position));
+
+    // Apparent radii of the Sun and Earth and their separation, as seen from the spacecraft
+    let sun_radius = (SUN_RADIUS / sun_distance).asin();
+    let earth_radius = (radius / earth_distance).min(1.0).asin();
+    let separation = (-(position.0 * to_sun.0 + position.1 * to_sun.1 + position.2 * to_sun.2)
+        / (earth_distance * sun_distance))
+        .clamp(-1.0, 1.0)
+        .acos();
+
+    if separation >= sun_radius + earth_radius {
+        Eclipse::Sunlit
+    } else if separation <= earth_radius - sun_radius {
+        Eclipse::Umbra
+    } else {
+        Eclipse::Penumbra
+    }
+}
+
+/// Earth rotation angle (rad) at a UTC epoch given in seconds since 1900.
+fn earth_rotation_angle(utc_seconds: f64) -> f64 {
+    let days = 2_415_020.5 + utc_seconds / 86400.0 - 2_451_545.0;
+    let turns = 0.779_057_273_264 + 1.002_737_811_911_354_5 * days;
+    turns.rem_euclid(1.0) * 2.0 * std::f64::consts::PI
+}
+
+/// Advance a two-body orbit one fourth-order Runge-Kutta step of `dt` (sec).
+fn two_body_step(
+    position: (f64, f64, f64),
+    velocity: (f64, f64, f64),
+    gm: f64,
+    dt: f64,
+) -> ((f64, f64, f64), (f64, f64, f64)) {
+    let acceleration = |p: (f64, f64, f64)| {
+        let r = (p.0 * p.0 + p.1 * p.1 + p.2 * p.2).sqrt();
+        let k = -gm / (r * r * r);
+        (k * p.0, k * p.1, k * p.2)
+    };
+    let add = |a: (f64, f64, f64), b: (f64, f64, f64), h: f64| {
+        (a.0 + h * b.0, a.1 + h * b.1, a.2 + h * b.2)
+    };
+    let (k1p, k1v) = (velocity, acceleration(position));
+    let (k2p, k2v) = (
+        add(velocity, k1v, dt / 2.0),
+        acceleration(add(position, k1p, dt / 2.0)),
+    );
+    let (k3p, k3v) = (
+        add(velocity, k2v, dt / 2.0),
+        acceleration(add(position, k2p, dt / 2.0)),
+    );
+    let (k4p, k4v) = (add(velocity, k3v, dt), acceleration(add(position, k3p, dt)));
+    let combine = |x: (f64, f64, f64), k: [(f64, f64, f64); 4]| {
+        let sum = add(add(add(k[0], k[1], 2.0), k[2], 2.0), k[3], 1.0);
+        add(x, sum, dt / 6.0)
+    };
+    (
+        combine(position, [k1p, k2p, k3p, k4p]),
+        combine(velocity, [k1v, k2v, k3v, k4v]),
+    )
+}
+
+/// Find the next pass of the spacecraft above `min_elevation` (deg) over a station at
+/// (latitude, longitude) (deg), propagating the inertial position (km) and velocity (km/s) as a
+/// two-body orbit over a spherical Earth turned `rotation` (rad) at the start. Returns the AOS and
+/// LOS offsets (sec) and maximum elevation (deg); a pass still in progress at the search horizon
+/// ends there.
+fn next_pass(
+    position: (f64, f64, f64),
+    velocity: (f64, f64, f64),
+    gm: f64,
+    radius: f64,
+    rotation: f64,
+    station: (f64, f64),
+    min_elevation: f64,
+) -> Option<(f64, f64, f64)> {
+    let (latitude, longitude) = (station.0.to_radians(), station.1.to_radians());
+    let elevation = |t: f64, p: (f64, f64, f64)| {
+        let angle = longitude + rotation + EARTH_ROTATION_RATE * t;
+        let up = (
+            latitude.cos() * angle.cos(),
+            latitude.cos() * angle.sin(),
+            latitude.sin(),
+        );
+        let range = (
+            p.0 - radius * up.0,
+            p.1 - radius * up.1,
+            p.2 - radius * up.2,
+        );
+        let distance = (range.0 * range.0 + range.1 * range.1 + range.2 * range.2).sqrt();
+        ((range.0 * up.0 + range.1 * up.1 + range.2 * up.2) / distance)
+            .asin()
+            .to_degrees()
+    };
+
+    let (mut p, mut v) = (position, velocity);
+    let mut pass: Option<(f64, f64)> = None;
+    let mut t = 0.0;
+    while t <= PASS_HORIZON {
+        if (p.0 * p.0 + p.1 * p.1 + p.2 * p.2).sqrt() <= radius {
+            break;
+        }
+        let e = elevation(t, p);
+        match pass {
+            None if e >= min_elevation => pass = Some((t, e)),
+            Some((aos, max)) if e >= min_elevation => pass = Some((aos, max.max(e))),
+            Some((aos, max)) => return Some((aos, t, max)),
+            None => {}
+        }
+        let next = two_body_step(p, v, gm, PASS_STEP);
+        p = next.0;
+        v = next.1;
+        t += PASS_STEP;
+    }
+    pass.map(|(aos, max)| (aos, t.min(PASS_HORIZON), max))
+}
+
+/// Check that a burn schedule is safe to hand to the propagator.
+fn validate_burns(burns: &[Burn], now: u64) -> Result<()> {
+    if burns.len() > MAX_BURNS {
+        return Err(anyhow!(
+            "{} burns exceeds limit of {}",
+            burns.len(),
+            MAX_BURNS
+        ));
+    }
+    for (i, burn) in burns.iter().enumerate() {
+        burn.validate().map_err(|e| anyhow!("burn {}: {}", i, e))?;
+        if burn.start.saturating_add(MAX_BURN_AGE) < now {
+            return Err(anyhow!(
+                "burn {} start {} is more than {}s stale",
+                i,
+                burn.start,
+                MAX_BURN_AGE
+            ));
+        }
+    }
+
+    let mut windows: Vec<_> = burns
+        .iter()
+        .map(|b| (b.start, b.start.saturating_add(b.length as u64)))
+        .collect();
+    windows.sort_unstable();
+    for pair in windows.windows(2) {
+        if pair[1].0 < pair[0].1 {
+            return Err(anyhow!(
+                "burn starting at {} overlaps burn starting at {}",
+                pair[1].0,
+                pair[0].0
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Sign and store a firmware checkpoint.
+fn store_checkpoint(keys: &Ed25519KeyPair, state: Vec<u8>) -> Result<ExecutiveResponse> {
+    let generations = env_or("RAD_CHECKPOINTS", CHECKPOINT_GENERATIONS)?;
+    let state = sign_checkpoint(keys, state);
+    rotate_checkpoint(Path::new(CHECKPOINT_PATH), generations, &state)?;
+    Ok(ExecutiveResponse::Checkpoint { success: true })
+}
+
+/// Append a signature over the serialized state so the firmware can detect tampering.
+fn sign_checkpoint(keys: &Ed25519KeyPair, mut state: Vec<u8>) -> Vec<u8> {
+    let signature = keys.sign(&state);
+    state.extend_from_slice(signature.as_ref());
+    state
+}
+
+/// Write a new checkpoint generation, shifting older generations up and dropping the oldest.
+fn rotate_checkpoint(base: &Path, generations: usize, state: &[u8]) -> Result<()> {
+    for generation in (1..generations).rev() {
+        let newer = checkpoint_generation_path(base, generation - 1);
+        if newer.is_file() {
+            std::fs::rename(&newer, checkpoint_generation_path(base, generation))
+                .context("rotate checkpoint")?;
+        }
+    }
+    write_checkpoint(&checkpoint_generation_path(base, 0), state)
+}
+
+/// Atomically replace a checkpoint by renaming a temporary file next to it over the target.
+fn write_checkpoint(path: &Path, state: &[u8]) -> Result<()> {
+    let parent = path
+        .parent()
+        .filter(|x| !x.as_os_str().is_empty())
+        .unwrap_or_else(|| Path::new("."));
+    let mut output =
+        tempfile::NamedTempFile::new_in(parent).context("create temporary checkpoint")?;
+    output
+        .write_all(state)
+        .context("write temporary checkpoint")?;
+    output
+        .as_file()
+        .sync_all()
+        .context("flush temporary checkpoint")?;
+    output.persist(path).context("persist checkpoint")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{take_burns, ACTIVE_BURNS, RESTART_BURNS};
+    use rad_common::{Burn, ManeuverRecord};
+    use std::f64::consts::PI;
+    use std::sync::atomic::Ordering;
+    use std::sync::Mutex;
+
+    lazy_static! {
+        /// Serializes tests sharing the burn schedule.
+        static ref BURNS_TEST: Mutex<()> = Mutex::new(());
+    }
+
+    /// Clear the shared burn schedules and maneuver history, holding `BURNS_TEST`.
+    fn reset_burns() {
+        *BURNS.lock().expect("burns") = None;
+        *ACTIVE_BURNS.lock().expect("active burns") = vec![];
+        MANEUVERS.lock().expect("maneuvers").clear();
+        RESTART_BURNS.store(false, Ordering::SeqCst);
+    }
+
+    #[test]
+    fn test_atomic_checkpoint() {
+        let dir = tempfile::tempdir().expect("temporary directory");
+        let path = dir.path().join("checkpoint");
+        let old = vec![0x11u8; MAX_CHECKPOINT_FRAME_SIZE];
+        let new = vec![0x22u8; MAX_CHECKPOINT_FRAME_SIZE];
+        write_checkpoint(&path, &old).expect("write old checkpoint");
+
+        let reader = std::thread::spawn({
+            let path = path.clone();
+            let (old, new) = (old.clone(), new.clone());
+            move || {
+                for _ in 0..256 {
+                    let data = std::fs::read(&path).expect("read checkpoint");
+                    assert!(data == old || data == new, "partial checkpoint");
+                }
+            }
+        });
+        for i in 0..64 {
+            let data = if i % 2 == 0 { &new } else { &old };
+            write_checkpoint(&path, data).expect("write checkpoint");
+        }
+        reader.join().expect("reader");
+
+        write_checkpoint(&path, &new).expect("write new checkpoint");
+        assert_eq!(std::fs::read(&path).expect("read checkpoint"), new);
+        assert_eq!(std::fs::read_dir(dir.path()).expect("list").count(), 1);
+    }
+
+    #[test]
+    fn test_sign_checkpoint() {
+        use ring::signature::{UnparsedPublicKey, ED25519};
+
+        let dir = tempfile::tempdir().expect("temporary directory");
+        let keys_path = dir.path().join("keys.pkcs8");
+        let pub_key_path = dir.path().join("pub_key");
+        let keys = checkpoint_keys(&keys_path, &pub_key_path).expect("generate keys");
+        let reloaded = checkpoint_keys(&keys_path, &pub_key_path).expect("load keys");
+        assert_eq!(keys.public_key().as_ref(), reloaded.public_key().as_ref());
+
+        let pub_key = std::fs::read(&pub_key_path).expect("read public key");
+        let key = UnparsedPublicKey::new(&ED25519, &pub_key);
+        let state = vec![0x5au8; 1024];
+        let data = sign_checkpoint(&keys, state.clone());
+        let (signed, signature) = data.split_at(state.len());
+        assert_eq!(signed, &state[..]);
+        assert!(key.verify(signed, signature).is_ok());
+        assert!(key.verify(&state[1..], signature).is_err());
+    }
+
+    #[test]
+    fn test_rotate_checkpoint() {
+        let dir = tempfile::tempdir().expect("temporary directory");
+        let base = dir.path().join("rad.chkpt");
+        for i in 0..5u8 {
+            rotate_checkpoint(&base, 3, &[i; 16]).expect("rotate checkpoint");
+        }
+        for (generation, i) in [4u8, 3, 2].iter().enumerate() {
+            let path = checkpoint_generation_path(&base, generation);
+            assert_eq!(std::fs::read(&path).expect("read checkpoint"), vec![*i; 16]);
+        }
+        assert!(!checkpoint_generation_path(&base, 3).exists());
+    }
+
+    fn burn(start: u64, length: u8, thrust: f64) -> Burn {
+        Burn {
+            start,
+            length,
+            thrust,
+            vector: (1.0, 0.0, 0.0),
+        }
+    }
+
+    #[test]
+    fn test_eclipse_state() {
+        let sun = (1.496e8, 0.0, 0.0);
+        let radius = 6378.1363;
+        assert_eq!(
+            eclipse_state((7000.0, 0.0, 0.0), sun, radius),
+            Eclipse::Sunlit
+        );
+        assert_eq!(
+            eclipse_state((0.0, 7000.0, 0.0), sun, radius),
+            Eclipse::Sunlit
+        );
+        assert_eq!(
+            eclipse_state((-7000.0, 0.0, 0.0), sun, radius),
+            Eclipse::Umbra
+        );
+        assert_eq!(
+            eclipse_state((-7000.0, 6380.0, 0.0), sun, radius),
+            Eclipse::Penumbra
+        );
+    }
+
+    #[test]
+    fn test_next_pass() {
+        let (gm, radius) = (398600.4415, 6378.1363);
+        let r: f64 = 7000.0;
+        let position = (r, 0.0, 0.0);
+        let velocity = (0.0, (gm / r).sqrt(), 0.0);
+
+        // Equatorial station on the far side of the Earth: the spacecraft rises about half a
+        // synodic period later and passes nearly overhead for several minutes
+        let (aos, los, max_elevation) =
+            next_pass(position, velocity, gm, radius, PI, (0.0, 0.0), 10.0).expect("pass");
+        assert!(aos > 2700.0 && aos < 3000.0, "AOS {}", aos);
+        assert!(
+            los - aos > 450.0 && los - aos < 700.0,
+            "duration {}",
+            los - aos
+        );
+        assert!(max_elevation > 85.0, "max elevation {}", max_elevation);
+
+        // Station directly below: the pass is in progress
+        let (aos, los, _) =
+            next_pass(position, velocity, gm, radius, 0.0, (0.0, 0.0), 10.0).expect("pass");
+        assert_eq!(aos, 0.0);
+        assert!(los > 0.0 && los < 400.0, "LOS {}", los);
+
+        // An equatorial orbit never rises over a polar station
+        assert_eq!(
+            next_pass(position, velocity, gm, radius, 0.0, (80.0, 0.0), 10.0),
+            None
+        );
+    }
+
+    #[test]
+    fn test_earth_rotation_angle() {
+        // J2000.0 is 2000-01-01 12:00 UTC, 36524.5 days after 1900-01-01
+        let j2000 = 36524.5 * 86400.0;
+        let angle = earth_rotation_angle(j2000);
+        assert!((angle - 0.779_057_273_264 * 2.0 * PI).abs() < 1e-9);
+        // A sidereal day later the Earth has turned once
+        let sidereal_day = 86400.0 / 1.002_737_811_911_354_5;
+        let turned = earth_rotation_angle(j2000 + sidereal_day);
+        assert!((turned - angle).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_validate_burns() {
+        let now = 1_620_000_000;
+        let valid = vec![
+            burn(now + 20, 10, 1.0),
+            burn(now - 30, 10, 0.0),
+            burn(now, 20, 0.5),
+        ];
+        assert!(validate_burns(&valid, now).is_ok());
+        assert!(validate_burns(&[], now).is_ok());
+
+        let overlapping = vec![burn(now, 10, 0.5), burn(now + 9, 10, 0.5)];
+        assert!(validate_burns(&overlapping, now).is_err());
+
+        assert!(validate_burns(&[burn(now, 10, 1.5)], now).is_err());
+        assert!(validate_burns(&[burn(now, 10, -0.1)], now).is_err());
+        assert!(validate_burns(&[burn(now, 10, f64::NAN)], now).is_err());
+
+        assert!(validate_burns(&[burn(now - MAX_BURN_AGE - 1, 10, 0.5)], now).is_err());
+
+        let schedule: Vec<_> = (0..=MAX_BURNS as u64)
+            .map(|i| burn(now + 10 * i, 10, 0.5))
+            .collect();
+        assert!(validate_burns(&schedule[..MAX_BURNS], now).is_ok());
+        assert!(validate_burns(&schedule, now).is_err());
+
+        let mut infinite = burn(now, 10, 0.5);
+        infinite.vector.1 = f64::INFINITY;
+        assert!(validate_burns(&[infinite], now).is_err());
+    }
+
+    #[test]
+    fn test_maneuver_rejected() {
+        let response = handle_request(ExecutiveRequest::Maneuver {
+            burns: vec![burn(1_000, 10, 0.5)],
+        })
+        .expect("schedule maneuver");
+        assert!(matches!(
+            response,
+            ExecutiveResponse::Maneuver { success: false, .. }
+        ));
+    }
+
+    #[test]
+    fn test_abort_maneuver() {
+        let _guard = BURNS_TEST.lock().unwrap_or_else(|e| e.into_inner());
+        let now = sim_time();
+        let abort = || match handle_request(ExecutiveRequest::AbortManeuver).expect("abort") {
+            ExecutiveResponse::AbortManeuver { success, aborted } => {
+                assert!(success);
+                aborted
+            }
+            response => panic!("unexpected response {}", response),
         };
-        let buffer = bincode::serialize(&response).context("encode response")?;
-        socket
-            .write_u32(buffer.len() as _)
-            .await
-            .context("send response size")?;
-        socket.write_all(&buffer).await.context("send response")?;
+
+        // A pending schedule is dropped before it is applied
+        reset_burns();
+        let response = handle_request(ExecutiveRequest::Maneuver {
+            burns: vec![burn(now + 60, 10, 1.0)],
+        })
+        .expect("schedule maneuver");
+        assert!(matches!(
+            response,
+            ExecutiveResponse::Maneuver { success: true, .. }
+        ));
+        assert!(abort());
+        assert_eq!(take_burns(now).expect("take burns"), None);
+
+        // A burn in progress is cut short so no fuel is spent past the abort
+        let schedule = vec![
+            burn(now - 100, 10, 1.0),
+            burn(now - 5, 60, 1.0),
+            burn(now + 30, 10, 1.0),
+        ];
+        *BURNS.lock().expect("burns") = Some(schedule.clone());
+        take_burns(now - 200).expect("take burns");
+        assert!(abort());
+        let burns = take_burns(now)
+            .expect("take burns")
+            .expect("truncated schedule");
+        assert!(burns.iter().all(|b| b.start + b.length as u64 <= now + 1));
+        assert_eq!(burns[0], burn(now - 100, 10, 1.0));
+        assert_eq!(burns.len(), 2);
+
+        // The history keeps the original maneuver, marked aborted, rather than a new one
+        let maneuvers = MANEUVERS.lock().expect("maneuvers").clone();
+        assert_eq!(maneuvers.len(), 1);
+        assert_eq!(maneuvers[0].applied, now - 200);
+        assert_eq!(maneuvers[0].burns, schedule);
+        assert!(matches!(maneuvers[0].aborted, Some(t) if t >= now && t <= now + 1));
+
+        // Nothing left to abort
+        assert!(!abort());
+        reset_burns();
+    }
+
+    #[test]
+    fn test_maneuver_history() {
+        let _guard = BURNS_TEST.lock().unwrap_or_else(|e| e.into_inner());
+        reset_burns();
+        let now = sim_time();
+        let burn = burn(now + 60, 10, 0.5);
+        let response = handle_request(ExecutiveRequest::Maneuver {
+            burns: vec![burn.clone()],
+        })
+        .expect("schedule maneuver");
+        assert!(matches!(
+            response,
+            ExecutiveResponse::Maneuver { success: true, .. }
+        ));
+
+        // The simulation applies the pending schedule
+        let burns = take_burns(1_619_999_000).expect("take burns");
+        assert_eq!(burns, Some(vec![burn.clone()]));
+
+        match handle_request(ExecutiveRequest::ManeuverHistory).expect("maneuver history") {
+            ExecutiveResponse::ManeuverHistory { success, maneuvers } => {
+                assert!(success);
+                assert_eq!(
+                    maneuvers,
+                    vec![ManeuverRecord::new(1_619_999_000, vec![burn])]
+                );
+            }
+            response => panic!("unexpected response {}", response),
+        }
+        reset_burns();
     }
 }
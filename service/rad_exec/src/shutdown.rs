@@ -0,0 +1,63 @@
+//! Coordinated task shutdown.
+
+use std::future::Future;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio_util::sync::CancellationToken;
+
+/// Wait for SIGINT or SIGTERM.
+pub async fn signalled() -> std::io::Result<()> {
+    let mut terminate = signal(SignalKind::terminate())?;
+    tokio::select! {
+        result = tokio::signal::ctrl_c() => result?,
+        _ = terminate.recv() => {}
+    }
+    Ok(())
+}
+
+/// Run a future until it completes or shutdown is requested.
+pub async fn until_cancelled<F>(shutdown: &CancellationToken, task: F) -> Option<F::Output>
+where
+    F: Future,
+{
+    tokio::select! {
+        _ = shutdown.cancelled() => None,
+        output = task => Some(output),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tokio::time::{sleep, timeout};
+
+    #[tokio::test]
+    async fn test_tasks_exit_on_cancel() {
+        let shutdown = CancellationToken::new();
+        let tasks: Vec<_> = (0..4)
+            .map(|_| {
+                let shutdown = shutdown.clone();
+                tokio::spawn(async move {
+                    let mut iterations = 0;
+                    while until_cancelled(&shutdown, sleep(Duration::from_millis(10)))
+                        .await
+                        .is_some()
+                    {
+                        iterations += 1;
+                    }
+                    iterations
+                })
+            })
+            .collect();
+
+        sleep(Duration::from_millis(50)).await;
+        shutdown.cancel();
+        for task in tasks {
+            let iterations = timeout(Duration::from_secs(1), task)
+                .await
+                .expect("task did not exit")
+                .expect("task panicked");
+            assert!(iterations > 0);
+        }
+    }
+}
@@ -0,0 +1,313 @@
+//! Checkpoint inspection.
+
+use crate::data::SIGNATURE_SIZE;
+use crate::scrub::{repair_state, RepairStats};
+use crate::{RadError, State};
+use rad_common::{CHECKPOINT_PUB_KEY_PATH, MAX_MESSAGE_SIZE};
+use ring::signature::{UnparsedPublicKey, ED25519};
+use std::fmt;
+use std::path::Path;
+
+/// Checkpoint format version, bumped whenever the protected state layout changes.
+pub const CHECKPOINT_VERSION: u32 = 2;
+
+/// Marker preceding the format version, absent from checkpoints written before versioning.
+const CHECKPOINT_MAGIC: &[u8; 4] = b"RADC";
+
+/// Serialize protected state into versioned checkpoint data.
+pub fn encode_state(state: &State) -> Result<Vec<u8>, RadError> {
+    let mut data = CHECKPOINT_MAGIC.to_vec();
+    data.extend_from_slice(&CHECKPOINT_VERSION.to_le_bytes());
+    bincode::serialize_into(&mut data, state)?;
+    Ok(data)
+}
+
+/// Deserialize protected state from versioned checkpoint data, rejecting other versions.
+pub fn decode_state(data: &[u8]) -> Result<Box<State>, RadError> {
+    let header = CHECKPOINT_MAGIC.len() + 4;
+    if data.len() < header || &data[..CHECKPOINT_MAGIC.len()] != CHECKPOINT_MAGIC {
+        return Err(RadError::Data("unversioned checkpoint".to_string()));
+    }
+    let mut version = [0u8; 4];
+    version.copy_from_slice(&data[CHECKPOINT_MAGIC.len()..header]);
+    let version = u32::from_le_bytes(version);
+    if version != CHECKPOINT_VERSION {
+        return Err(RadError::Data(format!(
+            "unsupported checkpoint version {}, expected {}",
+            version, CHECKPOINT_VERSION
+        )));
+    }
+    Ok(bincode::deserialize(&data[header..])?)
+}
+
+/// Read the public key the executive signs checkpoints with.
+pub fn checkpoint_key() -> Result<Vec<u8>, RadError> {
+    Ok(std::fs::read(CHECKPOINT_PUB_KEY_PATH)?)
+}
+
+/// Read protected state from a checkpoint without modifying it.
+pub fn read_checkpoint<P>(path: P, key: &[u8]) -> Result<Box<State>, RadError>
+where
+    P: AsRef<Path>,
+{
+    let data = std::fs::read(path.as_ref())?;
+    decode_state(verify_checkpoint(&data, key)?)
+}
+
+/// Verify the signature trailing a checkpoint, returning the serialized state it covers.
+fn verify_checkpoint<'a>(data: &'a [u8], key: &[u8]) -> Result<&'a [u8], RadError> {
+    if data.len() < SIGNATURE_SIZE {
+        return Err(RadError::Signature);
+    }
+    let (state, signature) = data.split_at(data.len() - SIGNATURE_SIZE);
+    UnparsedPublicKey::new(&ED25519, key)
+        .verify(state, signature)
+        .map_err(|_| RadError::Signature)?;
+    Ok(state)
+}
+
+/// Checkpoint signing keys for tests.
+#[cfg(test)]
+pub fn test_keys() -> ring::signature::Ed25519KeyPair {
+    ring::signature::Ed25519KeyPair::from_seed_unchecked(&[0x5a; 32]).expect("checkpoint keys")
+}
+
+/// Public checkpoint key for tests.
+#[cfg(test)]
+pub fn test_key() -> Vec<u8> {
+    use ring::signature::KeyPair;
+    test_keys().public_key().as_ref().to_vec()
+}
+
+/// Serialize and sign a protected state as the executive does.
+#[cfg(test)]
+pub fn sign_checkpoint(state: &State) -> Vec<u8> {
+    let keys = test_keys();
+    let mut data = encode_state(state).expect("encode state");
+    let signature = keys.sign(&data);
+    data.extend_from_slice(signature.as_ref());
+    data
+}
+
+/// Checkpoint field difference.
+#[derive(Debug, PartialEq)]
+pub struct Difference {
+    /// Field path
+    pub field: String,
+    /// Value in the first checkpoint
+    pub a: String,
+    /// Value in the second checkpoint
+    pub b: String,
+}
+
+impl fmt::Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {} -> {}", self.field, self.a, self.b)
+    }
+}
+
+/// Checkpoint comparison.
+#[derive(Debug, Default)]
+pub struct Diff {
+    /// Repairs needed to load the first checkpoint
+    pub repairs_a: u64,
+    /// Repairs needed to load the second checkpoint
+    pub repairs_b: u64,
+    /// Differing fields
+    pub differences: Vec<Difference>,
+}
+
+impl Diff {
+    fn compare<T>(&mut self, field: String, a: T, b: T)
+    where
+        T: PartialEq + fmt::Display,
+    {
+        if a != b {
+            self.differences.push(Difference {
+                field,
+                a: a.to_string(),
+                b: b.to_string(),
+            });
+        }
+    }
+}
+
+impl fmt::Display for Diff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "integrity repairs: a={} b={}",
+            self.repairs_a, self.repairs_b
+        )?;
+        if self.differences.is_empty() {
+            writeln!(f, "checkpoints are identical")?;
+        }
+        for d in &self.differences {
+            writeln!(f, "{}", d)?;
+        }
+        Ok(())
+    }
+}
+
+/// Compare two protected states field by field after repairing them.
+pub fn diff(a: &mut State, b: &mut State) -> Result<Diff, RadError> {
+    let mut diff = Diff {
+        repairs_a: repair_state(a, &mut RepairStats::default())?,
+        repairs_b: repair_state(b, &mut RepairStats::default())?,
+        ..Default::default()
+    };
+
+    diff.compare("repairs".to_string(), a.repairs.get()?, b.repairs.get()?);
+    diff.compare(
+        "repairs_failed".to_string(),
+        a.repairs_failed.get()?,
+        b.repairs_failed.get()?,
+    );
+    diff.compare("restarts".to_string(), a.restarts.get()?, b.restarts.get()?);
+    diff.compare(
+        "event_index".to_string(),
+        a.event_index.get()?,
+        b.event_index.get()?,
+    );
+
+    for (i, (ea, eb)) in a.events.iter_mut().zip(b.events.iter_mut()).enumerate() {
+        let mut ma = vec![0u8; MAX_MESSAGE_SIZE];
+        let mut mb = vec![0u8; MAX_MESSAGE_SIZE];
+        let ta = ea.get(&mut ma)?;
+        let tb = eb.get(&mut mb)?;
+        diff.compare(format!("events[{}].timestamp", i), ta, tb);
+        diff.compare(
+            format!("events[{}].message", i),
+            message_string(&ma),
+            message_string(&mb),
+        );
+        diff.compare(
+            format!("events[{}].severity", i),
+            ea.severity()?,
+            eb.severity()?,
+        );
+    }
+
+    for (i, (ma, mb)) in a.modules.iter_mut().zip(b.modules.iter_mut()).enumerate() {
+        diff.compare(
+            format!("modules[{}].updated", i),
+            ma.updated()?,
+            mb.updated()?,
+        );
+        diff.compare(
+            format!("modules[{}].enabled", i),
+            ma.is_enabled()?,
+            mb.is_enabled()?,
+        );
+        diff.compare(
+            format!("modules[{}].encoded", i),
+            ma.is_encoded()?,
+            mb.is_encoded()?,
+        );
+        diff.compare(format!("modules[{}].budget", i), ma.budget()?, mb.budget()?);
+        diff.compare(
+            format!("modules[{}].verified", i),
+            ma.is_verified()?,
+            mb.is_verified()?,
+        );
+        diff.compare(
+            format!("modules[{}].signature", i),
+            hex::encode(ma.signature()?),
+            hex::encode(mb.signature()?),
+        );
+        diff.compare(
+            format!("modules[{}].checksum", i),
+            format!("{:016x}", ma.checksum()?),
+            format!("{:016x}", mb.checksum()?),
+        );
+    }
+
+    Ok(diff)
+}
+
+/// Render an event message for display.
+fn message_string(message: &[u8]) -> String {
+    let end = message
+        .iter()
+        .position(|&x| x == 0)
+        .unwrap_or(message.len());
+    format!("{:?}", String::from_utf8_lossy(&message[..end]))
+}
+
+/// Load two checkpoints and print their differences.
+pub fn diff_checkpoints<P>(a: P, b: P) -> Result<(), RadError>
+where
+    P: AsRef<Path>,
+{
+    let key = checkpoint_key()?;
+    let mut a = read_checkpoint(a, &key)?;
+    let mut b = read_checkpoint(b, &key)?;
+    print!("{}", diff(&mut a, &mut b)?);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn diff_module_enabled() {
+        let dir = std::env::temp_dir();
+        let path_a = dir.join(format!("rad_fw_diff_a_{}.chkpt", std::process::id()));
+        let path_b = dir.join(format!("rad_fw_diff_b_{}.chkpt", std::process::id()));
+
+        let a = Box::new(State::new().expect("state"));
+        let mut b = Box::new(State::new().expect("state"));
+        b.modules[2].set_enabled(true).expect("enable");
+        std::fs::write(&path_a, sign_checkpoint(&a)).expect("write");
+        std::fs::write(&path_b, sign_checkpoint(&b)).expect("write");
+
+        let mut a = read_checkpoint(&path_a, &test_key()).expect("read checkpoint");
+        let mut b = read_checkpoint(&path_b, &test_key()).expect("read checkpoint");
+        let _ = std::fs::remove_file(&path_a);
+        let _ = std::fs::remove_file(&path_b);
+
+        let diff = diff(&mut a, &mut b).expect("diff");
+        assert_eq!(
+            diff.differences,
+            vec![Difference {
+                field: "modules[2].enabled".to_string(),
+                a: "false".to_string(),
+                b: "true".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn checkpoint_version() {
+        let state = Box::new(State::new().expect("state"));
+        let data = encode_state(&state).expect("encode state");
+        assert!(decode_state(&data).is_ok());
+
+        // Checkpoints from before versioning or from another version are rejected
+        let legacy = bincode::serialize(state.as_ref()).expect("serialize");
+        assert!(matches!(decode_state(&legacy), Err(RadError::Data(_))));
+        let mut other = data.clone();
+        other[CHECKPOINT_MAGIC.len()..CHECKPOINT_MAGIC.len() + 4]
+            .copy_from_slice(&(CHECKPOINT_VERSION - 1).to_le_bytes());
+        assert!(matches!(decode_state(&other), Err(RadError::Data(_))));
+    }
+
+    #[test]
+    fn checkpoint_frame_size() {
+        use rad_common::frame::MAX_CHECKPOINT_FRAME_SIZE;
+        use rad_common::{message, ExecutiveRequest};
+
+        // Bytes that take the most characters to spell out in JSON
+        let state = Box::new(State::new().expect("state"));
+        let mut data = encode_state(&state).expect("encode state");
+        data.iter_mut().for_each(|x| *x = 0xff);
+        let request =
+            message::encode(&ExecutiveRequest::Checkpoint { state: data }).expect("encode");
+        assert!(
+            request.len() <= MAX_CHECKPOINT_FRAME_SIZE,
+            "{}",
+            request.len()
+        );
+    }
+}
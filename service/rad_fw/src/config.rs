@@ -0,0 +1,79 @@
+//! Firmware configuration.
+
+use crate::data::MODULE_UPDATE_THRESHOLD;
+use crate::vm::FileAccess;
+use crate::RadError;
+use rad_common::CHECKPOINT_GENERATIONS;
+use std::time::Duration;
+
+const MODULE_UPDATE_COOLDOWN_ARG: &str = "--module-update-cooldown=";
+const SCRUB_INTERVAL_ARG: &str = "--scrub-interval-ms=";
+const CHECKPOINTS_ARG: &str = "--checkpoints=";
+const FILE_READ_ALLOW_ARG: &str = "--file-read-allow=";
+
+/// Firmware configuration.
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Whether dynamic modules may be loaded and executed
+    pub modules: bool,
+    /// Minimum number of seconds between updates to a module
+    pub module_update_cooldown: u64,
+    /// Delay between protected state scrubbing passes
+    pub scrub_interval: Duration,
+    /// Number of rotated checkpoint generations to try when loading
+    pub checkpoints: usize,
+    /// Paths modules may read
+    pub file_access: FileAccess,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            modules: true,
+            module_update_cooldown: MODULE_UPDATE_THRESHOLD,
+            scrub_interval: Duration::from_millis(500),
+            checkpoints: CHECKPOINT_GENERATIONS,
+            file_access: FileAccess::default(),
+        }
+    }
+}
+
+impl Config {
+    /// Parse the configuration from command line arguments.
+    pub fn from_args<I>(args: I) -> Result<Self, RadError>
+    where
+        I: IntoIterator<Item = String>,
+    {
+        let mut config = Self::default();
+        for arg in args {
+            if arg == "--no-modules" {
+                config.modules = false;
+            } else if let Some(value) = arg.strip_prefix(MODULE_UPDATE_COOLDOWN_ARG) {
+                config.module_update_cooldown = value.parse().map_err(|_| {
+                    RadError::Config(format!("invalid module update cooldown {}", value))
+                })?;
+            } else if let Some(value) = arg.strip_prefix(SCRUB_INTERVAL_ARG) {
+                config.scrub_interval = value
+                    .parse()
+                    .ok()
+                    .filter(|x| *x > 0)
+                    .map(Duration::from_millis)
+                    .ok_or_else(|| RadError::Config(format!("invalid scrub interval {}", value)))?;
+            } else if let Some(value) = arg.strip_prefix(CHECKPOINTS_ARG) {
+                config.checkpoints = value
+                    .parse()
+                    .map_err(|_| RadError::Config(format!("invalid checkpoint count {}", value)))?;
+            } else if let Some(value) = arg.strip_prefix(FILE_READ_ALLOW_ARG) {
+                let path = std::fs::canonicalize(value).map_err(|e| {
+                    RadError::Config(format!("invalid file read path {}: {}", value, e))
+                })?;
+                if let FileAccess::Allow(paths) = &mut config.file_access {
+                    paths.push(path);
+                }
+            } else if arg == "--insecure-file-read" {
+                config.file_access = FileAccess::Insecure;
+            }
+        }
+        Ok(config)
+    }
+}
@@ -1,25 +1,39 @@
 //! Control channel.
 
-use crate::data::hash;
-use crate::{reset, RadError, State};
-use byteorder::{ReadBytesExt, WriteBytesExt, BE};
+use crate::config::Config;
+use crate::scrub::RepairStats;
+use crate::vm::MAX_INSTRUCTION_BUDGET;
+use crate::{request_reset, ModuleOutputs, RadError, State};
+use rad_common::frame::{read_frame, write_frame, MAX_FRAME_SIZE};
+use rad_common::message;
 use rad_common::{
-    ControlRequest, ControlResponse, ExecutiveRequest, ModuleStatus, COMMAND_PATH, MAX_MESSAGE_SIZE,
+    ControlRequest, ControlResponse, ExecutiveRequest, ExecutiveResponse, ModuleError,
+    ModuleStatus, Severity, COMMAND_PATH, MAX_BURNS, MAX_MESSAGE_SIZE,
 };
-use std::io::{Read, Write};
+use std::io::{ErrorKind, Read, Write};
 use std::os::unix::net::UnixListener;
 use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc::{Receiver, Sender};
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::sync::Arc;
+use std::thread::sleep;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-/// Process control requests.
+/// How often to check for a stop request while waiting for a connection.
+const ACCEPT_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Process control requests until `stop` is set.
 pub fn process_requests(
     tx_requests: Sender<ControlRequest>,
     rx_responses: Receiver<ControlResponse>,
+    stop: Arc<AtomicBool>,
 ) {
-    if let Err(e) = do_process_requests(tx_requests, rx_responses) {
-        error!("control channel: {:?}", e);
-        reset();
+    if let Err(e) = do_process_requests(tx_requests, rx_responses, &stop) {
+        // The main loop closes the response channel when it stops
+        if !stop.load(Ordering::SeqCst) {
+            error!("control channel: {:?}", e);
+            request_reset();
+        }
     }
 }
 
@@ -27,6 +41,7 @@ pub fn process_requests(
 fn do_process_requests(
     tx_requests: Sender<ControlRequest>,
     rx_responses: Receiver<ControlResponse>,
+    stop: &AtomicBool,
 ) -> Result<(), RadError> {
     info!("listening for control requests at {}", COMMAND_PATH);
     let command_path = Path::new(COMMAND_PATH);
@@ -35,57 +50,81 @@ fn do_process_requests(
     }
 
     let listener = UnixListener::bind(command_path)?;
-    loop {
+    listener.set_nonblocking(true)?;
+    let mut buffer = vec![];
+    while !stop.load(Ordering::SeqCst) {
         match listener.accept() {
             Ok((mut socket, _address)) => {
-                let size = socket.read_u32::<BE>()?;
-                let mut buffer = vec![0u8; size as _];
-                socket.read_exact(&mut buffer)?;
-                let request: ControlRequest = bincode::deserialize(&buffer)?;
-                debug!("control request: {}", request);
-                tx_requests.send(request)?;
-                let response = rx_responses.recv()?;
-                let buffer = bincode::serialize(&response)?;
-                socket.write_u32::<BE>(buffer.len() as _)?;
-                socket.write_all(&buffer)?;
+                socket.set_nonblocking(false)?;
+                process_connection(&mut socket, &mut buffer, &tx_requests, &rx_responses)?;
             }
+            Err(e) if e.kind() == ErrorKind::WouldBlock => sleep(ACCEPT_INTERVAL),
             Err(e) => {
                 error!("control request: {}", e);
             }
         }
     }
+    Ok(())
+}
+
+/// Process a single control request on a connection.
+fn process_connection<S>(
+    socket: &mut S,
+    buffer: &mut Vec<u8>,
+    tx_requests: &Sender<ControlRequest>,
+    rx_responses: &Receiver<ControlResponse>,
+) -> Result<(), RadError>
+where
+    S: Read + Write,
+{
+    read_frame(socket, buffer, MAX_FRAME_SIZE)?;
+    let request: ControlRequest = message::decode(buffer)?;
+    let (request_id, request) = request.untag();
+    match request_id {
+        Some(request_id) => debug!("control request #{}: {}", request_id, request),
+        None => debug!("control request: {}", request),
+    }
+    tx_requests.send(request)?;
+    let response = rx_responses.recv()?.tag(request_id);
+    buffer.clear();
+    message::encode_into(&mut *buffer, &response)?;
+    write_frame(socket, buffer)?;
+    Ok(())
 }
 
 /// Process a control request.
 pub fn process_request(
     state: &mut Box<State>,
+    config: &Config,
+    stats: &RepairStats,
+    outputs: &ModuleOutputs,
     request: ControlRequest,
     tx_exec_requests: &Sender<ExecutiveRequest>,
 ) -> Result<Option<ControlResponse>, RadError> {
     let response = match request {
         ControlRequest::Firmware => {
-            let mut events = vec![];
-            for e in &mut state.events {
-                let mut m = vec![0u8; MAX_MESSAGE_SIZE];
-                let t = e.get(&mut m)?;
-                events.push(rad_common::Event::new(t, m));
-            }
-            let mut modules = vec![];
-            for m in &mut state.modules {
-                modules.push(ModuleStatus::new(
-                    m.is_enabled()?,
-                    m.is_verified()?,
-                    hash(&m.code)?,
-                ));
-            }
+            let (events, modules) = firmware_status(state)?;
             Some(ControlResponse::Firmware {
                 success: true,
                 repairs: state.repairs.get()?,
                 restarts: state.restarts.get()?,
+                repairs_failed: state.repairs_failed.get()?,
                 events,
                 modules,
+                field_repairs: stats.fields(),
             })
         }
+        ControlRequest::Diagnostics => Some(ControlResponse::Diagnostics {
+            success: true,
+            repairs_succeeded: state.repairs.get()?,
+            repairs_failed: state.repairs_failed.get()?,
+            restarts: state.restarts.get()?,
+        }),
+        ControlRequest::Capabilities => Some(ControlResponse::Capabilities {
+            success: true,
+            modules: state.modules.len() as u64,
+            module_update_cooldown: config.module_update_cooldown,
+        }),
         ControlRequest::PositionVelocity => {
             tx_exec_requests.send(ExecutiveRequest::PositionVelocity)?;
             None
@@ -94,19 +133,50 @@ pub fn process_request(
             tx_exec_requests.send(ExecutiveRequest::KeplerianElements)?;
             None
         }
+        ControlRequest::OrbitSummary => {
+            tx_exec_requests.send(ExecutiveRequest::OrbitSummary)?;
+            None
+        }
+        ControlRequest::NextPass {
+            station_lat,
+            station_lon,
+            min_elevation,
+        } => {
+            tx_exec_requests.send(ExecutiveRequest::NextPass {
+                station_lat,
+                station_lon,
+                min_elevation,
+            })?;
+            None
+        }
         ControlRequest::Sensors => {
             tx_exec_requests.send(ExecutiveRequest::Sensors)?;
             None
         }
+        ControlRequest::EnableModule { id, .. } | ControlRequest::UpdateModule { id, .. }
+            if !config.modules =>
+        {
+            state.log(
+                Severity::Warn,
+                &format!("module {}: {}", id, ModuleError::Disabled),
+            );
+            Some(module_failure(&request, ModuleError::Disabled))
+        }
         ControlRequest::EnableModule { id, enable } => {
             let id = id as usize;
             if let Some(m) = state.modules.get_mut(id) {
                 m.set_enabled(enable)?;
-                state.log(&format!("enable module {}: success", id));
-                Some(ControlResponse::EnableModule { success: true })
+                state.log(Severity::Info, &format!("enable module {}: success", id));
+                Some(ControlResponse::EnableModule {
+                    success: true,
+                    error: None,
+                })
             } else {
-                state.log(&format!("enable module {}: failure", id));
-                Some(ControlResponse::EnableModule { success: false })
+                state.log(
+                    Severity::Warn,
+                    &format!("enable module {}: {}", id, ModuleError::OutOfRange),
+                );
+                Some(module_failure(&request, ModuleError::OutOfRange))
             }
         }
         ControlRequest::UpdateModule {
@@ -118,47 +188,117 @@ pub fn process_request(
             let id = id as usize;
             if let Some(m) = state.modules.get_mut(id) {
                 let ts = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
-                if m.can_update(ts)? {
+                if m.can_update(ts, config.module_update_cooldown)? {
                     m.set_enabled(false)?;
                     let checksum = m.update(ts, module, signature)?;
-                    let verified = m.verify_code()?;
+                    let verified = state.verify_module(id)?;
+                    let m = &mut state.modules[id];
                     m.set_enabled(true)?;
                     m.set_encoded(encoded)?;
-                    state.log(&format!("update module {}: success", id));
+                    let error = if verified {
+                        state.log(Severity::Info, &format!("update module {}: success", id));
+                        None
+                    } else {
+                        state.log(
+                            Severity::Warn,
+                            &format!("update module {}: {}", id, ModuleError::InvalidSignature),
+                        );
+                        Some(ModuleError::InvalidSignature)
+                    };
                     Some(ControlResponse::UpdateModule {
                         success: verified,
                         checksum,
                         verified,
                         enabled: true,
+                        error,
                     })
                 } else {
-                    state.log(&format!("update module {}: failure", id));
-                    Some(request.to_failure())
+                    state.log(
+                        Severity::Warn,
+                        &format!("update module {}: {}", id, ModuleError::UpdateTooSoon),
+                    );
+                    Some(module_failure(&request, ModuleError::UpdateTooSoon))
                 }
             } else {
-                state.log(&format!("update module {}: failure", id));
-                Some(request.to_failure())
+                state.log(
+                    Severity::Warn,
+                    &format!("update module {}: {}", id, ModuleError::OutOfRange),
+                );
+                Some(module_failure(&request, ModuleError::OutOfRange))
+            }
+        }
+        ControlRequest::ModuleBudget { id, budget } => {
+            let id = id as usize;
+            match state.modules.get_mut(id) {
+                Some(m) if budget <= MAX_INSTRUCTION_BUDGET => {
+                    m.set_budget(budget)?;
+                    state.log(
+                        Severity::Info,
+                        &format!("module {} budget {}: success", id, budget),
+                    );
+                    Some(ControlResponse::ModuleBudget { success: true })
+                }
+                _ => {
+                    state.log(
+                        Severity::Warn,
+                        &format!("module {} budget {}: failure", id, budget),
+                    );
+                    Some(request.to_failure())
+                }
             }
         }
+        ControlRequest::ModuleOutput { id } => match outputs.get(id as usize) {
+            Some(data) => Some(ControlResponse::Custom { data: data.clone() }),
+            None => Some(request.to_failure()),
+        },
+        ControlRequest::Telemetry => {
+            tx_exec_requests.send(ExecutiveRequest::Telemetry)?;
+            None
+        }
+        ControlRequest::SensorHistory { since } => {
+            tx_exec_requests.send(ExecutiveRequest::SensorHistory { since })?;
+            None
+        }
+        ControlRequest::ManeuverHistory => {
+            tx_exec_requests.send(ExecutiveRequest::ManeuverHistory)?;
+            None
+        }
+        ControlRequest::Maneuver { ref burns } if burns.len() > MAX_BURNS => {
+            state.log(
+                Severity::Warn,
+                &format!(
+                    "schedule maneuver: {} burns exceeds limit of {}",
+                    burns.len(),
+                    MAX_BURNS
+                ),
+            );
+            Some(request.to_failure())
+        }
         ControlRequest::Maneuver { burns } => {
             for burn in &burns {
-                state.log(&format!(
-                    "schedule maneuver: start={} length={}s thrust={}N vector=({}, {}, {})",
-                    burn.start,
-                    burn.length,
-                    burn.thrust,
-                    burn.vector.0,
-                    burn.vector.1,
-                    burn.vector.2
-                ));
+                state.log(Severity::Info, &format!("schedule maneuver: {}", burn));
             }
             tx_exec_requests.send(ExecutiveRequest::Maneuver { burns })?;
             None
         }
+        ControlRequest::AbortManeuver => {
+            state.log(Severity::Info, "abort maneuver");
+            tx_exec_requests.send(ExecutiveRequest::AbortManeuver)?;
+            None
+        }
+        ControlRequest::Reset => {
+            // The main loop checkpoints and exits on its next iteration, after this is answered
+            state.log(Severity::Info, "reset requested");
+            request_reset();
+            Some(ControlResponse::Reset { success: true })
+        }
         ControlRequest::NoOp
         | ControlRequest::Authenticate { .. }
-        | ControlRequest::Reset
-        | ControlRequest::Disconnect => {
+        | ControlRequest::Disconnect
+        | ControlRequest::Ping { .. }
+        | ControlRequest::Hello { .. }
+        | ControlRequest::Subscribe { .. }
+        | ControlRequest::Tagged { .. } => {
             return Err(RadError::Protocol(
                 "invalid control protocol message".to_string(),
             ));
@@ -167,3 +307,847 @@ pub fn process_request(
 
     Ok(response)
 }
+
+/// Complete a telemetry request with the executive's response and firmware status.
+pub fn telemetry_response(
+    state: &mut Box<State>,
+    stats: &RepairStats,
+    response: ExecutiveResponse,
+) -> Result<ControlResponse, RadError> {
+    match response {
+        ExecutiveResponse::Telemetry {
+            success,
+            t,
+            p,
+            v,
+            fuel,
+            radiation,
+            eclipse,
+        } => {
+            let (events, modules) = firmware_status(state)?;
+            Ok(ControlResponse::Telemetry {
+                success,
+                t,
+                position: p,
+                velocity: v,
+                fuel,
+                radiation,
+                eclipse,
+                repairs: state.repairs.get()?,
+                restarts: state.restarts.get()?,
+                repairs_failed: state.repairs_failed.get()?,
+                events,
+                modules,
+                field_repairs: stats.fields(),
+            })
+        }
+        response => Err(RadError::Protocol(format!(
+            "expected telemetry response, received {}",
+            response
+        ))),
+    }
+}
+
+/// Build a failure response for a module request, carrying the reason it failed.
+fn module_failure(request: &ControlRequest, reason: ModuleError) -> ControlResponse {
+    let mut response = request.to_failure();
+    if let ControlResponse::EnableModule { ref mut error, .. }
+    | ControlResponse::UpdateModule { ref mut error, .. } = response
+    {
+        *error = Some(reason);
+    }
+    response
+}
+
+/// Collect the event log and module status.
+fn firmware_status(
+    state: &mut Box<State>,
+) -> Result<(Vec<rad_common::Event>, Vec<ModuleStatus>), RadError> {
+    let mut events = Vec::with_capacity(state.events.len());
+    for e in state.ordered_events() {
+        // Read straight into the message the response owns
+        let mut message = vec![0u8; MAX_MESSAGE_SIZE];
+        let t = e.get(&mut message)?;
+        events.push(rad_common::Event::with_severity(t, message, e.severity()?));
+    }
+    let mut modules = Vec::with_capacity(state.modules.len());
+    for m in &mut state.modules {
+        modules.push(ModuleStatus::with_code(
+            m.is_enabled()?,
+            m.is_verified()?,
+            m.checksum()?,
+            m.updated()?,
+            m.code_len()? as u32,
+        ));
+    }
+    Ok((events, modules))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::{Module, ModuleRuntime};
+    use crate::vm::{FileAccess, INSTRUCTION_BUDGET};
+    use crate::{execute_modules, ModuleRuntimes};
+    use byteorder::{ReadBytesExt, WriteBytesExt, BE};
+    use rad_common::{Burn, Eclipse};
+    use ring::signature::Ed25519KeyPair;
+    use std::os::unix::net::UnixStream;
+    use std::sync::mpsc::channel;
+    use std::sync::Mutex;
+    use std::thread::spawn;
+
+    const RAD_KEYS: &[u8] = include_bytes!("../../data/rad_keys.pkcs8");
+
+    #[rustfmt::skip]
+    const MODULE: &[u8] = &[
+        // Return a one-byte result
+        0xb7, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00,
+        0x95, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    ];
+
+    #[rustfmt::skip]
+    const LOG_MODULE: &[u8] = &[
+        // Store "autonomy" at address 0
+        0x18, 0x01, 0x00, 0x00, 0x61, 0x75, 0x74, 0x6f,
+        0x00, 0x00, 0x00, 0x00, 0x6e, 0x6f, 0x6d, 0x79,
+        0xb7, 0x06, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x7b, 0x16, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        // Log it
+        0xb7, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0xb7, 0x02, 0x00, 0x00, 0x08, 0x00, 0x00, 0x00,
+        0x85, 0x00, 0x00, 0x00, 0x19, 0x00, 0x00, 0x00,
+        // Return no result
+        0xb7, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x95, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    ];
+
+    #[rustfmt::skip]
+    const OUTPUT_MODULE: &[u8] = &[
+        // Store "autonomy" at address 0
+        0x18, 0x01, 0x00, 0x00, 0x61, 0x75, 0x74, 0x6f,
+        0x00, 0x00, 0x00, 0x00, 0x6e, 0x6f, 0x6d, 0x79,
+        0xb7, 0x06, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x7b, 0x16, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        // Return it as an eight-byte result
+        0xb7, 0x00, 0x00, 0x00, 0x08, 0x00, 0x00, 0x00,
+        0x95, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    ];
+
+    /// Run the modules on a shared copy of the state, returning how many ran.
+    fn run_modules(state: &mut Box<State>, outputs: &mut ModuleOutputs, config: &Config) -> usize {
+        let shared = Mutex::new(std::mem::replace(
+            state,
+            Box::new(State::new().expect("state")),
+        ));
+        let shared_outputs = Mutex::new(std::mem::take(outputs));
+        let mut runtimes = ModuleRuntimes::default();
+        let executed =
+            execute_modules(&shared, &shared_outputs, &mut runtimes, config).expect("execute");
+        *state = shared.into_inner().expect("state");
+        *outputs = shared_outputs.into_inner().expect("outputs");
+        executed
+    }
+
+    /// Build a signed module update request.
+    fn update_module(id: u8) -> ControlRequest {
+        signed_module(id, MODULE)
+    }
+
+    /// Build a signed module update request for some code.
+    fn signed_module(id: u8, module: &[u8]) -> ControlRequest {
+        let keys = Ed25519KeyPair::from_pkcs8(RAD_KEYS).expect("module keys");
+        let mut code = module.to_vec();
+        code.resize(crate::data::MAX_MODULE_SIZE, 0);
+        ControlRequest::UpdateModule {
+            id,
+            module: module.to_vec(),
+            signature: keys.sign(&code).as_ref().to_vec(),
+            encoded: false,
+        }
+    }
+
+    #[test]
+    fn test_no_modules() {
+        let mut state = Box::new(State::new().expect("state"));
+        let (tx_exec_requests, _rx_exec_requests) = channel();
+
+        // Load a verified module while modules are allowed
+        let response = process_request(
+            &mut state,
+            &Config::default(),
+            &RepairStats::default(),
+            &ModuleOutputs::default(),
+            update_module(0),
+            &tx_exec_requests,
+        )
+        .expect("update module");
+        match response {
+            Some(ControlResponse::UpdateModule { verified, .. }) => assert!(verified),
+            _ => panic!("expected update module response"),
+        }
+
+        let config = Config::from_args(vec!["--no-modules".to_string()]).expect("config");
+        let response = process_request(
+            &mut state,
+            &config,
+            &RepairStats::default(),
+            &ModuleOutputs::default(),
+            update_module(1),
+            &tx_exec_requests,
+        )
+        .expect("update module");
+        assert_eq!(
+            response,
+            Some(module_failure(&update_module(1), ModuleError::Disabled))
+        );
+        assert!(!state.modules[1].is_enabled().expect("enabled"));
+        let response = process_request(
+            &mut state,
+            &config,
+            &RepairStats::default(),
+            &ModuleOutputs::default(),
+            ControlRequest::EnableModule {
+                id: 1,
+                enable: true,
+            },
+            &tx_exec_requests,
+        )
+        .expect("enable module");
+        assert_eq!(
+            response,
+            Some(ControlResponse::EnableModule {
+                success: false,
+                error: Some(ModuleError::Disabled),
+            })
+        );
+        assert!(!state.modules[1].is_enabled().expect("enabled"));
+
+        // The verified module is left alone
+        assert_eq!(
+            run_modules(&mut state, &mut ModuleOutputs::default(), &config),
+            0
+        );
+        assert!(state.modules[0].is_verified().expect("verified"));
+        assert!(state.modules[0].is_enabled().expect("enabled"));
+    }
+
+    #[test]
+    fn test_module_status() {
+        let mut state = Box::new(State::new().expect("state"));
+        let (tx_exec_requests, _rx_exec_requests) = channel();
+        let config = Config::default();
+        process_request(
+            &mut state,
+            &config,
+            &RepairStats::default(),
+            &ModuleOutputs::default(),
+            update_module(2),
+            &tx_exec_requests,
+        )
+        .expect("update module");
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("now")
+            .as_secs();
+        let response = process_request(
+            &mut state,
+            &config,
+            &RepairStats::default(),
+            &ModuleOutputs::default(),
+            ControlRequest::Firmware,
+            &tx_exec_requests,
+        )
+        .expect("firmware");
+        match response {
+            Some(ControlResponse::Firmware { modules, .. }) => {
+                assert!(now - modules[2].updated_ts <= 5);
+                assert_eq!(modules[2].code_len, MODULE.len() as u32);
+                assert!(modules[2].verified);
+                assert_eq!(modules[0].updated_ts, 0);
+                assert_eq!(modules[0].code_len, 0);
+            }
+            _ => panic!("expected firmware response"),
+        }
+    }
+
+    #[test]
+    fn test_reset() {
+        let mut state = Box::new(State::new().expect("state"));
+        let (tx_exec_requests, _rx_exec_requests) = channel();
+        let response = process_request(
+            &mut state,
+            &Config::default(),
+            &RepairStats::default(),
+            &ModuleOutputs::default(),
+            ControlRequest::Reset,
+            &tx_exec_requests,
+        )
+        .expect("reset");
+        assert_eq!(response, Some(ControlResponse::Reset { success: true }));
+        assert!(crate::shutdown_requested());
+    }
+
+    #[test]
+    fn test_maneuver_burn_limit() {
+        let mut state = Box::new(State::new().expect("state"));
+        let (tx_exec_requests, rx_exec_requests) = channel();
+        let schedule: Vec<_> = (0..=MAX_BURNS as u64)
+            .map(|i| Burn::new(1_620_000_000 + 10 * i, 10, 0.5, (1.0, 0.0, 0.0)).expect("burn"))
+            .collect();
+        let mut maneuver = |burns: &[Burn]| {
+            process_request(
+                &mut state,
+                &Config::default(),
+                &RepairStats::default(),
+                &ModuleOutputs::default(),
+                ControlRequest::Maneuver {
+                    burns: burns.to_vec(),
+                },
+                &tx_exec_requests,
+            )
+            .expect("maneuver")
+        };
+
+        // Too many burns are refused without reaching the executive
+        assert_eq!(
+            maneuver(&schedule),
+            Some(ControlResponse::Maneuver {
+                success: false,
+                last_burn_fuel: None,
+            })
+        );
+        assert!(rx_exec_requests.try_recv().is_err());
+
+        // A full schedule is forwarded
+        assert_eq!(maneuver(&schedule[..MAX_BURNS]), None);
+        match rx_exec_requests.try_recv().expect("executive request") {
+            ExecutiveRequest::Maneuver { burns } => assert_eq!(burns.len(), MAX_BURNS),
+            request => panic!("unexpected request {}", request),
+        }
+    }
+
+    #[test]
+    fn test_module_event_log() {
+        let mut state = Box::new(State::new().expect("state"));
+        let (tx_exec_requests, _rx_exec_requests) = channel();
+        let config = Config::default();
+        process_request(
+            &mut state,
+            &config,
+            &RepairStats::default(),
+            &ModuleOutputs::default(),
+            signed_module(3, LOG_MODULE),
+            &tx_exec_requests,
+        )
+        .expect("update module");
+
+        assert_eq!(
+            run_modules(&mut state, &mut ModuleOutputs::default(), &config),
+            1
+        );
+        let response = process_request(
+            &mut state,
+            &config,
+            &RepairStats::default(),
+            &ModuleOutputs::default(),
+            ControlRequest::Firmware,
+            &tx_exec_requests,
+        )
+        .expect("firmware");
+        match response {
+            Some(ControlResponse::Firmware { events, .. }) => {
+                let expected = b"module 3: autonomy";
+                assert!(events.iter().any(|e| e.message.starts_with(expected)));
+            }
+            _ => panic!("expected firmware response"),
+        }
+    }
+
+    #[test]
+    fn test_module_error_event() {
+        let mut state = Box::new(State::new().expect("state"));
+        let (tx_exec_requests, _rx_exec_requests) = channel();
+        let config = Config::default();
+        for request in vec![
+            update_module(0),
+            ControlRequest::ModuleBudget { id: 0, budget: 1 },
+        ] {
+            process_request(
+                &mut state,
+                &config,
+                &RepairStats::default(),
+                &ModuleOutputs::default(),
+                request,
+                &tx_exec_requests,
+            )
+            .expect("module request");
+        }
+
+        // The module runs out of budget
+        assert_eq!(
+            run_modules(&mut state, &mut ModuleOutputs::default(), &config),
+            0
+        );
+        assert!(!state.modules[0].is_enabled().expect("enabled"));
+        let response = process_request(
+            &mut state,
+            &config,
+            &RepairStats::default(),
+            &ModuleOutputs::default(),
+            ControlRequest::Firmware,
+            &tx_exec_requests,
+        )
+        .expect("firmware");
+        match response {
+            Some(ControlResponse::Firmware { events, .. }) => {
+                let severity = |prefix: &[u8]| {
+                    events
+                        .iter()
+                        .find(|e| e.message.starts_with(prefix))
+                        .map(|e| e.severity)
+                };
+                assert_eq!(severity(b"module 0 exec error"), Some(Severity::Error));
+                assert_eq!(severity(b"update module 0: success"), Some(Severity::Info));
+            }
+            _ => panic!("expected firmware response"),
+        }
+    }
+
+    #[test]
+    fn test_module_output() {
+        let mut state = Box::new(State::new().expect("state"));
+        let (tx_exec_requests, _rx_exec_requests) = channel();
+        let config = Config::default();
+        let mut outputs = ModuleOutputs::default();
+        process_request(
+            &mut state,
+            &config,
+            &RepairStats::default(),
+            &outputs,
+            signed_module(1, OUTPUT_MODULE),
+            &tx_exec_requests,
+        )
+        .expect("update module");
+
+        // Nothing has run yet
+        let response = process_request(
+            &mut state,
+            &config,
+            &RepairStats::default(),
+            &outputs,
+            ControlRequest::ModuleOutput { id: 1 },
+            &tx_exec_requests,
+        )
+        .expect("module output");
+        assert_eq!(response, Some(ControlResponse::Custom { data: vec![] }));
+
+        assert_eq!(run_modules(&mut state, &mut outputs, &config), 1);
+        let response = process_request(
+            &mut state,
+            &config,
+            &RepairStats::default(),
+            &outputs,
+            ControlRequest::ModuleOutput { id: 1 },
+            &tx_exec_requests,
+        )
+        .expect("module output");
+        assert_eq!(
+            response,
+            Some(ControlResponse::Custom {
+                data: b"autonomy".to_vec()
+            })
+        );
+
+        let request = || ControlRequest::ModuleOutput { id: 4 };
+        let response = process_request(
+            &mut state,
+            &config,
+            &RepairStats::default(),
+            &outputs,
+            request(),
+            &tx_exec_requests,
+        )
+        .expect("module output");
+        assert_eq!(response, Some(request().to_failure()));
+    }
+
+    #[test]
+    fn test_module_cache() {
+        let mut module = Module::new().expect("new module");
+        let load = |module: &mut Module, code: &[u8]| match signed_module(0, code) {
+            ControlRequest::UpdateModule {
+                module: code,
+                signature,
+                ..
+            } => {
+                module.update(1, &code, &signature).expect("update module");
+                assert!(module.verify_code().expect("verify code"));
+            }
+            request => panic!("unexpected request {}", request),
+        };
+        let (events, _rx_events) = channel();
+        let access = FileAccess::default();
+
+        let mut runtime = ModuleRuntime::default();
+
+        load(&mut module, MODULE);
+        module.set_enabled(true).expect("enable");
+        let output = module
+            .execute(&mut runtime, &access, &events)
+            .expect("execute");
+        assert_eq!(output.as_ref().map(Vec::len), Some(1));
+        assert_eq!(
+            module
+                .execute(&mut runtime, &access, &events)
+                .expect("execute"),
+            output
+        );
+        assert_eq!(runtime.runs(), 1);
+
+        // Changing the budget runs it again
+        module.set_budget(INSTRUCTION_BUDGET - 1).expect("budget");
+        assert_eq!(
+            module
+                .execute(&mut runtime, &access, &events)
+                .expect("execute"),
+            output
+        );
+        assert_eq!(runtime.runs(), 2);
+
+        // A run that completes after a change is not reused
+        let run = module.prepare(&mut runtime).expect("prepare").expect("run");
+        let stale = run.execute(&access, &events).expect("execute");
+        module.set_budget(INSTRUCTION_BUDGET).expect("budget");
+        runtime.complete(&run, &stale);
+        module
+            .execute(&mut runtime, &access, &events)
+            .expect("execute");
+        assert_eq!(runtime.runs(), 4);
+
+        // Modules making syscalls are never cached
+        load(&mut module, LOG_MODULE);
+        module
+            .execute(&mut runtime, &access, &events)
+            .expect("execute");
+        module
+            .execute(&mut runtime, &access, &events)
+            .expect("execute");
+        assert_eq!(runtime.runs(), 6);
+    }
+
+    #[test]
+    fn test_module_update_cooldown() {
+        let mut state = Box::new(State::new().expect("state"));
+        let (tx_exec_requests, _rx_exec_requests) = channel();
+        let config =
+            Config::from_args(vec!["--module-update-cooldown=1".to_string()]).expect("config");
+
+        let response = process_request(
+            &mut state,
+            &config,
+            &RepairStats::default(),
+            &ModuleOutputs::default(),
+            ControlRequest::Capabilities,
+            &tx_exec_requests,
+        )
+        .expect("capabilities");
+        assert_eq!(
+            response,
+            Some(ControlResponse::Capabilities {
+                success: true,
+                modules: 4,
+                module_update_cooldown: 1,
+            })
+        );
+
+        let updated = |response: Option<ControlResponse>| match response {
+            Some(ControlResponse::UpdateModule { success, .. }) => success,
+            _ => panic!("expected update module response"),
+        };
+        let response = process_request(
+            &mut state,
+            &config,
+            &RepairStats::default(),
+            &ModuleOutputs::default(),
+            update_module(0),
+            &tx_exec_requests,
+        )
+        .expect("update module");
+        assert!(updated(response));
+        let response = process_request(
+            &mut state,
+            &config,
+            &RepairStats::default(),
+            &ModuleOutputs::default(),
+            update_module(0),
+            &tx_exec_requests,
+        )
+        .expect("update module");
+        assert!(!updated(response));
+
+        std::thread::sleep(std::time::Duration::from_secs(2));
+        let response = process_request(
+            &mut state,
+            &config,
+            &RepairStats::default(),
+            &ModuleOutputs::default(),
+            update_module(0),
+            &tx_exec_requests,
+        )
+        .expect("update module");
+        assert!(updated(response));
+    }
+
+    #[test]
+    fn test_module_errors() {
+        let mut state = Box::new(State::new().expect("state"));
+        let (tx_exec_requests, _rx_exec_requests) = channel();
+        let config = Config::default();
+        let error = |response: Option<ControlResponse>| match response {
+            Some(ControlResponse::UpdateModule { error, .. })
+            | Some(ControlResponse::EnableModule { error, .. }) => error,
+            _ => panic!("expected module response"),
+        };
+
+        let response = process_request(
+            &mut state,
+            &config,
+            &RepairStats::default(),
+            &ModuleOutputs::default(),
+            update_module(200),
+            &tx_exec_requests,
+        )
+        .expect("update module");
+        assert_eq!(error(response), Some(ModuleError::OutOfRange));
+        let response = process_request(
+            &mut state,
+            &config,
+            &RepairStats::default(),
+            &ModuleOutputs::default(),
+            ControlRequest::EnableModule {
+                id: 200,
+                enable: true,
+            },
+            &tx_exec_requests,
+        )
+        .expect("enable module");
+        assert_eq!(error(response), Some(ModuleError::OutOfRange));
+
+        let response = process_request(
+            &mut state,
+            &config,
+            &RepairStats::default(),
+            &ModuleOutputs::default(),
+            update_module(0),
+            &tx_exec_requests,
+        )
+        .expect("update module");
+        assert_eq!(error(response), None);
+        let response = process_request(
+            &mut state,
+            &config,
+            &RepairStats::default(),
+            &ModuleOutputs::default(),
+            update_module(0),
+            &tx_exec_requests,
+        )
+        .expect("update module");
+        assert_eq!(error(response), Some(ModuleError::UpdateTooSoon));
+
+        let response = process_request(
+            &mut state,
+            &config,
+            &RepairStats::default(),
+            &ModuleOutputs::default(),
+            ControlRequest::UpdateModule {
+                id: 1,
+                module: MODULE.to_vec(),
+                signature: vec![0; crate::data::SIGNATURE_SIZE],
+                encoded: false,
+            },
+            &tx_exec_requests,
+        )
+        .expect("update module");
+        assert_eq!(error(response), Some(ModuleError::InvalidSignature));
+    }
+
+    #[test]
+    fn test_module_budget() {
+        let mut state = Box::new(State::new().expect("state"));
+        let (tx_exec_requests, _rx_exec_requests) = channel();
+        let config = Config::default();
+        assert_eq!(
+            state.modules[2].budget().expect("budget"),
+            crate::vm::INSTRUCTION_BUDGET
+        );
+
+        let response = process_request(
+            &mut state,
+            &config,
+            &RepairStats::default(),
+            &ModuleOutputs::default(),
+            ControlRequest::ModuleBudget {
+                id: 2,
+                budget: 4096,
+            },
+            &tx_exec_requests,
+        )
+        .expect("module budget");
+        assert_eq!(
+            response,
+            Some(ControlResponse::ModuleBudget { success: true })
+        );
+        assert_eq!(state.modules[2].budget().expect("budget"), 4096);
+        assert_eq!(
+            state.modules[1].budget().expect("budget"),
+            crate::vm::INSTRUCTION_BUDGET
+        );
+
+        let request = || ControlRequest::ModuleBudget { id: 4, budget: 0 };
+        let response = process_request(
+            &mut state,
+            &config,
+            &RepairStats::default(),
+            &ModuleOutputs::default(),
+            request(),
+            &tx_exec_requests,
+        )
+        .expect("module budget");
+        assert_eq!(response, Some(request().to_failure()));
+
+        // Budgets past the limit are refused and leave the module untouched
+        let request = || ControlRequest::ModuleBudget {
+            id: 2,
+            budget: crate::vm::MAX_INSTRUCTION_BUDGET + 1,
+        };
+        let response = process_request(
+            &mut state,
+            &config,
+            &RepairStats::default(),
+            &ModuleOutputs::default(),
+            request(),
+            &tx_exec_requests,
+        )
+        .expect("module budget");
+        assert_eq!(response, Some(request().to_failure()));
+        assert_eq!(state.modules[2].budget().expect("budget"), 4096);
+    }
+
+    #[test]
+    fn test_telemetry() {
+        let mut state = Box::new(State::new().expect("state"));
+        let (tx_exec_requests, rx_exec_requests) = channel();
+        state.restarts.increment(2).expect("restarts");
+        state.log(Severity::Info, "telemetry");
+
+        let response = process_request(
+            &mut state,
+            &Config::default(),
+            &RepairStats::default(),
+            &ModuleOutputs::default(),
+            ControlRequest::Telemetry,
+            &tx_exec_requests,
+        )
+        .expect("telemetry");
+        assert_eq!(response, None);
+        assert_eq!(
+            rx_exec_requests.try_recv().expect("executive request"),
+            ExecutiveRequest::Telemetry
+        );
+        assert!(rx_exec_requests.try_recv().is_err());
+
+        let response = telemetry_response(
+            &mut state,
+            &RepairStats::default(),
+            ExecutiveResponse::Telemetry {
+                success: true,
+                t: 1_620_000_000,
+                p: (7000.0, 1.0, 2.0),
+                v: (3.0, 7.5, 4.0),
+                fuel: 50.0,
+                radiation: 0.25,
+                eclipse: Eclipse::Umbra,
+            },
+        )
+        .expect("telemetry response");
+        match response {
+            ControlResponse::Telemetry {
+                success,
+                t,
+                position,
+                velocity,
+                fuel,
+                radiation,
+                eclipse,
+                repairs,
+                restarts,
+                repairs_failed,
+                events,
+                modules,
+                field_repairs,
+            } => {
+                assert!(success);
+                assert_eq!(t, 1_620_000_000);
+                assert_eq!(position, (7000.0, 1.0, 2.0));
+                assert_eq!(velocity, (3.0, 7.5, 4.0));
+                assert_eq!(fuel, 50.0);
+                assert_eq!(radiation, 0.25);
+                assert_eq!(eclipse, Eclipse::Umbra);
+                assert_eq!(repairs, 0);
+                assert_eq!(restarts, 2);
+                assert_eq!(repairs_failed, 0);
+                assert_eq!(events.len(), state.events.len());
+                assert_eq!(modules.len(), state.modules.len());
+                assert!(field_repairs.is_empty());
+            }
+            response => panic!("unexpected response {}", response),
+        }
+
+        assert!(telemetry_response(
+            &mut state,
+            &RepairStats::default(),
+            ExecutiveResponse::Maneuver {
+                success: true,
+                last_burn_fuel: None,
+            }
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_request_id_echo() {
+        let (mut client, mut server) = UnixStream::pair().expect("socket pair");
+        let (tx_requests, rx_requests) = channel();
+        let (tx_responses, rx_responses) = channel();
+        let handler = spawn(move || {
+            process_connection(&mut server, &mut vec![], &tx_requests, &rx_responses)
+        });
+
+        let request = ControlRequest::Firmware.tag(Some(0x1337));
+        let buffer = message::encode(&request).expect("encode request");
+        client
+            .write_u32::<BE>(buffer.len() as _)
+            .expect("send size");
+        client.write_all(&buffer).expect("send request");
+
+        // The main loop sees the bare request
+        assert_eq!(
+            rx_requests.recv().expect("receive"),
+            ControlRequest::Firmware
+        );
+        tx_responses
+            .send(ControlRequest::Firmware.to_failure())
+            .expect("send");
+
+        let size = client.read_u32::<BE>().expect("receive size");
+        let mut buffer = vec![0u8; size as _];
+        client.read_exact(&mut buffer).expect("receive response");
+        let response: ControlResponse = message::decode(&buffer).expect("decode response");
+        assert_eq!(
+            response.untag(),
+            (Some(0x1337), ControlRequest::Firmware.to_failure())
+        );
+        handler.join().expect("join").expect("process connection");
+    }
+}
@@ -7,22 +7,28 @@ extern crate log;
 #[macro_use]
 extern crate solana_rbpf as rbpf;
 
-use crate::data::{Event, Module, U64};
+use crate::config::Config;
+use crate::data::{Event, Module, ModuleRuntime, U64};
 use rad_common::{
-    ControlResponse, ExecutiveRequest, ExecutiveResponse, CHECKPOINT_PATH, MAX_MESSAGE_SIZE,
+    checkpoint_generation_path, format_state_location, ControlResponse, ExecutiveRequest,
+    ExecutiveResponse, Severity, CHECKPOINT_PATH, COMMAND_PATH, MAX_MESSAGE_SIZE,
 };
 use rbpf::error::EbpfError;
 use ring::signature::{UnparsedPublicKey, ED25519};
 use serde::{Deserialize, Serialize};
-use std::fs::File;
 use std::path::Path;
-use std::sync::mpsc::{channel, RecvError, SendError, TryRecvError};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{
+    channel, Receiver, RecvError, RecvTimeoutError, SendError, Sender, TryRecvError,
+};
 use std::sync::{Arc, Mutex, PoisonError};
 use std::thread::{sleep, spawn};
 use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use thiserror::Error;
 
 mod array;
+mod checkpoint;
+mod config;
 mod control;
 mod data;
 mod scrub;
@@ -31,8 +37,13 @@ mod vm;
 mod watchdog;
 
 const REPORT_INTERVAL: u64 = 10;
+const FLUSH_TIMEOUT: u64 = 5;
+const RESET_TIMEOUT: u64 = 10;
 const RAD_PUB_KEY_BYTES: &[u8] = include_bytes!("../../data/rad_pub_key");
 
+/// Set when a graceful reset has been requested.
+static SHUTDOWN: AtomicBool = AtomicBool::new(false);
+
 lazy_static! {
     static ref RAD_PUB_KEY: UnparsedPublicKey<&'static [u8]> =
         UnparsedPublicKey::new(&ED25519, RAD_PUB_KEY_BYTES);
@@ -47,6 +58,8 @@ pub enum RadError {
     ChannelSend,
     #[error("checksum failure: stored={0:016x} != computed={1:016x}")]
     Checksum(u64, u64),
+    #[error("configuration error: {0}")]
+    Config(String),
     #[error("critical data error: {0}")]
     Data(String),
     #[error("ECC error")]
@@ -61,12 +74,14 @@ pub enum RadError {
     Protocol(String),
     #[error("repair error: {0}")]
     Repair(String),
+    #[error("invalid checkpoint signature")]
+    Signature,
     #[error("time error")]
     Time(#[from] std::time::SystemTimeError),
     #[error("VM error")]
     Vm(String),
-    #[error("watchdog timeout")]
-    Watchdog,
+    #[error("watchdog timeout: {0}")]
+    Watchdog(String),
 }
 
 impl From<RecvError> for RadError {
@@ -96,12 +111,20 @@ where
     }
 }
 
+/// Most recent output of each module, kept outside the protected state and not checkpointed.
+pub type ModuleOutputs = [Vec<u8>; 4];
+
+/// Run bookkeeping of each module, kept outside the protected state.
+pub type ModuleRuntimes = [ModuleRuntime; 4];
+
 /// State.
 #[derive(Serialize, Deserialize)]
 #[repr(align(4096))]
 pub struct State {
     /// Number of repairs performed
     repairs: U64,
+    /// Number of irreparable corruptions, whether cleared or forcing a reset
+    repairs_failed: U64,
     /// Number of restarts performed
     restarts: U64,
     /// Event log pointer
@@ -116,6 +139,7 @@ impl State {
     fn new() -> Result<Self, RadError> {
         let state = Self {
             repairs: U64::new(0)?,
+            repairs_failed: U64::new(0)?,
             restarts: U64::new(0)?,
             event_index: U64::new(0)?,
             events: [
@@ -182,32 +206,74 @@ impl State {
     }
 
     /// Log an event.
-    pub fn log(&mut self, message: &str) {
-        let mut index = self.event_index.get().unwrap_or(0) as usize;
-        if index > self.events.len() {
-            index = 0;
+    pub fn log(&mut self, severity: Severity, message: &str) {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|x| x.as_secs())
+            .unwrap_or(0);
+        self.log_at(now, severity, message);
+    }
+
+    /// Log an event at a wall clock time.
+    ///
+    /// The wall clock time is recorded as is, even if the clock went backwards. Events are
+    /// ordered by their position in the log instead, see [`State::ordered_events`].
+    pub fn log_at(&mut self, now: u64, severity: Severity, message: &str) {
+        let latest = self
+            .events
+            .iter_mut()
+            .filter_map(|e| e.timestamp().ok())
+            .max()
+            .unwrap_or(0);
+        if now < latest {
+            warn!("clock regression of {}s in event log", latest - now);
         }
 
+        let index = self.event_index.get().unwrap_or(0) as usize % self.events.len();
+
         if let Some(e) = self.events.get_mut(index) {
-            // Nasty nasty -- the message (flag buffer) has to be at least MAX_MESSAGE_SIZE, which
-            // can be controlled from the eBPF return value
-            let t = SystemTime::now();
+            // Event messages are fixed size, so truncate long messages and zero-pad short ones
             let mut size = message.len();
             if size > MAX_MESSAGE_SIZE {
                 size = MAX_MESSAGE_SIZE;
             }
-            let _ = e.update(
-                t.duration_since(UNIX_EPOCH)
-                    .map(|x| x.as_secs())
-                    .unwrap_or(0),
-                &message.as_bytes()[..size],
-            );
+            let mut m = [0u8; MAX_MESSAGE_SIZE];
+            m[..size].copy_from_slice(&message.as_bytes()[..size]);
+            let _ = e.update(now, severity, &m);
         }
+        let _ = self
+            .event_index
+            .update(((index + 1) % self.events.len()) as u64);
+    }
+
+    /// Iterate over the events in the order they were logged, oldest first.
+    pub fn ordered_events(&mut self) -> impl Iterator<Item = &mut Event> {
+        let index = self.event_index.get().unwrap_or(0) as usize % self.events.len();
+        let (newer, older) = self.events.split_at_mut(index);
+        older.iter_mut().chain(newer.iter_mut())
+    }
+
+    /// Verify a module's code, logging an event if its code or signature fails its checksum.
+    pub fn verify_module(&mut self, id: usize) -> Result<bool, RadError> {
+        let result = self.modules[id].verify_code();
+        if let Err(e @ RadError::Checksum(..)) = &result {
+            self.log(Severity::Error, &format!("verify module {}: {}", id, e));
+        }
+        result
     }
 }
 
 /// Main.
 fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() == 4 && args[1] == "diff" {
+        if let Err(e) = checkpoint::diff_checkpoints(&args[2], &args[3]) {
+            eprintln!("checkpoint diff: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
     if let Err(e) = execute() {
         error!("{:?}", e);
     }
@@ -217,96 +283,155 @@ fn main() {
 fn execute() -> Result<(), RadError> {
     env_logger::init();
 
-    let checkpoint_path = Path::new(CHECKPOINT_PATH);
-    let mut state = if checkpoint_path.is_file() {
-        match load_checkpoint(checkpoint_path) {
-            Ok(state) => state,
-            Err(e) => {
-                warn!("checkpoint load error: {}", e);
-                info!(
-                    "removing corrupted checkpoint at {}",
-                    checkpoint_path.display()
-                );
-                if let Err(e) = std::fs::remove_file(checkpoint_path) {
-                    error!("unable to remove checkpoint: {}", e);
-                }
-                Box::new(State::new()?)
-            }
+    let config = Config::from_args(std::env::args().skip(1))?;
+    if !config.modules {
+        info!("dynamic modules disabled");
+    }
+    info!("module update cooldown: {}s", config.module_update_cooldown);
+    info!("scrub interval: {:?}", config.scrub_interval);
+
+    let state = match checkpoint::checkpoint_key() {
+        Ok(key) => load_checkpoints(CHECKPOINT_PATH, config.checkpoints, &key),
+        Err(e) => {
+            warn!("checkpoint key unavailable, not loading checkpoints: {}", e);
+            None
         }
-    } else {
-        Box::new(State::new()?)
+    };
+    let mut state = match state {
+        Some(state) => state,
+        None => Box::new(State::new()?),
     };
     state.make_executable();
     let state_ptr = state.as_ref() as *const State;
-    info!("loaded protected state at {:#?}-{:#?}", state_ptr, unsafe {
-        state_ptr.add(1)
-    });
+    info!(
+        "{}",
+        format_state_location(state_ptr as u64, std::mem::size_of::<State>() as u64)
+    );
+
+    // Worker threads run until this is set by a graceful reset
+    let stop = Arc::new(AtomicBool::new(false));
+    let mut workers = vec![];
 
     // Create watchdogs
     let main_wd = Arc::new(Mutex::new(Instant::now()));
-    spawn({
-        let main_wd = main_wd.clone();
-        move || watchdog::watchdog(vec![main_wd])
-    });
+    let scrub_wd = Arc::new(Mutex::new(Instant::now()));
+    workers.push(spawn({
+        let timers = vec![
+            (
+                "main".to_string(),
+                watchdog::WATCHDOG_TIMEOUT,
+                main_wd.clone(),
+            ),
+            (
+                "scrub".to_string(),
+                watchdog::WATCHDOG_TIMEOUT + config.scrub_interval,
+                scrub_wd.clone(),
+            ),
+        ];
+        let stop = stop.clone();
+        move || watchdog::watchdog(timers, stop)
+    }));
 
     let (tx_control_requests, rx_control_requests) = channel();
     let (tx_control_responses, rx_control_responses) = channel();
-    spawn(move || control::process_requests(tx_control_requests, rx_control_responses));
+    workers.push(spawn({
+        let stop = stop.clone();
+        move || control::process_requests(tx_control_requests, rx_control_responses, stop)
+    }));
 
     let (tx_exec_requests, rx_exec_requests) = channel();
     let (tx_exec_responses, rx_exec_responses) = channel();
-    spawn(move || service::proxy_requests(rx_exec_requests, tx_exec_responses));
+    workers.push(spawn(move || {
+        service::proxy_requests(rx_exec_requests, tx_exec_responses)
+    }));
+
+    let counter = |x: &U64| x.peek().map_or_else(|e| e.to_string(), |x| x.to_string());
+    info!(
+        "ECC repairs: succeeded={} failed={}",
+        counter(&state.repairs),
+        counter(&state.repairs_failed)
+    );
 
     info!("creating initial protected state checkpoint");
-    tx_exec_requests.send(ExecutiveRequest::Checkpoint {
-        state: bincode::serialize(state.as_ref())?,
-    })?;
+    let mut last_checkpoint = send_checkpoint(&state, &tx_exec_requests)?;
+
+    // Scrub memory on its own thread
+    let shared_state = Arc::new(Mutex::new(state));
+    let shared_stats = Arc::new(Mutex::new(scrub::RepairStats::default()));
+    let shared_outputs = Arc::new(Mutex::new(ModuleOutputs::default()));
+    let mut module_runtimes = ModuleRuntimes::default();
+    let (tx_scrub_failures, rx_scrub_failures) = channel();
+    workers.push(spawn({
+        let scrubber = scrub::Scrubber {
+            state: shared_state.clone(),
+            stats: shared_stats.clone(),
+            interval: config.scrub_interval,
+            watchdog: scrub_wd,
+            tx_failures: tx_scrub_failures,
+            stop: stop.clone(),
+        };
+        move || scrubber.scrub()
+    }));
 
+    // Lock the state only for short critical sections, so the scrubber is not starved
+    let lock_state = || shared_state.lock().map_err(|_| RadError::Mutex);
+    let lock_stats = || shared_stats.lock().map_err(|_| RadError::Mutex);
+    let lock_outputs = || shared_outputs.lock().map_err(|_| RadError::Mutex);
     let mut last_report_ts = SystemTime::now();
     loop {
         // Kick the watchdog
         *main_wd.lock().map_err(|_| RadError::Mutex)? = Instant::now();
 
+        // Reset after an irreparable corruption
+        match rx_scrub_failures.try_recv() {
+            Ok(e) => {
+                error!("irreparable protected state corruption: {}", e);
+                let checkpoint = scrub::record_repair_failure(&last_checkpoint, &e.to_string())?;
+                flush_checkpoint(checkpoint, &tx_exec_requests, &rx_exec_responses)?;
+                reset();
+            }
+            Err(TryRecvError::Empty) => {}
+            Err(TryRecvError::Disconnected) if shutdown_requested() => {}
+            Err(TryRecvError::Disconnected) => {
+                return Err(RadError::ChannelReceive);
+            }
+        }
+
+        // Stop cooperatively if another thread has requested a reset
+        if shutdown_requested() {
+            spawn(|| {
+                sleep(Duration::from_secs(RESET_TIMEOUT));
+                error!("graceful reset timed out");
+                reset();
+            });
+            reset_graceful(&*lock_state()?, &tx_exec_requests, &rx_exec_responses);
+            break;
+        }
+
         // Check if we should report
         if last_report_ts.elapsed()?.as_secs() > REPORT_INTERVAL {
+            let mut state = lock_state()?;
+            debug!("scrub passes: {}", lock_stats()?.passes());
             for (i, module) in state.modules.iter_mut().enumerate() {
                 debug!(
                     "module {:02}: enabled={} verified={} code[..16]={}...",
                     i,
                     module.is_enabled()?,
                     module.is_verified()?,
-                    hex::encode(&module.code[..16])
+                    hex::encode(&module.code()?[..16])
                 )
             }
-            tx_exec_requests.send(ExecutiveRequest::Checkpoint {
-                state: bincode::serialize(state.as_ref())?,
-            })?;
+            last_checkpoint = send_checkpoint(&state, &tx_exec_requests)?;
             last_report_ts = SystemTime::now();
         }
 
         // Run dynamic modules
-        let mut module_results = vec![];
-        let mut module_errors = vec![];
-        for (i, m) in state.modules.iter_mut().enumerate() {
-            match m.execute() {
-                Ok(data) => {
-                    if !data.is_empty() {
-                        module_results.push((i, data));
-                    }
-                }
-                Err(e) => {
-                    module_errors.push(format!("module {} exec error: {}", i, e));
-                    m.set_enabled(false)?;
-                }
-            }
-        }
-        for (i, data) in module_results {
-            state.log(&format!("module {} result: {}", i, hex::encode(data)));
-        }
-        for e in module_errors {
-            state.log(&e);
-            error!("{}", e);
-        }
+        execute_modules(
+            &shared_state,
+            &shared_outputs,
+            &mut module_runtimes,
+            &config,
+        )?;
 
         // Check the service channel
         match rx_exec_responses.try_recv() {
@@ -340,19 +465,75 @@ fn execute() -> Result<(), RadError> {
                 aop,
                 ta,
             })?,
+            Ok(ExecutiveResponse::OrbitSummary {
+                success,
+                periapsis_altitude,
+                apoapsis_altitude,
+                period,
+                periapsis_unsafe,
+            }) => tx_control_responses.send(ControlResponse::OrbitSummary {
+                success,
+                periapsis_altitude,
+                apoapsis_altitude,
+                period,
+                periapsis_unsafe,
+            })?,
+            Ok(ExecutiveResponse::NextPass { success, pass }) => {
+                tx_control_responses.send(ControlResponse::NextPass { success, pass })?
+            }
             Ok(ExecutiveResponse::Sensors {
                 success,
                 fuel,
                 radiation,
-            }) => tx_control_responses.send(ControlResponse::Sensors {
+                eclipse,
+            }) => {
+                if success {
+                    vm::record_sensors(fuel, radiation);
+                }
+                tx_control_responses.send(ControlResponse::Sensors {
+                    success,
+                    fuel,
+                    radiation,
+                    eclipse,
+                })?
+            }
+            Ok(ExecutiveResponse::Maneuver {
                 success,
-                fuel,
-                radiation,
+                last_burn_fuel,
+            }) => tx_control_responses.send(ControlResponse::Maneuver {
+                success,
+                last_burn_fuel,
             })?,
-            Ok(ExecutiveResponse::Maneuver { success }) => {
-                tx_control_responses.send(ControlResponse::Maneuver { success })?
+            Ok(ExecutiveResponse::SensorHistory { success, samples }) => {
+                tx_control_responses.send(ControlResponse::SensorHistory { success, samples })?
+            }
+            Ok(ExecutiveResponse::ManeuverHistory { success, maneuvers }) => {
+                tx_control_responses
+                    .send(ControlResponse::ManeuverHistory { success, maneuvers })?
+            }
+            Ok(ExecutiveResponse::AbortManeuver { success, aborted }) => {
+                tx_control_responses.send(ControlResponse::AbortManeuver { success, aborted })?
+            }
+            Ok(response @ ExecutiveResponse::Telemetry { .. }) => {
+                if let ExecutiveResponse::Telemetry {
+                    success: true,
+                    fuel,
+                    radiation,
+                    ..
+                } = response
+                {
+                    vm::record_sensors(fuel, radiation);
+                }
+                let mut state = lock_state()?;
+                let repair_stats = lock_stats()?;
+                tx_control_responses.send(control::telemetry_response(
+                    &mut state,
+                    &repair_stats,
+                    response,
+                )?)?
             }
             Err(TryRecvError::Empty) => {}
+            Err(TryRecvError::Disconnected) if shutdown_requested() => {}
             Err(TryRecvError::Disconnected) => {
                 return Err(RadError::ChannelReceive);
             }
@@ -361,16 +542,22 @@ fn execute() -> Result<(), RadError> {
         // Check the ground channel
         match rx_control_requests.try_recv() {
             Ok(request) => {
-                if let Some(response) =
-                    control::process_request(&mut state, request, &tx_exec_requests)?
-                {
+                let mut state = lock_state()?;
+                let repair_stats = lock_stats()?;
+                let outputs = lock_outputs()?;
+                if let Some(response) = control::process_request(
+                    &mut state,
+                    &config,
+                    &repair_stats,
+                    &outputs,
+                    request,
+                    &tx_exec_requests,
+                )? {
                     match response {
                         ControlResponse::EnableModule { .. }
                         | ControlResponse::UpdateModule { .. } => {
                             info!("creating protected state checkpoint");
-                            tx_exec_requests.send(ExecutiveRequest::Checkpoint {
-                                state: bincode::serialize(state.as_ref())?,
-                            })?;
+                            last_checkpoint = send_checkpoint(&state, &tx_exec_requests)?;
                         }
                         _ => (),
                     }
@@ -378,34 +565,389 @@ fn execute() -> Result<(), RadError> {
                 }
             }
             Err(TryRecvError::Empty) => {}
+            Err(TryRecvError::Disconnected) if shutdown_requested() => {}
             Err(TryRecvError::Disconnected) => {
                 return Err(RadError::ChannelReceive);
             }
         }
 
-        // Scrub memory
-        scrub::check_state(&mut state)?;
-
         sleep(Duration::from_millis(500));
     }
+
+    // Stop the worker threads, closing the channels they wait on, and wait for them to finish
+    stop.store(true, Ordering::SeqCst);
+    drop(tx_exec_requests);
+    drop(tx_control_responses);
+    for worker in workers {
+        if worker.join().is_err() {
+            error!("worker thread panicked");
+        }
+    }
+    reset();
+    Ok(())
+}
+
+/// Send a protected state checkpoint, returning the checkpoint data.
+fn send_checkpoint(
+    state: &State,
+    tx_exec_requests: &Sender<ExecutiveRequest>,
+) -> Result<Vec<u8>, RadError> {
+    let data = checkpoint::encode_state(state)?;
+    tx_exec_requests.send(ExecutiveRequest::Checkpoint {
+        state: data.clone(),
+    })?;
+    Ok(data)
+}
+
+/// Send a checkpoint and wait for the executive to store it.
+fn flush_checkpoint(
+    state: Vec<u8>,
+    tx_exec_requests: &Sender<ExecutiveRequest>,
+    rx_exec_responses: &Receiver<ExecutiveResponse>,
+) -> Result<(), RadError> {
+    tx_exec_requests.send(ExecutiveRequest::Checkpoint { state })?;
+    let deadline = Instant::now() + Duration::from_secs(FLUSH_TIMEOUT);
+    while let Some(wait) = deadline.checked_duration_since(Instant::now()) {
+        match rx_exec_responses.recv_timeout(wait) {
+            Ok(ExecutiveResponse::Checkpoint { success }) => {
+                info!("checkpoint flush success={}", success);
+                return Ok(());
+            }
+            Ok(_) => {}
+            Err(RecvTimeoutError::Timeout) => break,
+            Err(RecvTimeoutError::Disconnected) => return Err(RadError::ChannelReceive),
+        }
+    }
+    warn!("checkpoint flush timed out");
+    Ok(())
+}
+
+/// Run dynamic modules, logging their results and returning how many ran.
+fn execute_modules(
+    shared_state: &Mutex<Box<State>>,
+    shared_outputs: &Mutex<ModuleOutputs>,
+    runtimes: &mut ModuleRuntimes,
+    config: &Config,
+) -> Result<usize, RadError> {
+    if !config.modules {
+        return Ok(0);
+    }
+
+    let lock = || shared_state.lock().map_err(|_| RadError::Mutex);
+    let mut executed = 0;
+    let modules = lock()?.modules.len();
+    for (i, runtime) in runtimes.iter_mut().enumerate().take(modules) {
+        let run = match lock()?.modules[i].prepare(runtime)? {
+            Some(run) => run,
+            None => continue,
+        };
+
+        // Run the VM without the state locked, so scrubbing and control requests can continue
+        let (tx_events, rx_events) = channel();
+        let result = run.execute(&config.file_access, &tx_events);
+
+        let mut state = lock()?;
+        for e in rx_events.try_iter() {
+            state.log(Severity::Info, &format!("module {}: {}", i, e));
+        }
+        match result {
+            Ok(data) => {
+                executed += 1;
+                runtime.complete(&run, &data);
+                if !data.is_empty() {
+                    state.log(
+                        Severity::Info,
+                        &format!("module {} result: {}", i, hex::encode(&data)),
+                    );
+                }
+                shared_outputs.lock().map_err(|_| RadError::Mutex)?[i] = data;
+            }
+            Err(e) => {
+                let e = format!("module {} exec error: {}", i, e);
+                state.log(Severity::Error, &e);
+                error!("{}", e);
+                state.modules[i].set_enabled(false)?;
+            }
+        }
+    }
+    Ok(executed)
 }
 
 /// Load protected state from a checkpoint.
-fn load_checkpoint<P>(path: P) -> Result<Box<State>, RadError>
+fn load_checkpoint<P>(path: P, key: &[u8]) -> Result<Box<State>, RadError>
 where
     P: AsRef<Path>,
 {
-    let input = File::open(path.as_ref())?;
-    let mut state: Box<State> = bincode::deserialize_from(input)?;
+    let mut state = checkpoint::read_checkpoint(path, key)?;
     state.restarts.increment(1)?;
-    for module in &mut state.modules {
-        module.verify_code()?;
-        module.set_enabled(false)?;
+    for i in 0..state.modules.len() {
+        state.verify_module(i)?;
+        state.modules[i].set_enabled(false)?;
     }
     Ok(state)
 }
 
+/// Move a checkpoint written before rotation into the newest generation, unless one exists.
+fn migrate_legacy_checkpoint(base: &Path) -> Result<(), RadError> {
+    let newest = checkpoint_generation_path(base, 0);
+    if base.is_file() && !newest.exists() {
+        info!(
+            "migrating legacy checkpoint {} to {}",
+            base.display(),
+            newest.display()
+        );
+        std::fs::rename(base, newest)?;
+    }
+    Ok(())
+}
+
+/// Load protected state from the newest valid checkpoint generation.
+fn load_checkpoints<P>(base: P, generations: usize, key: &[u8]) -> Option<Box<State>>
+where
+    P: AsRef<Path>,
+{
+    if let Err(e) = migrate_legacy_checkpoint(base.as_ref()) {
+        warn!("legacy checkpoint migration error: {}", e);
+    }
+    for generation in 0..generations {
+        let path = checkpoint_generation_path(&base, generation);
+        if !path.is_file() {
+            continue;
+        }
+        match load_checkpoint(&path, key) {
+            Ok(state) => {
+                info!(
+                    "loaded checkpoint generation {} from {}",
+                    generation,
+                    path.display()
+                );
+                return Some(state);
+            }
+            Err(e) => {
+                warn!(
+                    "checkpoint generation {} load error at {}: {}",
+                    generation,
+                    path.display(),
+                    e
+                );
+                if let RadError::Signature = e {
+                    info!("removing tampered checkpoint at {}", path.display());
+                    if let Err(e) = std::fs::remove_file(&path) {
+                        error!("unable to remove checkpoint: {}", e);
+                    }
+                }
+            }
+        }
+    }
+    None
+}
+
+/// Request a graceful reset, which the main loop performs on its next iteration.
+fn request_reset() {
+    SHUTDOWN.store(true, Ordering::SeqCst);
+}
+
+/// Whether a graceful reset has been requested.
+fn shutdown_requested() -> bool {
+    SHUTDOWN.load(Ordering::SeqCst)
+}
+
+/// Stop the firmware cooperatively, flushing a final checkpoint and removing the control socket.
+fn reset_graceful(
+    state: &State,
+    tx_exec_requests: &Sender<ExecutiveRequest>,
+    rx_exec_responses: &Receiver<ExecutiveResponse>,
+) {
+    info!("performing graceful reset");
+    SHUTDOWN.store(true, Ordering::SeqCst);
+    match checkpoint::encode_state(state) {
+        Ok(checkpoint) => {
+            if let Err(e) = flush_checkpoint(checkpoint, tx_exec_requests, rx_exec_responses) {
+                error!("final checkpoint: {}", e);
+            }
+        }
+        Err(e) => error!("final checkpoint: {}", e),
+    }
+    let command_path = Path::new(COMMAND_PATH);
+    if command_path.exists() {
+        if let Err(e) = std::fs::remove_file(command_path) {
+            error!("unable to remove control socket: {}", e);
+        }
+    }
+}
+
 /// Reset the firmware.
 fn reset() {
     std::process::exit(13);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_checkpoint_fallback() {
+        let dir = std::env::temp_dir().join(format!("rad_fw_checkpoints_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).expect("create directory");
+        let key = checkpoint::test_key();
+        let base = dir.join("rad.chkpt");
+
+        let state = Box::new(State::new().expect("state"));
+        let data = checkpoint::sign_checkpoint(state.as_ref());
+        std::fs::write(checkpoint_generation_path(&base, 1), &data).expect("write checkpoint");
+        std::fs::write(
+            checkpoint_generation_path(&base, 0),
+            &data[..data.len() / 2],
+        )
+        .expect("write truncated checkpoint");
+
+        let mut state = load_checkpoints(&base, 3, &key).expect("load checkpoint");
+        assert_eq!(state.restarts.get().expect("restarts"), 1);
+        assert!(load_checkpoints(&base, 1, &key).is_none());
+        std::fs::remove_dir_all(&dir).expect("remove directory");
+    }
+
+    #[test]
+    fn test_checkpoint_reverifies_code() {
+        let dir = std::env::temp_dir().join(format!("rad_fw_code_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).expect("create directory");
+        let key = checkpoint::test_key();
+        let base = dir.join("rad.chkpt");
+
+        // The newest generation holds a module marked verified whose code has since changed
+        let keys = ring::signature::Ed25519KeyPair::from_pkcs8(include_bytes!(
+            "../../data/rad_keys.pkcs8"
+        ))
+        .expect("module keys");
+        let mut code = vec![0u8; data::MAX_MODULE_SIZE];
+        code[0] = 0x95;
+        let signature = keys.sign(&code);
+        let mut state = Box::new(State::new().expect("state"));
+        state.modules[0]
+            .update(1, &code, signature.as_ref())
+            .expect("update");
+        assert!(state.modules[0].verify_code().expect("verify"));
+        std::fs::write(
+            checkpoint_generation_path(&base, 1),
+            checkpoint::sign_checkpoint(state.as_ref()),
+        )
+        .expect("write checkpoint");
+        state.modules[0]
+            .update(2, &[0xb7; 8], signature.as_ref())
+            .expect("update");
+        assert!(state.modules[0].is_verified().expect("verified"));
+        std::fs::write(
+            checkpoint_generation_path(&base, 0),
+            checkpoint::sign_checkpoint(state.as_ref()),
+        )
+        .expect("write checkpoint");
+
+        // It still loads, with the module's verification derived again from its code
+        let mut state = load_checkpoints(&base, 3, &key).expect("load checkpoint");
+        assert_eq!(state.modules[0].updated().expect("updated"), 2);
+        assert!(!state.modules[0].is_verified().expect("verified"));
+        std::fs::remove_dir_all(&dir).expect("remove directory");
+    }
+
+    #[test]
+    fn test_legacy_checkpoint_migration() {
+        let dir = std::env::temp_dir().join(format!("rad_fw_legacy_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).expect("create directory");
+        let key = checkpoint::test_key();
+        let base = dir.join("rad.chkpt");
+
+        let state = Box::new(State::new().expect("state"));
+        std::fs::write(&base, checkpoint::sign_checkpoint(state.as_ref())).expect("write");
+        let mut state = load_checkpoints(&base, 3, &key).expect("load checkpoint");
+        assert_eq!(state.restarts.get().expect("restarts"), 1);
+        assert!(!base.exists());
+        assert!(checkpoint_generation_path(&base, 0).is_file());
+        std::fs::remove_dir_all(&dir).expect("remove directory");
+    }
+
+    #[test]
+    fn test_checkpoint_signature() {
+        let dir = std::env::temp_dir().join(format!("rad_fw_signature_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).expect("create directory");
+        let key = checkpoint::test_key();
+        let path = dir.join("rad.chkpt");
+
+        let state = Box::new(State::new().expect("state"));
+        let mut data = checkpoint::sign_checkpoint(state.as_ref());
+        std::fs::write(&path, &data).expect("write checkpoint");
+        assert!(load_checkpoint(&path, &key).is_ok());
+
+        // Flip a counter bit the ECC would otherwise repair
+        data[0] ^= 0x01;
+        std::fs::write(&path, &data).expect("write tampered checkpoint");
+        assert!(matches!(
+            load_checkpoint(&path, &key),
+            Err(RadError::Signature)
+        ));
+
+        let base = dir.join("rad.chkpt.gen");
+        std::fs::write(checkpoint_generation_path(&base, 0), &data).expect("write checkpoint");
+        assert!(load_checkpoints(&base, 1, &key).is_none());
+        assert!(!checkpoint_generation_path(&base, 0).exists());
+        std::fs::remove_dir_all(&dir).expect("remove directory");
+    }
+
+    #[test]
+    fn test_reset_graceful() {
+        let mut state = Box::new(State::new().expect("state"));
+        state.log(Severity::Info, &"x".repeat(MAX_MESSAGE_SIZE));
+        let expected = checkpoint::encode_state(&state).expect("encode state");
+
+        let (tx_exec_requests, rx_exec_requests) = channel();
+        let (tx_exec_responses, rx_exec_responses) = channel();
+        let executive = spawn(move || match rx_exec_requests.recv() {
+            Ok(ExecutiveRequest::Checkpoint { state }) => {
+                tx_exec_responses
+                    .send(ExecutiveResponse::Checkpoint { success: true })
+                    .expect("send response");
+                state
+            }
+            request => panic!("unexpected request {:?}", request.map(|x| x.to_string())),
+        });
+
+        reset_graceful(&state, &tx_exec_requests, &rx_exec_responses);
+        assert!(shutdown_requested());
+        assert_eq!(executive.join().expect("executive"), expected);
+    }
+
+    #[test]
+    fn test_clock_regression() {
+        let mut state = Box::new(State::new().expect("state"));
+        let message = "x".repeat(MAX_MESSAGE_SIZE);
+        let logged = [1_620_000_000, 1_620_000_010, 1_619_999_000, 1_620_000_020];
+        for now in &logged {
+            state.log_at(*now, Severity::Info, &message);
+        }
+        let timestamps: Vec<_> = state
+            .ordered_events()
+            .map(|e| e.timestamp().expect("timestamp"))
+            .filter(|&t| t != 0)
+            .collect();
+        assert_eq!(timestamps, logged);
+    }
+
+    #[test]
+    fn test_event_rotation() {
+        let mut state = Box::new(State::new().expect("state"));
+        let message = |i: u64| format!("{:0>width$}", i, width = MAX_MESSAGE_SIZE);
+        for i in 0..40 {
+            state.log_at(1_620_000_000 + i, Severity::Info, &message(i));
+        }
+
+        let mut m = [0u8; MAX_MESSAGE_SIZE];
+        let mut retained = vec![];
+        for e in &mut state.events {
+            let t = e.get(&mut m).expect("event");
+            assert_ne!(t, 0);
+            retained.push(String::from_utf8_lossy(&m).to_string());
+        }
+        retained.sort();
+        let expected: Vec<_> = (8..40).map(message).collect();
+        assert_eq!(retained, expected);
+    }
+}
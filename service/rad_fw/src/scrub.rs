@@ -1,50 +1,532 @@
 //! Memory scrubbing.
 
-use crate::data::Repairable;
+use crate::checkpoint::{decode_state, encode_state};
+use crate::data::{Event, Repairable};
 use crate::{reset, RadError, State};
+use rad_common::{FieldRepairs, Severity};
+use rayon::prelude::*;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
 use std::thread::sleep;
-use std::time::Duration;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-macro_rules! check {
-    ($data:expr, $repairs:ident) => {
-        if !$data.verify()? {
-            $data.repair()?;
-            $repairs += 1;
+/// Outcome of checking a single field.
+enum Check {
+    Intact,
+    Repaired,
+    Failed(RadError),
+}
+
+/// Verify a field and repair it if needed.
+fn check_field<T: Repairable>(data: &mut T) -> Result<Check, RadError> {
+    if data.verify()? {
+        return Ok(Check::Intact);
+    }
+    Ok(match data.repair() {
+        Ok(()) => Check::Repaired,
+        Err(e) => Check::Failed(e),
+    })
+}
+
+/// Verify and repair independent fields in parallel, returning their outcomes in order.
+fn check_fields<T: Repairable + Send>(fields: &mut [T]) -> Vec<Result<Check, RadError>> {
+    fields.par_iter_mut().map(check_field).collect()
+}
+
+/// Field that could not be repaired.
+struct RepairFailure {
+    field: String,
+    error: RadError,
+    /// Whether the field could not be cleared and the state must be reset
+    fatal: bool,
+}
+
+impl std::fmt::Display for RepairFailure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.field, self.error)?;
+        if !self.fatal {
+            write!(f, " (cleared)")?;
         }
-    };
+        Ok(())
+    }
+}
+
+/// Outcome of a scrubbing pass.
+#[derive(Default)]
+struct Scrub {
+    repairs: u64,
+    failures: Vec<RepairFailure>,
 }
 
-/// Scrubbing thread.
-pub fn _scrub(mut state: Box<State>) {
-    if let Err(e) = _do_scrub(&mut state) {
-        error!("scrub protected state: {:?}", e);
-        state.log(&format!("{:?}", e));
-        reset();
+impl Scrub {
+    /// Record the outcome of checking a field.
+    fn record(&mut self, field: String, check: Check, stats: &mut RepairStats) {
+        match check {
+            Check::Intact => {}
+            Check::Repaired => {
+                self.repairs += 1;
+                stats.record(field);
+            }
+            Check::Failed(error) => self.failures.push(RepairFailure {
+                field,
+                error,
+                fatal: true,
+            }),
+        }
+    }
+
+    /// Return an error describing the fatal failures, if any.
+    fn fatal(&self) -> Result<(), RadError> {
+        let fatal: Vec<_> = self
+            .failures
+            .iter()
+            .filter(|x| x.fatal)
+            .map(|x| x.to_string())
+            .collect();
+        if fatal.is_empty() {
+            Ok(())
+        } else {
+            Err(RadError::Repair(fatal.join("; ")))
+        }
+    }
+}
+
+/// Per-field repair statistics, kept outside the protected state.
+#[derive(Default)]
+pub struct RepairStats {
+    fields: BTreeMap<String, (u64, u64)>,
+    passes: u64,
+}
+
+impl RepairStats {
+    /// Return the number of scrubbing passes performed.
+    pub fn passes(&self) -> u64 {
+        self.passes
+    }
+
+    /// Record a repair of a field.
+    fn record(&mut self, field: String) {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|x| x.as_secs())
+            .unwrap_or(0);
+        let entry = self.fields.entry(field).or_insert((0, 0));
+        entry.0 = entry.0.saturating_add(1);
+        entry.1 = now;
     }
+
+    /// Return the statistics for every repaired field.
+    pub fn fields(&self) -> Vec<FieldRepairs> {
+        self.fields
+            .iter()
+            .map(|(field, (repairs, last_repaired))| {
+                FieldRepairs::new(field.clone(), *repairs, *last_repaired)
+            })
+            .collect()
+    }
+}
+
+/// Periodic scrubber of the shared protected state.
+pub struct Scrubber {
+    pub state: Arc<Mutex<Box<State>>>,
+    pub stats: Arc<Mutex<RepairStats>>,
+    /// Delay between scrubbing passes
+    pub interval: Duration,
+    /// Watchdog timer kicked before every pass
+    pub watchdog: Arc<Mutex<Instant>>,
+    /// Irreparable corruptions, handled by the main loop
+    pub tx_failures: Sender<RadError>,
+    /// Set to stop scrubbing during a graceful reset
+    pub stop: Arc<AtomicBool>,
 }
 
-/// Scrubbing thread.
-fn _do_scrub(state: &mut Box<State>) -> Result<(), RadError> {
-    debug!("executing scrubbing thread");
+impl Scrubber {
+    /// Scrubbing thread.
+    pub fn scrub(self) {
+        if let Err(e) = self.run(None) {
+            error!("scrub protected state: {:?}", e);
+            reset();
+        }
+    }
+
+    /// Scrub until stopped or the deadline, if any.
+    ///
+    /// Irreparable corruptions are logged and reported to the main loop, and scrubbing carries on
+    /// so the watchdog keeps being kicked until the main loop resets.
+    fn run(&self, deadline: Option<Instant>) -> Result<(), RadError> {
+        debug!("executing scrubbing thread every {:?}", self.interval);
 
-    loop {
-        check_state(state)?;
-        sleep(Duration::from_secs(1));
+        while !self.stop.load(Ordering::SeqCst)
+            && !matches!(deadline, Some(x) if Instant::now() >= x)
+        {
+            *self.watchdog.lock().map_err(|_| RadError::Mutex)? = Instant::now();
+            {
+                let mut state = self.state.lock().map_err(|_| RadError::Mutex)?;
+                let mut stats = self.stats.lock().map_err(|_| RadError::Mutex)?;
+                stats.passes += 1;
+                if let Err(e) = check_state(&mut state, &mut stats) {
+                    error!("scrub protected state: {}", e);
+                    self.tx_failures.send(e)?;
+                }
+            }
+            sleep(self.interval);
+        }
+        Ok(())
     }
 }
 
 /// Check a state for memory errors and repair them.
-pub fn check_state(state: &mut Box<State>) -> Result<(), RadError> {
-    let mut repairs = 0;
-    check!(state.repairs, repairs);
-    check!(state.restarts, repairs);
-    check!(state.event_index, repairs);
-    for event in &mut state.events {
-        check!(event, repairs);
-    }
-    for module in &mut state.modules {
-        check!(module, repairs);
-    }
-    state.repairs.increment(repairs)?;
-    Ok(())
+///
+/// Fields that cannot be repaired are logged as events and scrubbing continues with the rest.
+/// Unrepairable event log entries are cleared and counted as failed repairs; any other
+/// unrepairable field is an error, after which the state must be reset.
+pub fn check_state(state: &mut Box<State>, stats: &mut RepairStats) -> Result<(), RadError> {
+    let scrub = scrub_state(state, stats)?;
+    for failure in &scrub.failures {
+        error!("unrepairable field {}", failure);
+        state.log(Severity::Error, &format!("repair failed: {}", failure));
+    }
+    state.repairs.increment(scrub.repairs)?;
+    let cleared = scrub.failures.iter().filter(|x| !x.fatal).count() as u64;
+    if cleared > 0 {
+        state.repairs_failed.increment(cleared)?;
+    }
+    scrub.fatal()
+}
+
+/// Repair a state without recording the repairs, returning how many were made.
+pub fn repair_state(state: &mut State, stats: &mut RepairStats) -> Result<u64, RadError> {
+    let scrub = scrub_state(state, stats)?;
+    scrub.fatal()?;
+    Ok(scrub.repairs)
+}
+
+/// Verify and repair every field of a state, collecting the fields that could not be repaired.
+///
+/// Events and modules are checked in parallel; outcomes are recorded in field order.
+fn scrub_state(state: &mut State, stats: &mut RepairStats) -> Result<Scrub, RadError> {
+    let mut scrub = Scrub::default();
+    let counters = [
+        ("repairs", &mut state.repairs),
+        ("repairs_failed", &mut state.repairs_failed),
+        ("restarts", &mut state.restarts),
+        ("event_index", &mut state.event_index),
+    ];
+    for (field, data) in counters {
+        scrub.record(field.to_string(), check_field(data)?, stats);
+    }
+    let events = check_fields(&mut state.events);
+    for (i, check) in events.into_iter().enumerate() {
+        let check = check?;
+        let cleared = matches!(check, Check::Failed(_));
+        scrub.record(format!("events[{}]", i), check, stats);
+        if cleared {
+            // An unrepairable log entry is dropped rather than forcing a reset
+            state.events[i] = Event::new()?;
+            if let Some(failure) = scrub.failures.last_mut() {
+                failure.fatal = false;
+            }
+        }
+    }
+    let modules = check_fields(&mut state.modules);
+    for (i, check) in modules.into_iter().enumerate() {
+        scrub.record(format!("modules[{}]", i), check?, stats);
+    }
+    Ok(scrub)
+}
+
+/// Record an irreparable corruption against the last good checkpoint, returning the new checkpoint.
+pub fn record_repair_failure(checkpoint: &[u8], reason: &str) -> Result<Vec<u8>, RadError> {
+    let mut state = decode_state(checkpoint)?;
+    repair_state(&mut state, &mut RepairStats::default())?;
+    state.repairs_failed.increment(1)?;
+    state.log(Severity::Error, &format!("reset after {}", reason));
+    encode_state(&state)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::{Module, SHARDS, U64};
+    use rad_common::MAX_MESSAGE_SIZE;
+
+    /// Flip bits in the first byte of each of the given shards of a critical u64.
+    fn corrupt(x: &mut U64, shards: &[usize], mask: u8) {
+        for shard in shards {
+            x.data[*shard][0] ^= mask;
+        }
+    }
+
+    /// Flip a bit in a parity shard of a module's code, which is always repairable.
+    fn corrupt_code(module: &mut Module) {
+        module.code_field().data[SHARDS - 1][0] ^= 0x01;
+    }
+
+    #[test]
+    fn test_repair_failure() {
+        let mut state = Box::new(State::new().expect("state"));
+        let checkpoint = encode_state(&state).expect("encode state");
+
+        // Corrupt more shards of the repair counter than there is parity
+        corrupt(&mut state.repairs, &[0, 1, 2], 0xff);
+        assert!(check_state(&mut state, &mut RepairStats::default()).is_err());
+
+        let checkpoint =
+            record_repair_failure(&checkpoint, "repair error").expect("record failure");
+        let mut state = decode_state(&checkpoint).expect("decode state");
+        assert_eq!(state.repairs_failed.get().expect("repairs failed"), 1);
+        assert_eq!(state.repairs.get().expect("repairs"), 0);
+        assert!(logged(&mut state)
+            .iter()
+            .any(|m| m == "reset after repair error"));
+    }
+
+    /// Return the logged event messages.
+    fn logged(state: &mut State) -> Vec<String> {
+        let mut m = [0u8; MAX_MESSAGE_SIZE];
+        state
+            .events
+            .iter_mut()
+            .map(|e| {
+                e.get(&mut m).expect("event");
+                String::from_utf8_lossy(&m)
+                    .trim_end_matches('\0')
+                    .to_string()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_unrepairable_field() {
+        let mut state = Box::new(State::new().expect("state"));
+
+        // Restart counter and an event timestamp beyond repair, and a repairable module
+        corrupt(&mut state.restarts, &[0, 1, 2], 0xff);
+        corrupt(state.events[5].timestamp_field(), &[0, 1, 2], 0xff);
+        corrupt_code(&mut state.modules[3]);
+
+        let mut stats = RepairStats::default();
+        match check_state(&mut state, &mut stats) {
+            Err(RadError::Repair(e)) => {
+                assert!(e.starts_with("restarts: "), "{}", e);
+                assert!(!e.contains("events[5]"), "{}", e);
+            }
+            result => panic!("expected repair failure, got {:?}", result),
+        }
+
+        let messages = logged(&mut state);
+        assert!(messages
+            .iter()
+            .any(|m| m.starts_with("repair failed: restarts: ") && m.contains("checksum")));
+        assert!(messages
+            .iter()
+            .any(|m| m.starts_with("repair failed: events[5]: ") && m.ends_with("(cleared)")));
+        assert!(state.events[5].verify().expect("verify"));
+        assert!(state.modules[3].verify().expect("verify"));
+        assert_eq!(
+            stats.fields(),
+            vec![FieldRepairs::new(
+                "modules[3]".to_string(),
+                1,
+                stats.fields()[0].last_repaired
+            )]
+        );
+    }
+
+    #[test]
+    fn test_repair_failure_counted() {
+        let mut state = Box::new(State::new().expect("state"));
+
+        // An event timestamp beyond repair
+        corrupt(state.events[2].timestamp_field(), &[0, 1, 2], 0xff);
+        check_state(&mut state, &mut RepairStats::default()).expect("check state");
+        assert_eq!(state.repairs_failed.get().expect("repairs failed"), 1);
+
+        let path = std::env::temp_dir().join(format!(
+            "rad_fw_repairs_failed_{}.chkpt",
+            std::process::id()
+        ));
+        std::fs::write(&path, crate::checkpoint::sign_checkpoint(&state)).expect("write");
+        let mut state = crate::checkpoint::read_checkpoint(&path, &crate::checkpoint::test_key())
+            .expect("read checkpoint");
+        std::fs::remove_file(&path).expect("remove checkpoint");
+        assert_eq!(state.repairs_failed.get().expect("repairs failed"), 1);
+    }
+
+    #[test]
+    fn test_checksum_event() {
+        let mut state = Box::new(State::new().expect("state"));
+        corrupt(&mut state.restarts, &[0, 1, 2], 0xff);
+        let (stored, computed) = match state.restarts.peek() {
+            Err(RadError::Checksum(stored, computed)) => (stored, computed),
+            result => panic!("expected checksum error, got {:?}", result),
+        };
+        assert_ne!(stored, computed);
+
+        assert!(check_state(&mut state, &mut RepairStats::default()).is_err());
+        let expected = format!(
+            "repair failed: restarts: checksum failure: stored={:016x} != computed={:016x}",
+            stored, computed
+        );
+        assert!(logged(&mut state).contains(&expected));
+    }
+
+    #[test]
+    fn test_module_checksum_event() {
+        let mut state = Box::new(State::new().expect("state"));
+        for shard in 0..3 {
+            state.modules[2].code_field().data[shard][0] ^= 0xff;
+        }
+        let (stored, computed) = match state.verify_module(2) {
+            Err(RadError::Checksum(stored, computed)) => (stored, computed),
+            result => panic!("expected checksum error, got {:?}", result),
+        };
+        let expected = format!(
+            "verify module 2: checksum failure: stored={:016x} != computed={:016x}",
+            stored, computed
+        );
+        assert!(logged(&mut state).contains(&expected));
+    }
+
+    #[test]
+    fn test_scrub_interval() {
+        let (tx_failures, rx_failures) = std::sync::mpsc::channel();
+        let passes = |interval| {
+            let scrubber = Scrubber {
+                state: Arc::new(Mutex::new(Box::new(State::new().expect("state")))),
+                stats: Arc::new(Mutex::new(RepairStats::default())),
+                interval,
+                watchdog: Arc::new(Mutex::new(Instant::now())),
+                tx_failures: tx_failures.clone(),
+                stop: Arc::new(AtomicBool::new(false)),
+            };
+            let deadline = Instant::now() + Duration::from_millis(300);
+            scrubber.run(Some(deadline)).expect("scrub");
+            let passes = scrubber.stats.lock().expect("stats").passes();
+            passes
+        };
+        let fast = passes(Duration::from_millis(10));
+        let slow = passes(Duration::from_millis(100));
+        assert!(fast > slow, "fast={} slow={}", fast, slow);
+        assert!(slow >= 1);
+        assert!(rx_failures.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_scrub_stop() {
+        let scrubber = Scrubber {
+            state: Arc::new(Mutex::new(Box::new(State::new().expect("state")))),
+            stats: Arc::new(Mutex::new(RepairStats::default())),
+            interval: Duration::from_secs(3600),
+            watchdog: Arc::new(Mutex::new(Instant::now())),
+            tx_failures: std::sync::mpsc::channel().0,
+            stop: Arc::new(AtomicBool::new(true)),
+        };
+        scrubber.run(None).expect("scrub");
+        assert_eq!(scrubber.stats.lock().expect("stats").passes(), 0);
+    }
+
+    #[test]
+    fn test_scrub_after_failure() {
+        let mut state = Box::new(State::new().expect("state"));
+        corrupt(&mut state.restarts, &[0, 1, 2], 0xff);
+        let (tx_failures, rx_failures) = std::sync::mpsc::channel();
+        let started = Instant::now();
+        let scrubber = Scrubber {
+            state: Arc::new(Mutex::new(state)),
+            stats: Arc::new(Mutex::new(RepairStats::default())),
+            interval: Duration::from_millis(10),
+            watchdog: Arc::new(Mutex::new(started)),
+            tx_failures,
+            stop: Arc::new(AtomicBool::new(false)),
+        };
+        scrubber
+            .run(Some(started + Duration::from_millis(100)))
+            .expect("scrub");
+
+        // Every pass reports the failure and kicks the watchdog
+        let passes = scrubber.stats.lock().expect("stats").passes();
+        assert!(passes > 1, "passes={}", passes);
+        assert_eq!(rx_failures.try_iter().count() as u64, passes);
+        assert!(*scrubber.watchdog.lock().expect("watchdog") > started);
+    }
+
+    #[test]
+    fn test_parallel_scrub() {
+        // Corrupt every other event timestamp and the code of every module
+        let corrupted = || {
+            let mut state = Box::new(State::new().expect("state"));
+            for event in state.events.iter_mut().step_by(2) {
+                corrupt(event.timestamp_field(), &[0], 0x01);
+            }
+            for module in &mut state.modules {
+                corrupt_code(module);
+            }
+            state
+        };
+
+        let mut parallel = corrupted();
+        let mut parallel_stats = RepairStats::default();
+        let scrub = scrub_state(&mut parallel, &mut parallel_stats).expect("scrub");
+        assert!(scrub.failures.is_empty());
+
+        // Reference pass, one field at a time
+        let mut sequential = corrupted();
+        let mut sequential_stats = RepairStats::default();
+        let mut reference = Scrub::default();
+        for (i, event) in sequential.events.iter_mut().enumerate() {
+            let check = check_field(event).expect("check");
+            reference.record(format!("events[{}]", i), check, &mut sequential_stats);
+        }
+        for (i, module) in sequential.modules.iter_mut().enumerate() {
+            let check = check_field(module).expect("check");
+            reference.record(format!("modules[{}]", i), check, &mut sequential_stats);
+        }
+
+        let counts = |stats: &RepairStats| -> Vec<(String, u64)> {
+            stats
+                .fields()
+                .into_iter()
+                .map(|x| (x.field, x.repairs))
+                .collect()
+        };
+        assert_eq!(scrub.repairs, 20);
+        assert_eq!(scrub.repairs, reference.repairs);
+        assert_eq!(counts(&parallel_stats), counts(&sequential_stats));
+        assert_eq!(
+            bincode::serialize(parallel.as_ref()).expect("serialize"),
+            bincode::serialize(sequential.as_ref()).expect("serialize")
+        );
+    }
+
+    #[test]
+    fn test_field_repairs() {
+        let mut state = Box::new(State::new().expect("state"));
+        let mut stats = RepairStats::default();
+
+        // Hit the restart counter every round and the last module's code once
+        for round in 0..3 {
+            corrupt(&mut state.restarts, &[0], 0x01);
+            if round == 0 {
+                corrupt_code(&mut state.modules[3]);
+            }
+            check_state(&mut state, &mut stats).expect("check state");
+        }
+
+        let fields = stats.fields();
+        let repairs = |name: &str| {
+            fields
+                .iter()
+                .find(|x| x.field == name)
+                .map(|x| x.repairs)
+                .unwrap_or(0)
+        };
+        assert_eq!(repairs("restarts"), 3);
+        assert_eq!(repairs("modules[3]"), 1);
+        assert_eq!(repairs("repairs"), 0);
+        assert!(fields.iter().all(|x| x.last_repaired > 0));
+        assert_eq!(state.repairs.get().expect("repairs"), 4);
+    }
 }
@@ -1,22 +1,21 @@
 //! Service requests.
 
-use crate::{reset, RadError};
-use byteorder::{ReadBytesExt, WriteBytesExt, BE};
+use crate::{request_reset, RadError};
+use rad_common::frame::{read_frame, write_frame, MAX_FRAME_SIZE};
+use rad_common::message;
 use rad_common::{ExecutiveRequest, ExecutiveResponse, SERVICE_PATH};
-use std::io::{Read, Write};
 use std::os::unix::net::UnixStream;
 use std::sync::mpsc::{Receiver, Sender};
 
-/// Proxy service requests.
+/// Proxy service requests until the request channel is closed.
 pub fn proxy_requests(
     rx_exec_requests: Receiver<ExecutiveRequest>,
     tx_exec_responses: Sender<ExecutiveResponse>,
-) -> Result<(), RadError> {
+) {
     if let Err(e) = do_proxy_requests(rx_exec_requests, tx_exec_responses) {
         error!("proxy service requests: {:?}", e);
-        reset();
+        request_reset();
     }
-    Ok(())
 }
 
 /// Proxy service requests.
@@ -26,16 +25,16 @@ fn do_proxy_requests(
 ) -> Result<(), RadError> {
     info!("proxying service requests to {}", SERVICE_PATH);
     let mut socket = UnixStream::connect(SERVICE_PATH)?;
-    loop {
-        let request = rx_exec_requests.recv()?;
+    let mut buffer = vec![];
+    // The main loop closes the request channel when it stops
+    while let Ok(request) = rx_exec_requests.recv() {
         debug!("executive request: {}", request);
-        let buffer = bincode::serialize(&request)?;
-        socket.write_u32::<BE>(buffer.len() as _)?;
-        socket.write_all(&buffer)?;
-        let size = socket.read_u32::<BE>()?;
-        let mut buffer = vec![0u8; size as _];
-        socket.read_exact(&mut buffer)?;
-        let response: ExecutiveResponse = bincode::deserialize(&buffer)?;
+        buffer.clear();
+        message::encode_into(&mut buffer, &request)?;
+        write_frame(&mut socket, &buffer)?;
+        read_frame(&mut socket, &mut buffer, MAX_FRAME_SIZE)?;
+        let response: ExecutiveResponse = message::decode(&buffer)?;
         tx_exec_responses.send(response)?;
     }
+    Ok(())
 }
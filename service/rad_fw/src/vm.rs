@@ -1,14 +1,63 @@
 //! Module VM.
 
+use crate::data::MAX_MODULE_SIZE;
 use crate::RadError;
+use rad_common::MAX_MESSAGE_SIZE;
+use rbpf::ebpf;
 use rbpf::memory_region::{AccessType, MemoryMapping, MemoryRegion};
 use rbpf::user_error::UserError;
 use rbpf::vm::{
     EbpfVm, Executable, InstructionMeter, ProgramResult, SyscallObject, SyscallRegistry,
 };
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 const DECODER: &[u8] = include_bytes!("../../data/decode.so");
 
+/// Default number of instructions a module may execute.
+pub const INSTRUCTION_BUDGET: u64 = 1024;
+
+/// Largest instruction budget a module may be given.
+pub const MAX_INSTRUCTION_BUDGET: u64 = 64 * 1024;
+
+/// Wall-clock limit on a single program run, well inside the watchdog timeout.
+pub const EXECUTION_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// File read syscall number.
+const FILE_READ: u32 = 23;
+/// Sensor read syscall number.
+const SENSOR_READ: u32 = 24;
+/// Event log syscall number.
+const EVENT_LOG: u32 = 25;
+/// Syscalls registered for every program.
+const SYSCALLS: &[u32] = &[FILE_READ, SENSOR_READ, EVENT_LOG];
+/// Number of events a module may log per run, so one module cannot rotate out the whole log.
+pub const MAX_MODULE_EVENTS: usize = 4;
+/// Number of bytes the sensor read syscall stores.
+pub const SENSOR_READ_SIZE: u64 = 16;
+/// Fixed-point scale of sensor values passed to modules.
+pub const SENSOR_SCALE: f64 = 1000.0;
+
+lazy_static! {
+    /// Latest fuel and radiation readings relayed from the executive.
+    static ref SENSORS: Mutex<Option<(f64, f64)>> = Mutex::new(None);
+}
+
+/// Record the latest fuel and radiation readings for modules to read.
+pub fn record_sensors(fuel: f64, radiation: f64) {
+    if let Ok(mut sensors) = SENSORS.lock() {
+        *sensors = Some((fuel, radiation));
+    }
+}
+
+/// Latest recorded sensor readings.
+fn sensors() -> Option<(f64, f64)> {
+    SENSORS.lock().ok().and_then(|x| *x)
+}
+
 /// Instruction meter.
 struct RadMeter {
     remaining: u64,
@@ -16,14 +65,14 @@ struct RadMeter {
 
 impl RadMeter {
     /// Create a new meter.
-    fn new() -> Self {
-        Self { remaining: 1024 }
+    fn new(remaining: u64) -> Self {
+        Self { remaining }
     }
 }
 
 impl InstructionMeter for RadMeter {
     fn consume(&mut self, amount: u64) {
-        self.remaining -= amount;
+        self.remaining = self.remaining.saturating_sub(amount);
     }
 
     fn get_remaining(&self) -> u64 {
@@ -31,8 +80,44 @@ impl InstructionMeter for RadMeter {
     }
 }
 
+/// Paths modules may read through the file read syscall.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FileAccess {
+    /// Canonical path prefixes that may be read; empty denies every read
+    Allow(Vec<PathBuf>),
+    /// Any path not mentioning "rad", with no canonicalization
+    Insecure,
+}
+
+impl Default for FileAccess {
+    fn default() -> Self {
+        FileAccess::Allow(vec![])
+    }
+}
+
+impl FileAccess {
+    /// Resolve a module-supplied path, if it may be read.
+    fn permit(&self, path: &str) -> Option<PathBuf> {
+        match self {
+            FileAccess::Allow(prefixes) => {
+                // Resolve ".." and symlinks before comparing against the allowlist
+                let path = std::fs::canonicalize(path).ok()?;
+                if prefixes.iter().any(|x| path.starts_with(x)) {
+                    Some(path)
+                } else {
+                    None
+                }
+            }
+            FileAccess::Insecure if !path.contains("rad") => Some(PathBuf::from(path)),
+            FileAccess::Insecure => None,
+        }
+    }
+}
+
 /// File read syscall.
-struct FileRead;
+struct FileRead {
+    access: FileAccess,
+}
 
 impl SyscallObject<UserError> for FileRead {
     fn call(
@@ -55,7 +140,7 @@ impl SyscallObject<UserError> for FileRead {
 
         // Try to read from the path and assign into memory
         if let Ok(path) = String::from_utf8(path_bytes) {
-            if !path.contains("rad") {
+            if let Some(path) = self.access.permit(&path) {
                 if let Ok(data) = std::fs::read_to_string(&path) {
                     let data = data.into_bytes();
                     let host_store_addr = question_mark!(
@@ -78,6 +163,100 @@ impl SyscallObject<UserError> for FileRead {
     }
 }
 
+/// Sensor read syscall.
+///
+/// Called with r1 set to a store address and r2 to the buffer size, which must be at least
+/// `SENSOR_READ_SIZE`. Stores fuel then radiation as little-endian u64 values scaled by
+/// `SENSOR_SCALE` and returns the number of bytes stored, or 0 if there is no reading yet or the
+/// buffer is too small.
+struct SensorRead {
+    readings: Option<(f64, f64)>,
+}
+
+impl SyscallObject<UserError> for SensorRead {
+    fn call(
+        &mut self,
+        store_addr: u64,
+        store_size: u64,
+        _arg3: u64,
+        _arg4: u64,
+        _arg5: u64,
+        memory_mapping: &MemoryMapping,
+        result: &mut ProgramResult<UserError>,
+    ) {
+        debug!("sensor_read({:x}, {:x})", store_addr, store_size);
+
+        if let Some((fuel, radiation)) = self.readings {
+            if store_size >= SENSOR_READ_SIZE {
+                let host_store_addr = question_mark!(
+                    memory_mapping.map(AccessType::Store, store_addr, SENSOR_READ_SIZE),
+                    result
+                );
+                let mut data = ((fuel * SENSOR_SCALE) as u64).to_le_bytes().to_vec();
+                data.extend_from_slice(&((radiation * SENSOR_SCALE) as u64).to_le_bytes());
+                for (i, x) in data.iter().enumerate() {
+                    unsafe {
+                        let p = (host_store_addr + (i as u64)) as *mut u8;
+                        *p = *x;
+                    }
+                }
+                *result = Ok(SENSOR_READ_SIZE);
+                return;
+            }
+        }
+
+        *result = Ok(0);
+    }
+}
+
+/// Event log syscall.
+///
+/// Called with r1 set to a message address and r2 to its size, at most `MAX_MESSAGE_SIZE`.
+/// Queues the message for the firmware event log and returns its size, or 0 if the message is
+/// too large or the module has already logged `MAX_MODULE_EVENTS` messages this run.
+struct EventLog {
+    events: Sender<String>,
+    logged: usize,
+}
+
+impl SyscallObject<UserError> for EventLog {
+    fn call(
+        &mut self,
+        load_addr: u64,
+        load_size: u64,
+        _arg3: u64,
+        _arg4: u64,
+        _arg5: u64,
+        memory_mapping: &MemoryMapping,
+        result: &mut ProgramResult<UserError>,
+    ) {
+        debug!("event_log({:x}, {:x})", load_addr, load_size);
+
+        if load_size as usize <= MAX_MESSAGE_SIZE && self.logged < MAX_MODULE_EVENTS {
+            let host_load_addr = question_mark!(
+                memory_mapping.map(AccessType::Load, load_addr, load_size),
+                result
+            );
+            let mut data = vec![];
+            for i in 0..load_size {
+                let p = (host_load_addr + i) as *const u8;
+                data.push(unsafe { *p });
+            }
+            if self
+                .events
+                .send(String::from_utf8_lossy(&data).to_string())
+                .is_ok()
+            {
+                self.logged += 1;
+                *result = Ok(load_size);
+                return;
+            }
+        }
+
+        *result = Ok(0);
+    }
+}
+
 /// Send a control response.
 struct SendMessage {
     data: Vec<u8>,
@@ -115,7 +294,15 @@ impl SyscallObject<UserError> for SendMessage {
     }
 }
 
-pub fn execute_elf(code: &[u8], memory: &mut [u8], decode: bool) -> Result<u64, RadError> {
+pub fn execute_elf(
+    code: &[u8],
+    memory: &mut [u8],
+    decode: bool,
+    budget: u64,
+    access: &FileAccess,
+    events: &Sender<String>,
+) -> Result<u64, RadError> {
+    check_size(code)?;
     let code = if decode {
         decode_code(code)?
     } else {
@@ -123,11 +310,34 @@ pub fn execute_elf(code: &[u8], memory: &mut [u8], decode: bool) -> Result<u64,
     };
     let exe_conf = rbpf::vm::Config::default();
     let exe = Executable::<UserError, RadMeter>::from_elf(&code, None, exe_conf)?;
-    execute(exe, memory)
+    validate_program(exe.as_ref())?;
+    execute(
+        exe,
+        memory,
+        budget,
+        FileRead {
+            access: access.clone(),
+        },
+        SensorRead {
+            readings: sensors(),
+        },
+        EventLog {
+            events: events.clone(),
+            logged: 0,
+        },
+    )
 }
 
-/// Execute a program.
-pub fn execute_bytes(code: &[u8], memory: &mut [u8], decode: bool) -> Result<u64, RadError> {
+/// Execute a program with an instruction budget.
+pub fn execute_bytes(
+    code: &[u8],
+    memory: &mut [u8],
+    decode: bool,
+    budget: u64,
+    access: &FileAccess,
+    events: &Sender<String>,
+) -> Result<u64, RadError> {
+    check_size(code)?;
     let code = if decode {
         decode_code(code)?
     } else {
@@ -135,35 +345,176 @@ pub fn execute_bytes(code: &[u8], memory: &mut [u8], decode: bool) -> Result<u64
     };
     let exe_conf = rbpf::vm::Config::default();
     let exe = Executable::<UserError, RadMeter>::from_text_bytes(&code, None, exe_conf)?;
-    execute(exe, memory)
+    validate_program(exe.as_ref())?;
+    execute(
+        exe,
+        memory,
+        budget,
+        FileRead {
+            access: access.clone(),
+        },
+        SensorRead {
+            readings: sensors(),
+        },
+        EventLog {
+            events: events.clone(),
+            logged: 0,
+        },
+    )
+}
+
+/// Execute a module program, decoding included, under a single wall-clock deadline.
+///
+/// The running flag stays set until the worker finishes, even after the deadline has passed.
+pub fn execute_module(
+    code: &[u8],
+    memory: &mut [u8],
+    decode: bool,
+    budget: u64,
+    access: &FileAccess,
+    events: &Sender<String>,
+    running: &Arc<AtomicBool>,
+) -> Result<u64, RadError> {
+    let code = code.to_owned();
+    let access = access.clone();
+    let events = events.clone();
+    execute_with_deadline(memory, EXECUTION_TIMEOUT, running, move |memory| {
+        execute_bytes(&code, memory, decode, budget, &access, &events)
+    })
+}
+
+/// Check whether a program makes no syscalls, so each run produces the same output.
+pub fn is_pure(code: &[u8]) -> bool {
+    (0..code.len() / ebpf::INSN_SIZE).all(|pc| {
+        let insn = ebpf::get_insn(code, pc);
+        insn.opc != ebpf::CALL_IMM || !SYSCALLS.contains(&(insn.imm as u32))
+    })
+}
+
+/// Check that a program fits in a module before parsing it.
+fn check_size(code: &[u8]) -> Result<(), RadError> {
+    if code.len() > MAX_MODULE_SIZE {
+        return Err(RadError::Vm(format!(
+            "program size {} exceeds maximum {}",
+            code.len(),
+            MAX_MODULE_SIZE
+        )));
+    }
+    Ok(())
 }
 
-/// Decode a program.
+/// Check that a parsed program can run before executing it.
+fn validate_program(exe: &dyn Executable<UserError, RadMeter>) -> Result<(), RadError> {
+    let (_, text) = exe.get_text_bytes()?;
+    let instructions = text.len() / ebpf::INSN_SIZE;
+    // Calls must resolve to a registered syscall or a function in the program
+    for pc in 0..instructions {
+        let insn = ebpf::get_insn(text, pc);
+        if insn.opc == ebpf::CALL_IMM {
+            let hash = insn.imm as u32;
+            if !SYSCALLS.contains(&hash) && exe.lookup_bpf_function(hash).is_none() {
+                return Err(RadError::Vm(format!(
+                    "program calls unregistered syscall {:#x} at instruction {}",
+                    hash, pc
+                )));
+            }
+        }
+    }
+
+    let entrypoint = exe
+        .get_entrypoint_instruction_offset()
+        .map_err(|_| RadError::Vm("program has no entrypoint".to_string()))?;
+    if entrypoint >= instructions {
+        return Err(RadError::Vm(format!(
+            "program entrypoint {} outside {} instructions",
+            entrypoint, instructions
+        )));
+    }
+    Ok(())
+}
+
+/// Decode a program, zero-padding a trailing partial chunk.
 fn decode_code(encoded_code: &[u8]) -> Result<Vec<u8>, RadError> {
     let mut memory = [0u8; 256];
     let mut decoded_code = vec![];
-    for i in 0..(encoded_code.len() / 8) {
-        let index = i * 8;
-        memory[..8].copy_from_slice(&encoded_code[index..(index + 8)]);
-        let x = execute_elf(DECODER, &mut memory, false)?;
+    for chunk in encoded_code.chunks(8) {
+        memory[..8].fill(0);
+        memory[..chunk.len()].copy_from_slice(chunk);
+        let x = execute_elf(
+            DECODER,
+            &mut memory,
+            false,
+            INSTRUCTION_BUDGET,
+            &FileAccess::default(),
+            &channel().0,
+        )?;
         decoded_code.push(x as u8);
     }
     Ok(decoded_code)
 }
 
-/// Execute a parsed program.
-fn execute(
+/// Run a program on a worker thread, giving up on it after a wall-clock timeout.
+///
+/// The worker runs on a copy of memory that is written back only if it finishes in time. A
+/// worker stuck in a blocking syscall cannot be interrupted, so it is left to finish on its own,
+/// clearing the running flag when it does.
+fn execute_with_deadline<F>(
+    memory: &mut [u8],
+    timeout: Duration,
+    running: &Arc<AtomicBool>,
+    f: F,
+) -> Result<u64, RadError>
+where
+    F: FnOnce(&mut [u8]) -> Result<u64, RadError> + Send + 'static,
+{
+    if running.swap(true, Ordering::SeqCst) {
+        return Err(RadError::Vm("previous run still executing".to_string()));
+    }
+    let (tx, rx) = channel();
+    let mut worker_memory = memory.to_vec();
+    let worker_running = running.clone();
+    std::thread::spawn(move || {
+        let result = f(&mut worker_memory);
+        worker_running.store(false, Ordering::SeqCst);
+        let _ = tx.send((result, worker_memory));
+    });
+    match rx.recv_timeout(timeout) {
+        Ok((result, worker_memory)) => {
+            memory.copy_from_slice(&worker_memory);
+            result
+        }
+        Err(RecvTimeoutError::Timeout) => Err(RadError::Vm("timeout".to_string())),
+        Err(RecvTimeoutError::Disconnected) => {
+            running.store(false, Ordering::SeqCst);
+            Err(RadError::Vm("worker panicked".to_string()))
+        }
+    }
+}
+
+/// Execute a parsed program with an instruction budget.
+fn execute<F>(
     mut exe: Box<dyn Executable<UserError, RadMeter>>,
     memory: &mut [u8],
-) -> Result<u64, RadError> {
+    budget: u64,
+    file_read: F,
+    sensor_read: SensorRead,
+    event_log: EventLog,
+) -> Result<u64, RadError>
+where
+    F: SyscallObject<UserError> + 'static,
+{
     let mut registry = SyscallRegistry::default();
-    registry.register_syscall_by_hash(23, FileRead::call)?;
+    registry.register_syscall_by_hash(FILE_READ, F::call)?;
+    registry.register_syscall_by_hash(SENSOR_READ, SensorRead::call)?;
+    registry.register_syscall_by_hash(EVENT_LOG, EventLog::call)?;
     exe.set_syscall_registry(registry);
 
     let region = MemoryRegion::new_from_slice(memory, 0, 32, true);
     let mut vm = EbpfVm::<UserError, RadMeter>::new(exe.as_ref(), memory, &[region])?;
-    vm.bind_syscall_context_object(Box::new(FileRead {}), None)?;
-    let result = vm.execute_program_interpreted(&mut RadMeter::new())?;
+    vm.bind_syscall_context_object(Box::new(file_read), None)?;
+    vm.bind_syscall_context_object(Box::new(sensor_read), None)?;
+    vm.bind_syscall_context_object(Box::new(event_log), None)?;
+    let result = vm.execute_program_interpreted(&mut RadMeter::new(budget))?;
     Ok(result)
 }
 
@@ -174,6 +525,14 @@ mod tests {
 
     const FLAG: &[u8] = include_bytes!("../../flag");
 
+    fn deny() -> FileAccess {
+        FileAccess::default()
+    }
+
+    fn events() -> Sender<String> {
+        channel().0
+    }
+
     #[test]
     fn test_decoder() {
         let _ = env_logger::try_init();
@@ -183,14 +542,91 @@ mod tests {
         memory[1] = 0x01;
         memory[2] = 0x01;
         memory[3] = 0x01;
-        let result = execute_elf(DECODER, &mut memory, false).expect("execute");
+        let result = execute_elf(
+            DECODER,
+            &mut memory,
+            false,
+            INSTRUCTION_BUDGET,
+            &deny(),
+            &events(),
+        )
+        .expect("execute");
         assert_eq!(0x00, result);
 
         memory[5] = 0x01;
-        let result = execute_elf(DECODER, &mut memory, false).expect("execute");
+        let result = execute_elf(
+            DECODER,
+            &mut memory,
+            false,
+            INSTRUCTION_BUDGET,
+            &deny(),
+            &events(),
+        )
+        .expect("execute");
         assert_eq!(0x01, result);
     }
 
+    #[test]
+    fn test_decode_partial_chunk() {
+        let _ = env_logger::try_init();
+        let mut encoded = vec![];
+        for x in &[0xb7u8, 0x00, 0x01] {
+            encoded.extend_from_slice(&[*x; 8]);
+        }
+        encoded.extend_from_slice(&[0x95; 5]);
+        let decoded = decode_code(&encoded).expect("decode");
+        assert_eq!(decoded, vec![0xb7, 0x00, 0x01, 0x95]);
+    }
+
+    #[test]
+    fn test_meter_saturates() {
+        let mut meter = RadMeter::new(4);
+        meter.consume(3);
+        assert_eq!(meter.get_remaining(), 1);
+        meter.consume(16);
+        assert_eq!(meter.get_remaining(), 0);
+        meter.consume(1);
+        assert_eq!(meter.get_remaining(), 0);
+    }
+
+    #[rustfmt::skip]
+    const SPIN: &[u8] = &[
+        // Loop forever
+        0xb7, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x07, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00,
+        0x05, 0x00, 0xfe, 0xff, 0x00, 0x00, 0x00, 0x00,
+        0x95, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    ];
+
+    #[test]
+    fn test_budget_exhausted() {
+        let _ = env_logger::try_init();
+        let mut memory = [0u8; 1024];
+        match execute_bytes(SPIN, &mut memory, false, 8, &deny(), &events()) {
+            Err(RadError::Vm(e)) => assert!(e.contains("maximum number of instructions"), "{}", e),
+            result => panic!("expected budget exhaustion, got {:?}", result),
+        }
+    }
+
+    #[rustfmt::skip]
+    const COUNT: &[u8] = &[
+        // Count to 100
+        0xb7, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x07, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00,
+        0xa5, 0x00, 0xfe, 0xff, 0x64, 0x00, 0x00, 0x00,
+        0x95, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    ];
+
+    #[test]
+    fn test_budget() {
+        let _ = env_logger::try_init();
+        let mut memory = [0u8; 1024];
+        assert!(execute_bytes(COUNT, &mut memory, false, 64, &deny(), &events()).is_err());
+        let result =
+            execute_bytes(COUNT, &mut memory, false, 256, &deny(), &events()).expect("execute");
+        assert_eq!(result, 100);
+    }
+
     #[rustfmt::skip]
     const EXPLOIT: &[u8] = &[
         // Read from /flag
@@ -209,7 +645,15 @@ mod tests {
     fn test_flag_read() {
         let _ = env_logger::try_init();
         let mut memory = [0xccu8; 1024];
-        let result = execute_bytes(EXPLOIT, &mut memory, false).expect("execute");
+        let result = execute_bytes(
+            EXPLOIT,
+            &mut memory,
+            false,
+            INSTRUCTION_BUDGET,
+            &FileAccess::Insecure,
+            &events(),
+        )
+        .expect("execute");
         assert_eq!(memory.len(), result as _);
         assert_eq!(FLAG, &memory[..FLAG.len()]);
     }
@@ -235,8 +679,241 @@ mod tests {
         }
 
         let mut memory = [0u8; 1024];
-        let result = execute_bytes(&code, &mut memory, true).expect("execute");
+        let result = execute_bytes(
+            &code,
+            &mut memory,
+            true,
+            INSTRUCTION_BUDGET,
+            &FileAccess::Insecure,
+            &events(),
+        )
+        .expect("execute");
         assert_eq!(memory.len(), result as _);
         assert_eq!(FLAG, &memory[..FLAG.len()]);
     }
+
+    #[rustfmt::skip]
+    const READ_FLAG_RESULT: &[u8] = &[
+        // Read from /flag
+        0x18, 0x01, 0x00, 0x00, 0x2e, 0x2e, 0x2f, 0x66,
+        0x00, 0x00, 0x00, 0x00, 0x6c, 0x61, 0x67, 0x00,
+        0xb7, 0x02, 0x00, 0x00, 0x07, 0x00, 0x00, 0x00,
+        0xb7, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x85, 0x00, 0x00, 0x00, 0x17, 0x00, 0x00, 0x00,
+        // Exit with the file read syscall result
+        0x95, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    ];
+
+    #[test]
+    fn test_file_read_allowlist() {
+        let _ = env_logger::try_init();
+        let flag = std::fs::canonicalize("../flag").expect("flag path");
+
+        let allowed = FileAccess::Allow(vec![flag.clone()]);
+        let mut memory = [0xccu8; 1024];
+        let result = execute_bytes(
+            READ_FLAG_RESULT,
+            &mut memory,
+            false,
+            INSTRUCTION_BUDGET,
+            &allowed,
+            &events(),
+        )
+        .expect("execute");
+        assert_eq!(result, FLAG.len() as u64);
+        assert_eq!(FLAG, &memory[..FLAG.len()]);
+
+        let elsewhere = FileAccess::Allow(vec![flag.parent().expect("parent").join("data")]);
+        for access in &[deny(), elsewhere] {
+            let mut memory = [0xccu8; 1024];
+            let result = execute_bytes(
+                READ_FLAG_RESULT,
+                &mut memory,
+                false,
+                INSTRUCTION_BUDGET,
+                access,
+                &events(),
+            )
+            .expect("execute");
+            assert_eq!(result, 0);
+            assert!(memory.iter().all(|x| *x == 0xcc));
+        }
+    }
+
+    #[test]
+    fn test_file_access_traversal() {
+        let data = std::fs::canonicalize("../data").expect("data path");
+        let access = FileAccess::Allow(vec![data.clone()]);
+        assert_eq!(
+            access.permit("../data/rad_pub_key"),
+            Some(data.join("rad_pub_key"))
+        );
+        assert_eq!(access.permit("../data/../flag"), None);
+        assert_eq!(access.permit("../data/missing"), None);
+        assert_eq!(
+            FileAccess::Insecure.permit("../flag"),
+            Some(PathBuf::from("../flag"))
+        );
+        assert_eq!(FileAccess::Insecure.permit("rad_keys"), None);
+    }
+
+    #[rustfmt::skip]
+    const FILE_READ: &[u8] = &[
+        // Call the file read syscall
+        0x85, 0x00, 0x00, 0x00, 0x17, 0x00, 0x00, 0x00,
+        // Exit
+        0x95, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    ];
+
+    /// File read stand-in that blocks past the execution timeout.
+    struct Sleep;
+
+    impl SyscallObject<UserError> for Sleep {
+        fn call(
+            &mut self,
+            _arg1: u64,
+            _arg2: u64,
+            _arg3: u64,
+            _arg4: u64,
+            _arg5: u64,
+            _memory_mapping: &MemoryMapping,
+            result: &mut ProgramResult<UserError>,
+        ) {
+            std::thread::sleep(EXECUTION_TIMEOUT * 2);
+            *result = Ok(0);
+        }
+    }
+
+    #[test]
+    fn test_execution_timeout() {
+        let _ = env_logger::try_init();
+        let mut memory = [0xccu8; 1024];
+        let start = std::time::Instant::now();
+        let running = Arc::new(AtomicBool::new(false));
+        let result =
+            execute_with_deadline(&mut memory, EXECUTION_TIMEOUT, &running, move |memory| {
+                let exe_conf = rbpf::vm::Config::default();
+                let exe =
+                    Executable::<UserError, RadMeter>::from_text_bytes(FILE_READ, None, exe_conf)?;
+                let sensor_read = SensorRead { readings: None };
+                let event_log = EventLog {
+                    events: channel().0,
+                    logged: 0,
+                };
+                execute(
+                    exe,
+                    memory,
+                    INSTRUCTION_BUDGET,
+                    Sleep,
+                    sensor_read,
+                    event_log,
+                )
+            });
+        assert!(start.elapsed() < EXECUTION_TIMEOUT * 2);
+        match result {
+            Err(RadError::Vm(e)) => assert_eq!(e, "timeout"),
+            x => panic!("expected timeout, got {:?}", x),
+        }
+        assert!(memory.iter().all(|x| *x == 0xcc));
+
+        // The stuck worker blocks another run until it finishes
+        assert!(running.load(Ordering::SeqCst));
+        match execute_with_deadline(&mut memory, EXECUTION_TIMEOUT, &running, |_| Ok(0)) {
+            Err(RadError::Vm(e)) => assert_eq!(e, "previous run still executing"),
+            x => panic!("expected running error, got {:?}", x),
+        }
+    }
+
+    #[test]
+    fn test_deadline_writes_back() {
+        let mut memory = [0u8; 8];
+        let running = Arc::new(AtomicBool::new(false));
+        let result = execute_with_deadline(&mut memory, EXECUTION_TIMEOUT, &running, |memory| {
+            memory.fill(1);
+            Ok(8)
+        })
+        .expect("execute");
+        assert_eq!(result, 8);
+        assert_eq!(memory, [1u8; 8]);
+        assert!(!running.load(Ordering::SeqCst));
+    }
+
+    const SENSORS: &[u8] = &[
+        // Read sensors into address 0
+        0xb7, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xb7, 0x02, 0x00, 0x00, 0x10, 0x00, 0x00,
+        0x00, 0x85, 0x00, 0x00, 0x00, 0x18, 0x00, 0x00, 0x00, // Return fuel + radiation
+        0xb7, 0x06, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x79, 0x60, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x79, 0x61, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x0f, 0x10, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x95, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    ];
+
+    #[test]
+    fn test_is_pure() {
+        assert!(is_pure(COUNT));
+        assert!(is_pure(SPIN));
+        assert!(!is_pure(SENSORS));
+        assert!(!is_pure(EXPLOIT));
+        assert!(is_pure(&[]));
+    }
+
+    #[test]
+    fn test_sensor_read() {
+        let _ = env_logger::try_init();
+        record_sensors(12.5, 0.25);
+        let mut memory = [0u8; 1024];
+        let result = execute_bytes(
+            SENSORS,
+            &mut memory,
+            false,
+            INSTRUCTION_BUDGET,
+            &deny(),
+            &events(),
+        )
+        .expect("execute");
+        assert_eq!(result, 12_500 + 250);
+        assert_eq!(&memory[..8], &12_500u64.to_le_bytes());
+        assert_eq!(&memory[8..16], &250u64.to_le_bytes());
+    }
+
+    #[test]
+    fn test_validate_program() {
+        let exe_conf = rbpf::vm::Config::default();
+        let exe =
+            Executable::<UserError, RadMeter>::from_elf(DECODER, None, exe_conf).expect("parse");
+        validate_program(exe.as_ref()).expect("valid");
+
+        // Oversized programs are rejected before parsing, on either path
+        let mut oversized = DECODER.to_vec();
+        oversized.resize(MAX_MODULE_SIZE + 1, 0);
+        let mut memory = vec![0u8; 1024];
+        for decode in [false, true] {
+            match execute_bytes(
+                &oversized,
+                &mut memory,
+                decode,
+                INSTRUCTION_BUDGET,
+                &deny(),
+                &events(),
+            ) {
+                Err(RadError::Vm(e)) => assert!(e.contains("exceeds")),
+                x => panic!("expected size error, got {:?}", x),
+            }
+        }
+
+        let code = [
+            0x85, 0x00, 0x00, 0x00, 0x99, 0x00, 0x00, 0x00, // call 0x99
+            0x95, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // exit
+        ];
+        match execute_bytes(
+            &code,
+            &mut memory,
+            false,
+            INSTRUCTION_BUDGET,
+            &deny(),
+            &events(),
+        ) {
+            Err(RadError::Vm(e)) => assert!(e.contains("unregistered syscall 0x99")),
+            x => panic!("expected syscall error, got {:?}", x),
+        }
+    }
 }
@@ -1,36 +1,90 @@
 //! Software watchdog.
 
 use crate::{reset, RadError};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
 use std::thread::sleep;
 use std::time::{Duration, Instant};
 
-const WATCHDOG_TIMEOUT: u64 = 10;
+pub const WATCHDOG_TIMEOUT: Duration = Duration::from_secs(10);
 
-/// Watchdog thread.
-pub fn watchdog(timers: Vec<Arc<Mutex<Instant>>>) {
-    if let Err(e) = do_watchdog(&timers) {
+/// Named watchdog timer with its own timeout and last kick time.
+pub type Timer = (String, Duration, Arc<Mutex<Instant>>);
+
+/// Watchdog thread, running until `stop` is set.
+pub fn watchdog(timers: Vec<Timer>, stop: Arc<AtomicBool>) {
+    if let Err(e) = do_watchdog(&timers, &stop) {
         error!("watchdog: {:?}", e);
         reset();
     }
 }
 
 /// Watchdog thread.
-fn do_watchdog(timers: &[Arc<Mutex<Instant>>]) -> Result<(), RadError> {
+fn do_watchdog(timers: &[Timer], stop: &AtomicBool) -> Result<(), RadError> {
     debug!("executing watchdog thread");
 
-    loop {
+    while !stop.load(Ordering::SeqCst) {
         sleep(Duration::from_secs(1));
-        for timer in timers.iter() {
-            if timer
-                .lock()
-                .map_err(|_| RadError::Mutex)?
-                .elapsed()
-                .as_secs()
-                > WATCHDOG_TIMEOUT
-            {
-                return Err(RadError::Watchdog);
-            }
+        check_timers(timers)?;
+    }
+    Ok(())
+}
+
+/// Check every timer against its own timeout.
+fn check_timers(timers: &[Timer]) -> Result<(), RadError> {
+    for (name, timeout, timer) in timers.iter() {
+        let elapsed = timer.lock().map_err(|_| RadError::Mutex)?.elapsed();
+        if elapsed > *timeout {
+            error!("watchdog {} expired after {:?}", name, elapsed);
+            return Err(RadError::Watchdog(name.clone()));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_named_timeouts() {
+        let timer = |name: &str, timeout| {
+            (
+                name.to_string(),
+                timeout,
+                Arc::new(Mutex::new(Instant::now())),
+            )
+        };
+        let long = timer("long", Duration::from_secs(3600));
+        let short = timer("short", Duration::from_millis(50));
+        let timers = vec![long.clone(), short.clone()];
+        assert!(check_timers(&timers).is_ok());
+
+        sleep(Duration::from_millis(100));
+        match check_timers(&timers) {
+            Err(RadError::Watchdog(name)) => assert_eq!(name, "short"),
+            result => panic!("expected watchdog timeout, got {:?}", result),
         }
+        assert!(check_timers(&[long]).is_ok());
+
+        // Kicking the short timer keeps it alive
+        *short.2.lock().expect("lock") = Instant::now();
+        assert!(check_timers(&timers).is_ok());
+    }
+
+    #[test]
+    fn test_watchdog_stop() {
+        let timer = (
+            "main".to_string(),
+            Duration::from_secs(3600),
+            Arc::new(Mutex::new(Instant::now())),
+        );
+        let stop = Arc::new(AtomicBool::new(false));
+        let watchdog = std::thread::spawn({
+            let stop = stop.clone();
+            move || watchdog(vec![timer], stop)
+        });
+        stop.store(true, Ordering::SeqCst);
+        watchdog.join().expect("watchdog");
     }
 }
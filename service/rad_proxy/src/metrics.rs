@@ -0,0 +1,160 @@
+//! Operational counters served in the Prometheus text format.
+
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Request, Response, Server, StatusCode};
+use std::convert::Infallible;
+use std::fmt::Write;
+use std::net::TcpListener;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio_util::sync::CancellationToken;
+
+/// Monotonic counter.
+#[derive(Default)]
+pub struct Counter(AtomicU64);
+
+impl Counter {
+    /// Add one to the counter.
+    pub fn inc(&self) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Read the counter.
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Proxy and node counters.
+#[derive(Default)]
+pub struct Metrics {
+    /// Client connections accepted by the proxy
+    pub client_connections: Counter,
+    /// Client tokens the proxy accepted
+    pub client_auth_successes: Counter,
+    /// Client tokens the proxy rejected as invalid or replayed
+    pub client_auth_failures: Counter,
+    /// Clients rejected for exceeding their team's connection limits
+    pub team_limit_rejections: Counter,
+    /// Clients proxied to each node, by node index
+    pub node_selections: Vec<Counter>,
+    /// Connections accepted by a node
+    pub node_connections: Counter,
+    /// Teams the authentication endpoint or cache accepted
+    pub team_auth_successes: Counter,
+    /// Teams the authentication endpoint or cache rejected
+    pub team_auth_failures: Counter,
+    /// Team authentications answered from the cache
+    pub auth_cache_hits: Counter,
+    /// Team container restarts attempted
+    pub container_restarts: Counter,
+    /// Team container restarts that did not yield a reachable service
+    pub container_restart_failures: Counter,
+}
+
+impl Metrics {
+    /// Create counters for a proxy in front of `nodes` nodes.
+    pub fn new(nodes: usize) -> Self {
+        Self {
+            node_selections: (0..nodes).map(|_| Counter::default()).collect(),
+            ..Self::default()
+        }
+    }
+
+    /// Render the counters in the Prometheus text format.
+    pub fn render(&self) -> String {
+        let mut output = String::new();
+        let mut family = |name: &str, help: &str, samples: &[(&str, u64)]| {
+            let _ = writeln!(output, "# HELP {} {}", name, help);
+            let _ = writeln!(output, "# TYPE {} counter", name);
+            for (labels, value) in samples {
+                let _ = writeln!(output, "{}{} {}", name, labels, value);
+            }
+        };
+        family(
+            "rad_proxy_client_connections_total",
+            "Client connections accepted by the proxy.",
+            &[("", self.client_connections.get())],
+        );
+        family(
+            "rad_proxy_client_auth_total",
+            "Client tokens checked by the proxy.",
+            &[
+                ("{result=\"success\"}", self.client_auth_successes.get()),
+                ("{result=\"failure\"}", self.client_auth_failures.get()),
+            ],
+        );
+        family(
+            "rad_proxy_team_limit_rejections_total",
+            "Clients rejected for exceeding their team's connection limits.",
+            &[("", self.team_limit_rejections.get())],
+        );
+        let selections: Vec<_> = self
+            .node_selections
+            .iter()
+            .enumerate()
+            .map(|(i, x)| (format!("{{node=\"{}\"}}", i), x.get()))
+            .collect();
+        let selections: Vec<_> = selections.iter().map(|(l, x)| (l.as_str(), *x)).collect();
+        family(
+            "rad_proxy_node_selections_total",
+            "Clients proxied to each node.",
+            &selections,
+        );
+        family(
+            "rad_node_connections_total",
+            "Connections accepted by the node.",
+            &[("", self.node_connections.get())],
+        );
+        family(
+            "rad_node_team_auth_total",
+            "Team authentication results.",
+            &[
+                ("{result=\"success\"}", self.team_auth_successes.get()),
+                ("{result=\"failure\"}", self.team_auth_failures.get()),
+            ],
+        );
+        family(
+            "rad_node_auth_cache_hits_total",
+            "Team authentications answered from the cache.",
+            &[("", self.auth_cache_hits.get())],
+        );
+        family(
+            "rad_node_container_restarts_total",
+            "Team container restarts attempted.",
+            &[("", self.container_restarts.get())],
+        );
+        family(
+            "rad_node_container_restart_failures_total",
+            "Team container restarts that did not yield a reachable service.",
+            &[("", self.container_restart_failures.get())],
+        );
+        output
+    }
+}
+
+/// Serve `/metrics` until shutdown.
+pub async fn serve(
+    listener: TcpListener,
+    metrics: Arc<Metrics>,
+    shutdown: CancellationToken,
+) -> hyper::Result<()> {
+    let make_service = make_service_fn(move |_| {
+        let metrics = metrics.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request: Request<Body>| {
+                let mut response = Response::new(Body::empty());
+                if request.uri().path() == "/metrics" {
+                    *response.body_mut() = Body::from(metrics.render());
+                } else {
+                    *response.status_mut() = StatusCode::NOT_FOUND;
+                }
+                async move { Ok::<_, Infallible>(response) }
+            }))
+        }
+    });
+    Server::from_tcp(listener)?
+        .serve(make_service)
+        .with_graceful_shutdown(async move { shutdown.cancelled().await })
+        .await
+}